@@ -1,9 +1,31 @@
 .section .text.init
 .global _start
 
+# Per-hart stack: sp = __stacks_end - tp * __hart_stack_size
+.macro setup_hart_stack
+    ld t0, boot_hart_stack_size
+    mul t0, t0, tp
+    la sp, __stacks_end
+    sub sp, sp, t0
+.endm
+
 _start:
-    # Set up stack pointer (highest priority)
-    li sp, 0x80100000
+.ifdef SMODE
+    # S-mode: SBI firmware passes the hart ID in a0 (mhartid is M-mode only)
+    mv tp, a0
+.else
+    # Hart ID is kept in tp for the rest of the boot
+    csrr tp, mhartid
+.endif
+    
+    # Devicetree pointer from firmware/QEMU (a1); s1 survives early_init
+    mv s1, a1
+    
+    # Harts beyond __max_harts have no stack: park them forever
+    ld t0, boot_max_harts
+    bgeu tp, t0, park_forever
+    
+    setup_hart_stack
     
     # Clear frame pointer
     li fp, 0
@@ -19,14 +41,82 @@ _start:
     li t1, 0
     li t2, 0
     li a0, 0
-    li a1, 0
     li a2, 0
     li a3, 0
     
-    # Call Rust main function
+.ifndef SMODE
+    # Secondary harts must not run before .bss/.data are ready
+    # (in S-mode only the boot hart enters here; the others are started
+    # later through SBI HSM at _secondary_start)
+    bnez tp, secondary_park
+.endif
+    
+    # Early init: clear .bss and relocate .data (LMA -> VMA)
+    # Must run before any Rust code touches a static
+    call early_init
+    
+    # Call Rust main function (a0 = hart ID, a1 = devicetree)
+    mv a0, tp
+    mv a1, s1
     call rust_main
     
     # If rust_main returns (should never happen), infinite loop
 1:
     nop
-    j 1b
\ No newline at end of file
+    j 1b
+
+.ifdef SMODE
+.global _secondary_start
+_secondary_start:
+    # Entered through SBI HSM hart_start (a0 = hart ID, a1 = opaque)
+    mv tp, a0
+    ld t0, boot_max_harts
+    bgeu tp, t0, park_forever
+    
+    setup_hart_stack
+    li fp, 0
+    .option push
+    .option norelax
+    la gp, __global_pointer$
+    .option pop
+    
+    # Enter the per-hart Rust entry point (a0 = hart ID)
+    mv a0, tp
+    call rust_secondary_main
+.else
+secondary_park:
+    # Wait in WFI until the primary hart sets our MSIP bit.
+    # Only MSIE is enabled and mstatus.MIE stays clear, so the
+    # interrupt wakes WFI without taking a trap.
+    li t0, 8                # MIE.MSIE
+    csrw mie, t0
+2:
+    wfi
+    csrr t0, mip
+    andi t0, t0, 8          # MIP.MSIP
+    beqz t0, 2b
+    
+    # Make the primary hart's initialization visible before entering Rust
+    fence rw, rw
+    
+    # Enter the per-hart Rust entry point (a0 = hart ID)
+    mv a0, tp
+    call rust_secondary_main
+.endif
+
+park_forever:
+.ifdef SMODE
+    csrw sie, zero
+.else
+    csrw mie, zero
+.endif
+3:
+    wfi
+    j 3b
+
+.section .rodata
+.balign 8
+boot_max_harts:
+    .dword __max_harts
+boot_hart_stack_size:
+    .dword __hart_stack_size
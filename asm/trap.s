@@ -1,71 +1,235 @@
 .section .text
 .global trap_handler
-.align 4
-
-trap_handler:
-    # より安全なレジスタ保存
-    # スタックポインタを確認してから操作
-    
-    # まず、スタックが有効かチェック（基本的な範囲確認）
-    li t0, 0x80000000       # RAM start
-    bgtu t0, sp, bad_stack  # sp < RAM start なら危険
-    li t0, 0x88000000       # RAM end (128MB)
-    bltu t0, sp, bad_stack  # sp > RAM end なら危険
-    
-    # スタック操作（より慎重に）
-    addi sp, sp, -256       # 十分なスペースを確保
-    
-    # レジスタ保存（より多くのレジスタを安全に保存）
-    sd ra,   0(sp)
-    sd t0,   8(sp)
-    sd t1,  16(sp)
-    sd t2,  24(sp)
-    sd a0,  32(sp)
-    sd a1,  40(sp)
-    sd a2,  48(sp)
-    sd a3,  56(sp)
-    sd a4,  64(sp)
-    sd a5,  72(sp)
-    sd a6,  80(sp)
-    sd a7,  88(sp)
-    sd s0,  96(sp)
-    sd s1, 104(sp)
-    
-    # Rustトラップハンドラを呼び出し
-    call rust_trap_handler
-    
-    # レジスタ復帰
-    ld ra,   0(sp)
-    ld t0,   8(sp)
-    ld t1,  16(sp)
-    ld t2,  24(sp)
-    ld a0,  32(sp)
-    ld a1,  40(sp)
-    ld a2,  48(sp)
-    ld a3,  56(sp)
-    ld a4,  64(sp)
-    ld a5,  72(sp)
-    ld a6,  80(sp)
-    ld a7,  88(sp)
-    ld s0,  96(sp)
-    ld s1, 104(sp)
-    
-    # スタックポインタ復帰
-    addi sp, sp, 256
-    
-    # トラップから復帰
+.global trap_vector_table
+
+# トラップフレームのサイズ（arch/riscv64/trap.rsのTrapFrameと一致させること）
+# 0..256: x0..x31（x0は未使用）、256: mepc、264: mstatus、272: mcause、280: mtval
+.equ TRAP_FRAME_SIZE, 288
+.equ FRAME_EPC, 256
+.equ FRAME_STATUS, 264
+.equ FRAME_CAUSE, 272
+.equ FRAME_TVAL, 280
+
+# mscratchが指すハートごとのトラップ状態（trap.rsのHartTrapStateと一致させること）
+.equ STATE_LIMIT, 0             # タスクスタック下限（ガード領域の直上）
+.equ STATE_TOP, 8               # タスクスタック上端
+.equ STATE_EMERGENCY, 16        # 緊急スタック上端
+.equ STATE_TRAP_TOP, 24         # トラップスタック上端
+.equ STATE_TRAP_LIMIT, 32       # トラップスタック下限
+.equ STATE_DEPTH, 40            # トラップのネスト深さ
+.equ STATE_SAVE_T1, 48          # t1の退避場所
+.equ STATE_SAVE_T2, 56          # t2の退避場所
+
+.macro csr_swap_scratch reg
+.ifdef SMODE
+    csrrw \reg, sscratch, \reg
+.else
+    csrrw \reg, mscratch, \reg
+.endif
+.endm
+
+.macro csr_clear_scratch
+.ifdef SMODE
+    csrw sscratch, zero
+.else
+    csrw mscratch, zero
+.endif
+.endm
+
+.macro csr_read_scratch reg
+.ifdef SMODE
+    csrr \reg, sscratch
+.else
+    csrr \reg, mscratch
+.endif
+.endm
+
+# 特権モードに応じたトラップCSRの読み書き（name = epc/status/cause/tval）
+.macro csr_read_trap reg, name
+.ifdef SMODE
+    csrr \reg, s\name
+.else
+    csrr \reg, m\name
+.endif
+.endm
+
+.macro csr_write_trap name, reg
+.ifdef SMODE
+    csrw s\name, \reg
+.else
+    csrw m\name, \reg
+.endif
+.endm
+
+# トラップ入口: トラップスタックに切り替え、TrapFrameを保存して\handler(&mut TrapFrame)を呼ぶ
+.macro trap_entry name, handler
+.align 2
+\name:
+    # t0 <-> mscratch（ハートのトラップ状態へのポインタ）を交換し、t1/t2は状態構造体に退避
+    csr_swap_scratch t0
+    beqz t0, \name\()_unchecked  # 未設定（init_trap_stack前）: 現在のスタックを使う
+
+    sd t1, STATE_SAVE_T1(t0)
+    sd t2, STATE_SAVE_T2(t0)
+    ld t1, STATE_DEPTH(t0)
+    bnez t1, \name\()_nested
+
+    # 最初のトラップ: 割り込まれたspがタスクスタック範囲内か確認してから切り替え
+    ld t2, STATE_LIMIT(t0)
+    bltu sp, t2, stack_overflow # ガード領域に入っている
+    ld t2, STATE_TOP(t0)
+    bgtu sp, t2, stack_overflow # スタック上端より上（破損）
+    mv t2, sp
+    ld sp, STATE_TRAP_TOP(t0)
+    j \name\()_switched
+
+\name\()_nested:
+    # ネストしたトラップ: すでにトラップスタック上にいるので、フレーム分の余裕を確認
+    ld t2, STATE_TRAP_LIMIT(t0)
+    addi t2, t2, TRAP_FRAME_SIZE
+    bltu sp, t2, trap_stack_overflow
+    mv t2, sp
+
+\name\()_switched:
+    addi t1, t1, 1
+    sd t1, STATE_DEPTH(t0)
+    addi sp, sp, -TRAP_FRAME_SIZE
+    sd t2, 16(sp)               # トラップ前のsp
+    ld t1, STATE_SAVE_T1(t0)
+    ld t2, STATE_SAVE_T2(t0)
+    csr_swap_scratch t0         # t0を復元し、mscratchを状態ポインタに戻す
+    j \name\()_save
+
+\name\()_unchecked:
+    csr_swap_scratch t0         # t0を復元（mscratchは0のまま）
+    addi sp, sp, -TRAP_FRAME_SIZE
+    sd t0, (5 * 8)(sp)
+    addi t0, sp, TRAP_FRAME_SIZE
+    sd t0, 16(sp)               # トラップ前のsp
+    ld t0, (5 * 8)(sp)
+
+\name\()_save:
+    # 汎用レジスタをすべて保存（x2=spは保存済み）
+    sd x1, 8(sp)
+    .irp n, 3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+    sd x\n, (\n * 8)(sp)
+    .endr
+    sd zero, 0(sp)
+
+    # トラップCSRを保存
+    csr_read_trap t0, epc
+    sd t0, FRAME_EPC(sp)
+    csr_read_trap t0, status
+    sd t0, FRAME_STATUS(sp)
+    csr_read_trap t0, cause
+    sd t0, FRAME_CAUSE(sp)
+    csr_read_trap t0, tval
+    sd t0, FRAME_TVAL(sp)
+
+    # Rustハンドラを呼び出し（a0 = &mut TrapFrame）
+    mv a0, sp
+    call \handler
+
+    # ハンドラが書き換えた可能性のあるmepc/mstatusを復帰
+    ld t0, FRAME_EPC(sp)
+    csr_write_trap epc, t0
+    ld t0, FRAME_STATUS(sp)
+    csr_write_trap status, t0
+
+    # ネスト深さを戻す（t0/t1はこの後フレームから復帰する）
+    csr_read_scratch t0
+    beqz t0, 1f
+    ld t1, STATE_DEPTH(t0)
+    addi t1, t1, -1
+    sd t1, STATE_DEPTH(t0)
+1:
+
+    # 汎用レジスタ復帰（spは最後に復帰）
+    ld x1, 8(sp)
+    .irp n, 3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+    ld x\n, (\n * 8)(sp)
+    .endr
+    ld sp, 16(sp)
+
+    # トラップから復帰（S-modeビルドではsret）
+.ifdef SMODE
+    sret
+.else
     mret
+.endif
+
+.endm
+
+# 割り込み要因ごとのベクタ番号（S-modeではスーパーバイザ割り込み）
+.ifdef SMODE
+.equ VEC_SOFTWARE, 1
+.equ VEC_TIMER, 5
+.equ VEC_EXTERNAL, 9
+.else
+.equ VEC_SOFTWARE, 3
+.equ VEC_TIMER, 7
+.equ VEC_EXTERNAL, 11
+.endif
+
+.macro vector_slot code
+.if \code == VEC_SOFTWARE
+    j trap_vector_software
+.elseif \code == VEC_TIMER
+    j trap_vector_timer
+.elseif \code == VEC_EXTERNAL
+    j trap_vector_external
+.else
+    j trap_handler              # 例外（0番）とその他の割り込みは汎用入口へ
+.endif
+.endm
+
+# 汎用入口（ダイレクトモード、例外、その他の割り込み）
+trap_entry trap_handler, rust_trap_handler
+
+# ベクタモードの割り込み別入口（mcauseをデコードせずに各ハンドラへ）
+trap_entry trap_vector_software, rust_software_interrupt
+trap_entry trap_vector_timer, rust_timer_interrupt
+trap_entry trap_vector_external, rust_external_interrupt
+
+# ベクタテーブル（mtvec MODE=1）: 割り込みコードiでbase + 4*iへ飛ぶ
+# 各エントリは4バイト固定にする必要があるので圧縮命令を禁止する
+.align 8
+trap_vector_table:
+.option push
+.option norvc
+.irp code, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15
+    vector_slot \code
+.endr
+.option pop
+
+stack_overflow:
+    # タスクスタックが範囲外: 緊急スタックに切り替えてstack_overflow_panic(sp, limit)へ
+    # （t0 = 状態ポインタ）
+    mv a0, sp
+    ld a1, STATE_TOP(t0)
+    bgtu sp, a1, overflow_report    # 上端超えの場合は上端を報告
+    ld a1, STATE_LIMIT(t0)
+    j overflow_report
+
+trap_stack_overflow:
+    # ネストが深すぎてトラップスタックが尽きた
+    mv a0, sp
+    ld a1, STATE_TRAP_LIMIT(t0)
+
+overflow_report:
+    ld sp, STATE_EMERGENCY(t0)
+
+    # mscratchには割り込まれた側のt0が入ったままなので、深さを数えてから0にする
+    # （報告中のトラップ、例えばダンプ中のprobe_readは_uncheckedで緊急スタックを使う）
+    ld t1, STATE_DEPTH(t0)
+    addi t1, t1, 1
+    sd t1, STATE_DEPTH(t0)
+    csr_clear_scratch
+
+    li fp, 0
+    call trap_stack_overflow_handler
 
-bad_stack:
-    # スタックが無効な場合の緊急処理
-    # UARTに直接エラー出力
-    li t0, 0x10000000       # UART address
-    li t1, 83               # 'S' for Stack error
-    sb t1, 0(t0)
-    li t1, 10               # '\n'
-    sb t1, 0(t0)
-    
-    # 無限ループ（復帰不可能）
+    # 復帰しない
 1:
-    nop
-    j 1b
\ No newline at end of file
+    wfi
+    j 1b
@@ -1,13 +1,42 @@
+use std::{env, fs, path::PathBuf};
+
 fn main() {
-    cc::Build::new()
+    let smode = env::var_os("CARGO_FEATURE_SMODE").is_some();
+    let xip = env::var_os("CARGO_FEATURE_XIP").is_some();
+    if smode && xip {
+        panic!("features `smode` and `xip` cannot be enabled together");
+    }
+
+    let mut build = cc::Build::new();
+    build
         .file("asm/boot.s")
         .file("asm/trap.s")
         .flag("-march=rv64gc")
         .flag("-mabi=lp64d")
         .flag("-nostdlib")
-        .flag("-nostartfiles")
-        .compile("boot");
+        .flag("-nostartfiles");
+    // S-modeビルドではアセンブリ側も.ifdef SMODEで切り替える
+    if smode {
+        build.flag("-Wa,--defsym,SMODE=1");
+    }
+    build.compile("boot");
+
+    // link.ldがINCLUDEするメモリ領域定義を選択
+    let regions = if smode {
+        "memory-smode.x"
+    } else if xip {
+        "memory-xip.x"
+    } else {
+        "memory-ram.x"
+    };
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::copy(regions, out_dir.join("regions.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out_dir.display());
 
-    println!("cargo:rerun-if-changed=boot.s");
-    println!("cargo:rerun-if-changed=trap.s");
+    println!("cargo:rerun-if-changed=asm/boot.s");
+    println!("cargo:rerun-if-changed=asm/trap.s");
+    println!("cargo:rerun-if-changed=link.ld");
+    println!("cargo:rerun-if-changed=memory-ram.x");
+    println!("cargo:rerun-if-changed=memory-smode.x");
+    println!("cargo:rerun-if-changed=memory-xip.x");
 }
@@ -0,0 +1,12 @@
+/* RAM実行イメージ (QEMU virt -bios none -kernel)
+   全セクションをRAMに配置し、.dataのLMAとVMAは一致する */
+MEMORY
+{
+  RAM : ORIGIN = 0x80000000, LENGTH = 128M
+}
+
+REGION_ALIAS("REGION_TEXT", RAM);
+REGION_ALIAS("REGION_RODATA", RAM);
+REGION_ALIAS("REGION_DATA", RAM);
+REGION_ALIAS("REGION_BSS", RAM);
+REGION_ALIAS("REGION_LOAD", RAM);
@@ -0,0 +1,12 @@
+/* S-modeイメージ (QEMU virt -bios default / OpenSBI)
+   OpenSBIが先頭2MBを使用し、カーネルは0x80200000から実行される */
+MEMORY
+{
+  RAM : ORIGIN = 0x80200000, LENGTH = 126M
+}
+
+REGION_ALIAS("REGION_TEXT", RAM);
+REGION_ALIAS("REGION_RODATA", RAM);
+REGION_ALIAS("REGION_DATA", RAM);
+REGION_ALIAS("REGION_BSS", RAM);
+REGION_ALIAS("REGION_LOAD", RAM);
@@ -0,0 +1,14 @@
+/* ROM/XIPイメージ (feature = "xip")
+   .text/.rodataはフラッシュ上で直接実行し、.dataはフラッシュに格納して
+   起動時にearly_init()がRAMへコピーする */
+MEMORY
+{
+  ROM : ORIGIN = 0x20000000, LENGTH = 32M
+  RAM : ORIGIN = 0x80000000, LENGTH = 128M
+}
+
+REGION_ALIAS("REGION_TEXT", ROM);
+REGION_ALIAS("REGION_RODATA", ROM);
+REGION_ALIAS("REGION_DATA", RAM);
+REGION_ALIAS("REGION_BSS", RAM);
+REGION_ALIAS("REGION_LOAD", ROM);
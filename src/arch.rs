@@ -209,7 +209,7 @@ pub struct ArchInfo {
     /// Memory page size in bytes (if paging is supported)
     pub page_size: usize,
 
-    /// Whether this architecture has a Memory Management Unit
+    /// Whether the kernel runs with its own accesses translated by the MMU
     pub has_mmu: bool,
 }
 
@@ -221,7 +221,9 @@ pub const ARCH_INFO: ArchInfo = ArchInfo {
     name: "RISC-V 64-bit",
     word_size: 8,
     page_size: 4096,
-    has_mmu: false, // Not currently used in our bare-metal implementation
+    // Sv39 only translates the kernel's own accesses in S-mode; M-mode
+    // builds program satp but run untranslated
+    has_mmu: cfg!(feature = "smode"),
 };
 
 /// Print architecture information to console
@@ -240,7 +242,7 @@ pub fn print_arch_info() {
     crate::print_number!(ARCH_INFO.page_size as u64);
     crate::println!(" bytes");
     if ARCH_INFO.has_mmu {
-        crate::println!("  MMU: Available");
+        crate::println!("  MMU: Available (Sv39)");
     } else {
         crate::println!("  MMU: Not used");
     }
@@ -6,10 +6,21 @@
 //! and status registers, interrupt controllers, and timer facilities.
 
 pub mod csr;
+pub mod ipi;
+pub mod mmu;
+pub mod platform;
+pub mod plic;
+#[cfg(not(feature = "smode"))]
+pub mod pmp;
+#[cfg(feature = "smode")]
+pub mod sbi;
 pub mod timer;
+pub mod trap;
 
 // Re-export commonly used types for convenience
+pub use plic::{Plic, PLIC};
 pub use timer::{ClintTimer, TimerDuration, CLINT_TIMER};
+pub use trap::{Exception, RiscvTrapHandler, TrapFrame, TrapHandlerFn, TrapMode, TRAP_HANDLER};
 
 /// Machine word size for RISC-V 64-bit architecture
 pub const WORD_SIZE: usize = 8;
@@ -17,10 +28,26 @@ pub const WORD_SIZE: usize = 8;
 /// Standard page size for RISC-V architecture
 pub const PAGE_SIZE: usize = 4096;
 
+/// Maximum number of harts supported by the kernel
+///
+/// Each hart gets its own boot stack from the linker script, so this
+/// value must match `__max_harts` in `link.ld`.
+pub const MAX_HARTS: usize = 4;
+
+/// Privilege level the kernel runs at
+#[cfg(not(feature = "smode"))]
+pub const PRIVILEGE_MODE: &str = "M-mode";
+
+/// Privilege level the kernel runs at
+#[cfg(feature = "smode")]
+pub const PRIVILEGE_MODE: &str = "S-mode (SBI)";
+
 /// Memory map definitions for QEMU virt machine
 ///
 /// These constants define the physical memory layout used by the QEMU
 /// RISC-V virt machine, including RAM regions and memory-mapped peripherals.
+/// They are the defaults used when no devicetree is available; at runtime
+/// the kernel queries [`platform::boot_info`] instead.
 pub mod memory_map {
     /// Start address of main RAM
     pub const RAM_START: usize = 0x80000000;
@@ -34,20 +61,44 @@ pub mod memory_map {
     /// UART0 base address for console I/O
     pub const UART0_BASE: usize = 0x10000000;
 
+    /// UART0 register window size
+    pub const UART0_SIZE: usize = 0x100;
+
+    /// UART0 interrupt source number on the PLIC
+    pub const UART0_IRQ: u32 = 10;
+
     /// Core-Local Interruptor (CLINT) base address
     pub const CLINT_BASE: usize = 0x2000000;
 
     /// CLINT address space size
     pub const CLINT_SIZE: usize = 0x10000;
 
+    /// Offset of the MSIP array within the CLINT
+    pub const MSIP_OFFSET: usize = 0x0;
+
+    /// Offset of the MTIMECMP array within the CLINT
+    pub const MTIMECMP_OFFSET: usize = 0x4000;
+
+    /// Offset of the MTIME register within the CLINT
+    pub const MTIME_OFFSET: usize = 0xBFF8;
+
     /// Machine Software Interrupt Pending register base
-    pub const MSIP_BASE: usize = CLINT_BASE + 0x0;
+    pub const MSIP_BASE: usize = CLINT_BASE + MSIP_OFFSET;
 
     /// Machine Timer Compare register base
-    pub const MTIMECMP_BASE: usize = CLINT_BASE + 0x4000;
+    pub const MTIMECMP_BASE: usize = CLINT_BASE + MTIMECMP_OFFSET;
 
     /// Machine Time register address
-    pub const MTIME_ADDR: usize = CLINT_BASE + 0xBFF8;
+    pub const MTIME_ADDR: usize = CLINT_BASE + MTIME_OFFSET;
+
+    /// Platform-Level Interrupt Controller (PLIC) base address
+    pub const PLIC_BASE: usize = 0x0c000000;
+
+    /// PLIC address space size
+    pub const PLIC_SIZE: usize = 0x600000;
+
+    /// Number of PLIC interrupt sources on QEMU virt
+    pub const PLIC_NDEV: u32 = 95;
 }
 
 /// RISC-V specific error types
@@ -84,7 +135,8 @@ impl core::fmt::Display for RiscvError {
 ///
 /// Captures the state of important RISC-V control and status registers
 /// at a specific point in time, typically during trap handling or
-/// system state inspection.
+/// system state inspection. In S-mode builds the fields hold the
+/// supervisor counterparts (`sstatus`, `scause`, ...).
 #[derive(Debug, Clone, Copy)]
 pub struct RiscvContext {
     /// Machine Status register
@@ -146,7 +198,7 @@ impl RiscvContext {
     /// # Returns
     /// `true` if global interrupts are enabled in mstatus
     pub fn global_interrupts_enabled(&self) -> bool {
-        (self.mstatus & (1 << 3)) != 0
+        (self.mstatus & csr::bits::STATUS_IE) != 0
     }
 }
 
@@ -158,7 +210,7 @@ impl RiscvContext {
 /// # Returns
 /// `true` if the address is within the valid RAM range
 pub fn is_valid_ram_address(addr: usize) -> bool {
-    addr >= memory_map::RAM_START && addr < memory_map::RAM_END
+    platform::is_ram(addr)
 }
 
 /// Check if an address is properly aligned
@@ -178,11 +230,7 @@ pub fn is_aligned(addr: usize, alignment: usize) -> bool {
 /// # Returns
 /// The unique identifier for this hardware thread
 pub fn get_hart_id() -> u64 {
-    let mut val: u64;
-    unsafe {
-        core::arch::asm!("csrr {}, mhartid", out(reg) val);
-    }
-    val
+    csr::read_mhartid()
 }
 
 /// Get a string describing the ISA implementation
@@ -207,6 +255,8 @@ pub fn print_hardware_info() {
     crate::println_number!("Hart ID: ", get_hart_id());
     crate::print!("ISA: ");
     crate::println!(get_isa_string());
+    crate::print!("Privilege: ");
+    crate::println!(PRIVILEGE_MODE);
 
     let context = RiscvContext::capture();
     crate::println_hex!("MSTATUS: ", context.mstatus);
@@ -4,10 +4,35 @@
 //! This module provides both low-level CSR access functions and a higher-level
 //! hardware abstraction layer interface for RISC-V control and status registers.
 //! It includes both legacy compatibility functions and new HAL-compliant interfaces.
+//!
+//! The kernel runs in M-mode by default. With the `smode` feature it runs in
+//! S-mode under SBI firmware, and every trap-related accessor transparently
+//! targets the supervisor counterpart instead (`mstatus` -> `sstatus`,
+//! `mtvec` -> `stvec`, `mie` -> `sie`, ...). The `m*` names are kept so
+//! callers stay privilege-agnostic; use the privilege-neutral constants in
+//! [`bits`] (`STATUS_IE`, `IE_TIMER`, ...) when testing individual bits.
 
 use super::RiscvError;
 use crate::arch::{ControlStatusRegister, Register};
 
+/// Expand to the name of a trap CSR for the kernel's privilege level
+///
+/// `xcsr!("status")` is `"mstatus"` in M-mode builds and `"sstatus"` in
+/// S-mode builds.
+#[cfg(not(feature = "smode"))]
+macro_rules! xcsr {
+    ($name:literal) => {
+        concat!("m", $name)
+    };
+}
+
+#[cfg(feature = "smode")]
+macro_rules! xcsr {
+    ($name:literal) => {
+        concat!("s", $name)
+    };
+}
+
 /// CSR register identifiers
 ///
 /// Enumeration of the control and status registers that can be accessed
@@ -131,7 +156,7 @@ pub static MHARTID: Csr = Csr::new(CsrId::MHartId);
 /// This function is unsafe because setting the trap vector affects
 /// exception and interrupt handling for the entire system.
 pub unsafe fn write_mtvec(addr: usize) {
-    core::arch::asm!("csrw mtvec, {}", in(reg) addr);
+    core::arch::asm!(concat!("csrw ", xcsr!("tvec"), ", {}"), in(reg) addr);
 }
 
 /// Read Machine Trap Vector Base Address register
@@ -141,7 +166,7 @@ pub unsafe fn write_mtvec(addr: usize) {
 pub fn read_mtvec() -> usize {
     let mut val: usize;
     unsafe {
-        core::arch::asm!("csrr {}, mtvec", out(reg) val);
+        core::arch::asm!(concat!("csrr {}, ", xcsr!("tvec")), out(reg) val);
     }
     val
 }
@@ -153,7 +178,20 @@ pub fn read_mtvec() -> usize {
 pub fn read_mcause() -> usize {
     let mut val: usize;
     unsafe {
-        core::arch::asm!("csrr {}, mcause", out(reg) val);
+        core::arch::asm!(concat!("csrr {}, ", xcsr!("cause")), out(reg) val);
+    }
+    val
+}
+
+/// Read Machine Trap Value register
+///
+/// # Returns
+/// The faulting address or instruction bits of the most recent trap,
+/// depending on the cause (0 if not provided)
+pub fn read_mtval() -> usize {
+    let mut val: usize;
+    unsafe {
+        core::arch::asm!(concat!("csrr {}, ", xcsr!("tval")), out(reg) val);
     }
     val
 }
@@ -165,11 +203,35 @@ pub fn read_mcause() -> usize {
 pub fn read_mepc() -> usize {
     let mut val: usize;
     unsafe {
-        core::arch::asm!("csrr {}, mepc", out(reg) val);
+        core::arch::asm!(concat!("csrr {}, ", xcsr!("epc")), out(reg) val);
+    }
+    val
+}
+
+/// Read Machine Scratch register
+///
+/// # Returns
+/// The per-hart pointer the trap entry keeps in `mscratch` (`sscratch`)
+pub fn read_mscratch() -> usize {
+    let mut val: usize;
+    unsafe {
+        core::arch::asm!(concat!("csrr {}, ", xcsr!("scratch")), out(reg) val);
     }
     val
 }
 
+/// Write Machine Scratch register
+///
+/// # Arguments
+/// * `val` - The new scratch value
+///
+/// # Safety
+/// The trap entry code dereferences `mscratch`; it must point to valid
+/// per-hart trap state (or be 0).
+pub unsafe fn write_mscratch(val: usize) {
+    core::arch::asm!(concat!("csrw ", xcsr!("scratch"), ", {}"), in(reg) val);
+}
+
 /// Write to Machine Exception Program Counter
 ///
 /// # Arguments
@@ -179,7 +241,7 @@ pub fn read_mepc() -> usize {
 /// This function is unsafe because modifying the exception PC affects
 /// control flow when returning from trap handlers.
 pub unsafe fn write_mepc(addr: usize) {
-    core::arch::asm!("csrw mepc, {}", in(reg) addr);
+    core::arch::asm!(concat!("csrw ", xcsr!("epc"), ", {}"), in(reg) addr);
 }
 
 /// Read Machine Status register
@@ -189,7 +251,7 @@ pub unsafe fn write_mepc(addr: usize) {
 pub fn read_mstatus() -> usize {
     let mut val: usize;
     unsafe {
-        core::arch::asm!("csrr {}, mstatus", out(reg) val);
+        core::arch::asm!(concat!("csrr {}, ", xcsr!("status")), out(reg) val);
     }
     val
 }
@@ -203,7 +265,7 @@ pub fn read_mstatus() -> usize {
 /// This function is unsafe because the machine status register controls
 /// interrupt enables, privilege levels, and other critical system state.
 pub unsafe fn write_mstatus(val: usize) {
-    core::arch::asm!("csrw mstatus, {}", in(reg) val);
+    core::arch::asm!(concat!("csrw ", xcsr!("status"), ", {}"), in(reg) val);
 }
 
 /// Read Machine Interrupt Enable register
@@ -213,7 +275,7 @@ pub unsafe fn write_mstatus(val: usize) {
 pub fn read_mie() -> usize {
     let mut val: usize;
     unsafe {
-        core::arch::asm!("csrr {}, mie", out(reg) val);
+        core::arch::asm!(concat!("csrr {}, ", xcsr!("ie")), out(reg) val);
     }
     val
 }
@@ -227,7 +289,7 @@ pub fn read_mie() -> usize {
 /// This function is unsafe because enabling/disabling interrupts affects
 /// system responsiveness and real-time behavior.
 pub unsafe fn write_mie(val: usize) {
-    core::arch::asm!("csrw mie, {}", in(reg) val);
+    core::arch::asm!(concat!("csrw ", xcsr!("ie"), ", {}"), in(reg) val);
 }
 
 /// Read Machine Interrupt Pending register
@@ -237,15 +299,33 @@ pub unsafe fn write_mie(val: usize) {
 pub fn read_mip() -> usize {
     let mut val: usize;
     unsafe {
-        core::arch::asm!("csrr {}, mip", out(reg) val);
+        core::arch::asm!(concat!("csrr {}, ", xcsr!("ip")), out(reg) val);
     }
     val
 }
 
+/// Clear bits in the Machine Interrupt Pending register
+///
+/// Only software-writable pending bits are affected (e.g. SSIP in S-mode).
+///
+/// # Arguments
+/// * `mask` - Bitmask of pending bits to clear
+///
+/// # Safety
+/// This function is unsafe because clearing pending bits can drop
+/// interrupts that have not been handled yet.
+pub unsafe fn clear_mip(mask: usize) {
+    core::arch::asm!(concat!("csrc ", xcsr!("ip"), ", {}"), in(reg) mask);
+}
+
 /// Read Machine Hart ID register
 ///
+/// `mhartid` is not accessible from S-mode, so S-mode builds return the
+/// hart ID that `boot.s` keeps in `tp` instead.
+///
 /// # Returns
 /// The unique identifier for this hardware thread
+#[cfg(not(feature = "smode"))]
 pub fn read_mhartid() -> u64 {
     let mut val: u64;
     unsafe {
@@ -254,6 +334,90 @@ pub fn read_mhartid() -> u64 {
     val
 }
 
+/// Read Machine Hart ID register
+///
+/// `mhartid` is not accessible from S-mode, so S-mode builds return the
+/// hart ID that `boot.s` keeps in `tp` instead.
+///
+/// # Returns
+/// The unique identifier for this hardware thread
+#[cfg(feature = "smode")]
+pub fn read_mhartid() -> u64 {
+    let mut val: u64;
+    unsafe {
+        core::arch::asm!("mv {}, tp", out(reg) val);
+    }
+    val
+}
+
+/// Read the `time` CSR
+///
+/// # Returns
+/// The current value of the platform timer (mirrors MTIME)
+pub fn read_time() -> u64 {
+    let mut val: u64;
+    unsafe {
+        core::arch::asm!("rdtime {}", out(reg) val);
+    }
+    val
+}
+
+/// Read the `cycle` CSR
+///
+/// # Returns
+/// Number of clock cycles executed by this hart (mirrors `mcycle`)
+pub fn read_cycle() -> u64 {
+    let mut val: u64;
+    unsafe {
+        core::arch::asm!("rdcycle {}", out(reg) val);
+    }
+    val
+}
+
+/// Read Supervisor Address Translation and Protection register
+///
+/// # Returns
+/// The current `satp` value (MODE, ASID and root page table PPN)
+pub fn read_satp() -> usize {
+    let mut val: usize;
+    unsafe {
+        core::arch::asm!("csrr {}, satp", out(reg) val);
+    }
+    val
+}
+
+/// Write Supervisor Address Translation and Protection register
+///
+/// `satp` is accessible from both M-mode and S-mode. Callers must issue
+/// [`sfence_vma_all`] afterwards if stale translations may exist.
+///
+/// # Arguments
+/// * `val` - The new `satp` value
+///
+/// # Safety
+/// This function is unsafe because switching address spaces changes how
+/// every S-mode memory access is translated.
+pub unsafe fn write_satp(val: usize) {
+    core::arch::asm!("csrw satp, {}", in(reg) val);
+}
+
+/// Flush the TLB entries for a single virtual address on this hart
+///
+/// # Arguments
+/// * `vaddr` - The virtual address whose translation changed
+pub fn sfence_vma(vaddr: usize) {
+    unsafe {
+        core::arch::asm!("sfence.vma {}, zero", in(reg) vaddr);
+    }
+}
+
+/// Flush all TLB entries on this hart
+pub fn sfence_vma_all() {
+    unsafe {
+        core::arch::asm!("sfence.vma");
+    }
+}
+
 /// RISC-V CSR bit field constants
 ///
 /// This module contains bit field definitions for various RISC-V CSRs,
@@ -292,6 +456,49 @@ pub mod bits {
     /// Machine external interrupt pending bit
     pub const MIP_MEIP: usize = 1 << 11;
 
+    // Supervisor register bit fields (used by S-mode builds)
+
+    /// Global interrupt enable bit in sstatus
+    pub const SSTATUS_SIE: usize = 1 << 1;
+
+    /// Previous interrupt enable bit in sstatus
+    pub const SSTATUS_SPIE: usize = 1 << 5;
+
+    /// Previous privilege mode bit in sstatus
+    pub const SSTATUS_SPP: usize = 1 << 8;
+
+    /// Supervisor software interrupt enable bit
+    pub const SIE_SSIE: usize = 1 << 1;
+
+    /// Supervisor timer interrupt enable bit
+    pub const SIE_STIE: usize = 1 << 5;
+
+    /// Supervisor external interrupt enable bit
+    pub const SIE_SEIE: usize = 1 << 9;
+
+    /// Supervisor software interrupt pending bit
+    pub const SIP_SSIP: usize = 1 << 1;
+
+    /// Supervisor timer interrupt pending bit
+    pub const SIP_STIP: usize = 1 << 5;
+
+    /// Supervisor external interrupt pending bit
+    pub const SIP_SEIP: usize = 1 << 9;
+
+    // Supervisor Address Translation and Protection register fields
+
+    /// Sv39 translation mode in satp.MODE
+    pub const SATP_MODE_SV39: usize = 8 << 60;
+
+    /// Mask of the satp.MODE field
+    pub const SATP_MODE_MASK: usize = 0xf << 60;
+
+    /// Shift of the satp.ASID field
+    pub const SATP_ASID_SHIFT: usize = 44;
+
+    /// Mask of the satp.PPN field
+    pub const SATP_PPN_MASK: usize = (1 << 44) - 1;
+
     // Machine Cause register bit fields
 
     /// Interrupt bit in mcause (bit 63)
@@ -335,6 +542,15 @@ pub mod bits {
     /// Environment call from Machine mode
     pub const EXCEPTION_ECALL_MMODE: usize = 11;
 
+    /// Instruction page fault
+    pub const EXCEPTION_INSTR_PAGE_FAULT: usize = 12;
+
+    /// Load page fault
+    pub const EXCEPTION_LOAD_PAGE_FAULT: usize = 13;
+
+    /// Store/AMO page fault
+    pub const EXCEPTION_STORE_PAGE_FAULT: usize = 15;
+
     // Interrupt codes for mcause register
 
     /// Machine software interrupt
@@ -345,13 +561,118 @@ pub mod bits {
 
     /// Machine external interrupt
     pub const INTERRUPT_EXT_MACHINE: usize = 11;
+
+    /// Supervisor software interrupt
+    pub const INTERRUPT_SW_SUPERVISOR: usize = 1;
+
+    /// Supervisor timer interrupt
+    pub const INTERRUPT_TIMER_SUPERVISOR: usize = 5;
+
+    /// Supervisor external interrupt
+    pub const INTERRUPT_EXT_SUPERVISOR: usize = 9;
+
+    /// Trap vector MODE field mask (mtvec/stvec)
+    pub const TVEC_MODE_MASK: usize = 0b11;
+
+    /// Trap vector mode: all traps enter at BASE
+    pub const TVEC_MODE_DIRECT: usize = 0;
+
+    /// Trap vector mode: interrupts enter at BASE + 4 * cause
+    pub const TVEC_MODE_VECTORED: usize = 1;
+
+    // Privilege-neutral aliases for the kernel's own privilege level
+
+    /// Global interrupt enable bit (MIE or SIE)
+    #[cfg(not(feature = "smode"))]
+    pub const STATUS_IE: usize = MSTATUS_MIE;
+    /// Global interrupt enable bit (MIE or SIE)
+    #[cfg(feature = "smode")]
+    pub const STATUS_IE: usize = SSTATUS_SIE;
+
+    /// Previous interrupt enable bit (MPIE or SPIE)
+    #[cfg(not(feature = "smode"))]
+    pub const STATUS_PIE: usize = MSTATUS_MPIE;
+    /// Previous interrupt enable bit (MPIE or SPIE)
+    #[cfg(feature = "smode")]
+    pub const STATUS_PIE: usize = SSTATUS_SPIE;
+
+    /// Shift of the previous privilege field (MPP or SPP)
+    #[cfg(not(feature = "smode"))]
+    pub const STATUS_PP_SHIFT: usize = 11;
+    /// Shift of the previous privilege field (MPP or SPP)
+    #[cfg(feature = "smode")]
+    pub const STATUS_PP_SHIFT: usize = 8;
+
+    /// Width mask of the previous privilege field after shifting
+    #[cfg(not(feature = "smode"))]
+    pub const STATUS_PP_MASK: usize = 3;
+    /// Width mask of the previous privilege field after shifting
+    #[cfg(feature = "smode")]
+    pub const STATUS_PP_MASK: usize = 1;
+
+    /// Software interrupt enable bit (MSIE or SSIE)
+    #[cfg(not(feature = "smode"))]
+    pub const IE_SOFT: usize = MIE_MSIE;
+    /// Software interrupt enable bit (MSIE or SSIE)
+    #[cfg(feature = "smode")]
+    pub const IE_SOFT: usize = SIE_SSIE;
+
+    /// Timer interrupt enable bit (MTIE or STIE)
+    #[cfg(not(feature = "smode"))]
+    pub const IE_TIMER: usize = MIE_MTIE;
+    /// Timer interrupt enable bit (MTIE or STIE)
+    #[cfg(feature = "smode")]
+    pub const IE_TIMER: usize = SIE_STIE;
+
+    /// External interrupt enable bit (MEIE or SEIE)
+    #[cfg(not(feature = "smode"))]
+    pub const IE_EXT: usize = MIE_MEIE;
+    /// External interrupt enable bit (MEIE or SEIE)
+    #[cfg(feature = "smode")]
+    pub const IE_EXT: usize = SIE_SEIE;
+
+    /// Software interrupt pending bit (MSIP or SSIP)
+    #[cfg(not(feature = "smode"))]
+    pub const IP_SOFT: usize = MIP_MSIP;
+    /// Software interrupt pending bit (MSIP or SSIP)
+    #[cfg(feature = "smode")]
+    pub const IP_SOFT: usize = SIP_SSIP;
+
+    /// Software interrupt cause code
+    #[cfg(not(feature = "smode"))]
+    pub const INTERRUPT_SW: usize = INTERRUPT_SW_MACHINE;
+    /// Software interrupt cause code
+    #[cfg(feature = "smode")]
+    pub const INTERRUPT_SW: usize = INTERRUPT_SW_SUPERVISOR;
+
+    /// Timer interrupt cause code
+    #[cfg(not(feature = "smode"))]
+    pub const INTERRUPT_TIMER: usize = INTERRUPT_TIMER_MACHINE;
+    /// Timer interrupt cause code
+    #[cfg(feature = "smode")]
+    pub const INTERRUPT_TIMER: usize = INTERRUPT_TIMER_SUPERVISOR;
+
+    /// External interrupt cause code
+    #[cfg(not(feature = "smode"))]
+    pub const INTERRUPT_EXT: usize = INTERRUPT_EXT_MACHINE;
+    /// External interrupt cause code
+    #[cfg(feature = "smode")]
+    pub const INTERRUPT_EXT: usize = INTERRUPT_EXT_SUPERVISOR;
+
+    /// Environment call from the kernel's own privilege level
+    #[cfg(not(feature = "smode"))]
+    pub const EXCEPTION_ECALL: usize = EXCEPTION_ECALL_MMODE;
+    /// Environment call from the kernel's own privilege level
+    #[cfg(feature = "smode")]
+    pub const EXCEPTION_ECALL: usize = EXCEPTION_ECALL_SMODE;
 }
 
 // High-level interrupt control functions
 
 /// Enable machine timer interrupts
 ///
-/// Sets the MTIE bit in the MIE register to enable timer interrupts.
+/// Sets the MTIE bit in the MIE register (STIE in `sie` for S-mode builds)
+/// to enable timer interrupts.
 ///
 /// # Returns
 /// `Ok(())` on success, `Err(RiscvError::HardwareFault)` if verification fails
@@ -361,12 +682,12 @@ pub mod bits {
 /// system scheduling and real-time behavior.
 pub unsafe fn enable_machine_timer_interrupt() -> Result<(), RiscvError> {
     let mut mie = read_mie();
-    mie |= bits::MIE_MTIE;
+    mie |= bits::IE_TIMER;
     write_mie(mie);
 
     // Verify the write succeeded
     let readback = read_mie();
-    if (readback & bits::MIE_MTIE) != 0 {
+    if (readback & bits::IE_TIMER) != 0 {
         Ok(())
     } else {
         Err(RiscvError::HardwareFault)
@@ -375,7 +696,8 @@ pub unsafe fn enable_machine_timer_interrupt() -> Result<(), RiscvError> {
 
 /// Enable machine external interrupts
 ///
-/// Sets the MEIE bit in the MIE register to enable external interrupts.
+/// Sets the MEIE bit in the MIE register (SEIE in `sie` for S-mode builds)
+/// to enable external interrupts.
 ///
 /// # Returns
 /// `Ok(())` on success, `Err(RiscvError::HardwareFault)` if verification fails
@@ -385,11 +707,11 @@ pub unsafe fn enable_machine_timer_interrupt() -> Result<(), RiscvError> {
 /// how the system responds to hardware events.
 pub unsafe fn enable_machine_external_interrupt() -> Result<(), RiscvError> {
     let mut mie = read_mie();
-    mie |= bits::MIE_MEIE;
+    mie |= bits::IE_EXT;
     write_mie(mie);
 
     let readback = read_mie();
-    if (readback & bits::MIE_MEIE) != 0 {
+    if (readback & bits::IE_EXT) != 0 {
         Ok(())
     } else {
         Err(RiscvError::HardwareFault)
@@ -398,7 +720,8 @@ pub unsafe fn enable_machine_external_interrupt() -> Result<(), RiscvError> {
 
 /// Enable machine software interrupts
 ///
-/// Sets the MSIE bit in the MIE register to enable software interrupts.
+/// Sets the MSIE bit in the MIE register (SSIE in `sie` for S-mode builds)
+/// to enable software interrupts.
 ///
 /// # Returns
 /// `Ok(())` on success, `Err(RiscvError::HardwareFault)` if verification fails
@@ -408,11 +731,11 @@ pub unsafe fn enable_machine_external_interrupt() -> Result<(), RiscvError> {
 /// inter-processor communication and task scheduling.
 pub unsafe fn enable_machine_software_interrupt() -> Result<(), RiscvError> {
     let mut mie = read_mie();
-    mie |= bits::MIE_MSIE;
+    mie |= bits::IE_SOFT;
     write_mie(mie);
 
     let readback = read_mie();
-    if (readback & bits::MIE_MSIE) != 0 {
+    if (readback & bits::IE_SOFT) != 0 {
         Ok(())
     } else {
         Err(RiscvError::HardwareFault)
@@ -421,7 +744,8 @@ pub unsafe fn enable_machine_software_interrupt() -> Result<(), RiscvError> {
 
 /// Enable global interrupts
 ///
-/// Sets the MIE bit in the mstatus register to enable interrupt handling.
+/// Sets the MIE bit in the mstatus register (SIE in `sstatus` for S-mode
+/// builds) to enable interrupt handling.
 ///
 /// # Returns
 /// `Ok(())` on success, `Err(RiscvError::HardwareFault)` if verification fails
@@ -431,11 +755,11 @@ pub unsafe fn enable_machine_software_interrupt() -> Result<(), RiscvError> {
 /// system concurrency and timing behavior.
 pub unsafe fn enable_global_interrupts() -> Result<(), RiscvError> {
     let mut mstatus = read_mstatus();
-    mstatus |= bits::MSTATUS_MIE;
+    mstatus |= bits::STATUS_IE;
     write_mstatus(mstatus);
 
     let readback = read_mstatus();
-    if (readback & bits::MSTATUS_MIE) != 0 {
+    if (readback & bits::STATUS_IE) != 0 {
         Ok(())
     } else {
         Err(RiscvError::HardwareFault)
@@ -444,7 +768,8 @@ pub unsafe fn enable_global_interrupts() -> Result<(), RiscvError> {
 
 /// Disable global interrupts
 ///
-/// Clears the MIE bit in the mstatus register to disable interrupt handling.
+/// Clears the MIE bit in the mstatus register (SIE in `sstatus` for S-mode
+/// builds) to disable interrupt handling.
 ///
 /// # Returns
 /// `Ok(())` on success, `Err(RiscvError::HardwareFault)` if verification fails
@@ -454,11 +779,11 @@ pub unsafe fn enable_global_interrupts() -> Result<(), RiscvError> {
 /// system responsiveness and real-time guarantees.
 pub unsafe fn disable_global_interrupts() -> Result<(), RiscvError> {
     let mut mstatus = read_mstatus();
-    mstatus &= !bits::MSTATUS_MIE;
+    mstatus &= !bits::STATUS_IE;
     write_mstatus(mstatus);
 
     let readback = read_mstatus();
-    if (readback & bits::MSTATUS_MIE) == 0 {
+    if (readback & bits::STATUS_IE) == 0 {
         Ok(())
     } else {
         Err(RiscvError::HardwareFault)
@@ -471,7 +796,61 @@ pub unsafe fn disable_global_interrupts() -> Result<(), RiscvError> {
 /// `true` if global interrupts are enabled, `false` otherwise
 pub fn interrupts_enabled() -> bool {
     let mstatus = read_mstatus();
-    (mstatus & bits::MSTATUS_MIE) != 0
+    (mstatus & bits::STATUS_IE) != 0
+}
+
+/// Disable global interrupts and report whether they were enabled
+///
+/// Clears the IE bit with a single `csrrc`, so no interrupt can be taken
+/// between reading and clearing it.
+///
+/// # Returns
+/// `true` if global interrupts were enabled before the call
+///
+/// # Safety
+/// This function is unsafe because disabling global interrupts can affect
+/// system responsiveness and real-time guarantees.
+pub unsafe fn disable_interrupts_save() -> bool {
+    let previous: usize;
+    core::arch::asm!(
+        concat!("csrrc {}, ", xcsr!("status"), ", {}"),
+        out(reg) previous,
+        in(reg) bits::STATUS_IE,
+    );
+    previous & bits::STATUS_IE != 0
+}
+
+/// Enable global interrupts and report whether they were enabled
+///
+/// # Returns
+/// `true` if global interrupts were enabled before the call
+///
+/// # Safety
+/// This function is unsafe because enabling global interrupts affects
+/// system concurrency and timing behavior.
+pub unsafe fn enable_interrupts_save() -> bool {
+    let previous: usize;
+    core::arch::asm!(
+        concat!("csrrs {}, ", xcsr!("status"), ", {}"),
+        out(reg) previous,
+        in(reg) bits::STATUS_IE,
+    );
+    previous & bits::STATUS_IE != 0
+}
+
+/// Restore the global interrupt state saved by `*_interrupts_save`
+///
+/// # Arguments
+/// * `enabled` - Whether global interrupts should be enabled
+///
+/// # Safety
+/// This function is unsafe because it can enable global interrupts.
+pub unsafe fn restore_interrupts(enabled: bool) {
+    if enabled {
+        core::arch::asm!(concat!("csrs ", xcsr!("status"), ", {}"), in(reg) bits::STATUS_IE);
+    } else {
+        core::arch::asm!(concat!("csrc ", xcsr!("status"), ", {}"), in(reg) bits::STATUS_IE);
+    }
 }
 
 /// Interrupt types for checking enable status
@@ -495,9 +874,9 @@ pub enum InterruptType {
 pub fn is_interrupt_enabled(interrupt_type: InterruptType) -> bool {
     let mie = read_mie();
     match interrupt_type {
-        InterruptType::Software => (mie & bits::MIE_MSIE) != 0,
-        InterruptType::Timer => (mie & bits::MIE_MTIE) != 0,
-        InterruptType::External => (mie & bits::MIE_MEIE) != 0,
+        InterruptType::Software => (mie & bits::IE_SOFT) != 0,
+        InterruptType::Timer => (mie & bits::IE_TIMER) != 0,
+        InterruptType::External => (mie & bits::IE_EXT) != 0,
     }
 }
 
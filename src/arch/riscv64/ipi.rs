@@ -0,0 +1,185 @@
+// src/arch/riscv64/ipi.rs
+//! RISC-V Software Interrupt Primitives
+//!
+//! This module provides low-level access to the per-hart software interrupt
+//! pending bits in the CLINT MSIP array. Each hart owns one 32-bit MSIP
+//! register; writing 1 raises a machine software interrupt on that hart.
+//!
+//! In S-mode builds (`smode` feature) the CLINT is owned by the SBI
+//! firmware: interrupts are sent with the SBI IPI extension and arrive as
+//! supervisor software interrupts (`sip.SSIP`), which a hart can only
+//! observe and clear for itself.
+
+#[cfg(feature = "smode")]
+use super::{csr, sbi};
+use super::{memory_map, platform, RiscvError, MAX_HARTS};
+
+/// Get the MSIP register address for a hart
+///
+/// # Arguments
+/// * `hart` - The target hart ID
+///
+/// # Returns
+/// Pointer to the hart's MSIP register in the CLINT
+pub fn msip_addr(hart: usize) -> *mut u32 {
+    (platform::clint_base() + memory_map::MSIP_OFFSET + hart * 4) as *mut u32
+}
+
+/// Raise a software interrupt on the specified hart
+///
+/// # Arguments
+/// * `hart` - The target hart ID
+///
+/// # Returns
+/// `Ok(())` on success, `Err(RiscvError::InvalidAddress)` if the hart ID
+/// is outside the supported range
+#[cfg(not(feature = "smode"))]
+pub fn send(hart: usize) -> Result<(), RiscvError> {
+    if hart >= MAX_HARTS {
+        return Err(RiscvError::InvalidAddress);
+    }
+
+    unsafe {
+        core::ptr::write_volatile(msip_addr(hart), 1);
+    }
+    Ok(())
+}
+
+/// Raise a software interrupt on the specified hart through SBI IPI
+///
+/// # Arguments
+/// * `hart` - The target hart ID
+///
+/// # Returns
+/// `Ok(())` on success, `Err(RiscvError::InvalidAddress)` if the hart ID
+/// is outside the supported range, or the mapped SBI error
+#[cfg(feature = "smode")]
+pub fn send(hart: usize) -> Result<(), RiscvError> {
+    if hart >= MAX_HARTS {
+        return Err(RiscvError::InvalidAddress);
+    }
+
+    sbi::send_ipi(1 << hart, 0)?;
+    Ok(())
+}
+
+/// Raise a software interrupt on every hart in a mask
+///
+/// # Arguments
+/// * `hart_mask` - Bitmask of target hart IDs
+///
+/// # Returns
+/// `Ok(())` on success, `Err(RiscvError::InvalidAddress)` if the mask
+/// contains a hart outside the supported range
+#[cfg(not(feature = "smode"))]
+pub fn send_mask(hart_mask: usize) -> Result<(), RiscvError> {
+    if hart_mask >> MAX_HARTS != 0 {
+        return Err(RiscvError::InvalidAddress);
+    }
+
+    for hart in 0..MAX_HARTS {
+        if hart_mask & (1 << hart) != 0 {
+            unsafe {
+                core::ptr::write_volatile(msip_addr(hart), 1);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Raise a software interrupt on every hart in a mask with one SBI call
+///
+/// # Arguments
+/// * `hart_mask` - Bitmask of target hart IDs
+///
+/// # Returns
+/// `Ok(())` on success, `Err(RiscvError::InvalidAddress)` if the mask
+/// contains a hart outside the supported range, or the mapped SBI error
+#[cfg(feature = "smode")]
+pub fn send_mask(hart_mask: usize) -> Result<(), RiscvError> {
+    if hart_mask >> MAX_HARTS != 0 {
+        return Err(RiscvError::InvalidAddress);
+    }
+    if hart_mask == 0 {
+        return Ok(());
+    }
+
+    sbi::send_ipi(hart_mask, 0)?;
+    Ok(())
+}
+
+/// Clear a pending software interrupt on the specified hart
+///
+/// # Arguments
+/// * `hart` - The target hart ID
+///
+/// # Returns
+/// `Ok(())` on success, `Err(RiscvError::InvalidAddress)` if the hart ID
+/// is outside the supported range
+#[cfg(not(feature = "smode"))]
+pub fn clear(hart: usize) -> Result<(), RiscvError> {
+    if hart >= MAX_HARTS {
+        return Err(RiscvError::InvalidAddress);
+    }
+
+    unsafe {
+        core::ptr::write_volatile(msip_addr(hart), 0);
+    }
+    Ok(())
+}
+
+/// Clear a pending supervisor software interrupt
+///
+/// # Arguments
+/// * `hart` - The target hart ID (must be the calling hart)
+///
+/// # Returns
+/// `Ok(())` on success, `Err(RiscvError::InvalidAddress)` if the hart ID
+/// is outside the supported range, or `Err(RiscvError::InvalidPrivilege)`
+/// for another hart
+#[cfg(feature = "smode")]
+pub fn clear(hart: usize) -> Result<(), RiscvError> {
+    if hart >= MAX_HARTS {
+        return Err(RiscvError::InvalidAddress);
+    }
+    if hart != csr::read_mhartid() as usize {
+        return Err(RiscvError::InvalidPrivilege);
+    }
+
+    unsafe {
+        csr::clear_mip(csr::bits::SIP_SSIP);
+    }
+    Ok(())
+}
+
+/// Check whether a software interrupt is pending on the specified hart
+///
+/// # Arguments
+/// * `hart` - The target hart ID
+///
+/// # Returns
+/// `true` if the hart's MSIP bit is set
+#[cfg(not(feature = "smode"))]
+pub fn is_pending(hart: usize) -> bool {
+    if hart >= MAX_HARTS {
+        return false;
+    }
+
+    unsafe { core::ptr::read_volatile(msip_addr(hart)) & 1 != 0 }
+}
+
+/// Check whether a supervisor software interrupt is pending
+///
+/// # Arguments
+/// * `hart` - The target hart ID (only the calling hart can be observed)
+///
+/// # Returns
+/// `true` if `hart` is the calling hart and its SSIP bit is set
+#[cfg(feature = "smode")]
+pub fn is_pending(hart: usize) -> bool {
+    if hart >= MAX_HARTS || hart != csr::read_mhartid() as usize {
+        return false;
+    }
+
+    csr::read_mip() & csr::bits::SIP_SSIP != 0
+}
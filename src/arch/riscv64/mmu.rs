@@ -0,0 +1,650 @@
+// src/arch/riscv64/mmu.rs
+//! RISC-V Sv39 Virtual Memory
+//!
+//! This module manages three-level Sv39 page tables: 4 KiB pages, 2 MiB
+//! megapages and 1 GiB gigapages over a 39-bit virtual address space.
+//! Page table pages are taken from the physical frame allocator.
+//!
+//! The kernel address space is an identity mapping (virtual == physical):
+//! text is mapped R+X, rodata R, data/bss/stacks and the remaining RAM R+W,
+//! and the UART, CLINT and PLIC windows R+W without execute. The guard page
+//! at the bottom of each hart stack is left unmapped. All kernel
+//! mappings are global and have the A/D bits preset so no hardware or
+//! software A/D update is ever required.
+//!
+//! In S-mode builds (`smode` feature) enabling paging translates every
+//! kernel access. In M-mode builds `satp` is programmed the same way, but
+//! M-mode fetches and accesses bypass translation; the tables then govern
+//! lower privilege levels only.
+//!
+//! Every online hart runs in the kernel address space: [`init`] switches
+//! harts that are already online through an IPI, and harts started later
+//! call [`activate_kernel`] themselves. Changes through [`kernel_unmap`] and
+//! [`kernel_protect`] shoot down the TLBs of the other online harts.
+
+use super::{csr, csr::bits, platform, MAX_HARTS, PAGE_SIZE};
+use crate::console::{hex, num};
+use crate::memory::{self, frame};
+use crate::sync::SpinLock;
+
+/// Number of entries in one page table
+const ENTRIES: usize = 512;
+
+/// Number of page table levels in Sv39
+const LEVELS: usize = 3;
+
+/// Highest mappable virtual address + 1 (addresses must not need sign extension)
+pub const VA_LIMIT: usize = 1 << 38;
+
+/// Size of a 2 MiB megapage
+pub const MEGAPAGE_SIZE: usize = PAGE_SIZE << 9;
+
+/// Size of a 1 GiB gigapage
+pub const GIGAPAGE_SIZE: usize = MEGAPAGE_SIZE << 9;
+
+/// Page table entry permission and attribute bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags(usize);
+
+impl PageFlags {
+    /// Valid
+    pub const VALID: Self = Self(1 << 0);
+
+    /// Readable
+    pub const READ: Self = Self(1 << 1);
+
+    /// Writable
+    pub const WRITE: Self = Self(1 << 2);
+
+    /// Executable
+    pub const EXECUTE: Self = Self(1 << 3);
+
+    /// Accessible from U-mode
+    pub const USER: Self = Self(1 << 4);
+
+    /// Global mapping (present in every address space)
+    pub const GLOBAL: Self = Self(1 << 5);
+
+    /// Accessed
+    pub const ACCESSED: Self = Self(1 << 6);
+
+    /// Dirty
+    pub const DIRTY: Self = Self(1 << 7);
+
+    /// No permissions
+    pub const NONE: Self = Self(0);
+
+    /// Kernel code: read + execute
+    pub const KERNEL_TEXT: Self = Self(Self::READ.0 | Self::EXECUTE.0 | Self::KERNEL_ATTRS.0);
+
+    /// Kernel read-only data
+    pub const KERNEL_RODATA: Self = Self(Self::READ.0 | Self::KERNEL_ATTRS.0);
+
+    /// Kernel data, stacks and free RAM: read + write, never executable
+    pub const KERNEL_DATA: Self =
+        Self(Self::READ.0 | Self::WRITE.0 | Self::DIRTY.0 | Self::KERNEL_ATTRS.0);
+
+    /// Device registers: read + write, never executable
+    pub const MMIO: Self = Self::KERNEL_DATA;
+
+    /// Attributes shared by all kernel leaves
+    const KERNEL_ATTRS: Self = Self(Self::GLOBAL.0 | Self::ACCESSED.0);
+
+    /// Mask of all flag bits in an entry
+    const MASK: usize = 0xff;
+
+    /// Get the raw bit representation
+    pub const fn bits(&self) -> usize {
+        self.0
+    }
+
+    /// Check whether all bits of `other` are set
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Check whether a leaf with these flags is usable (R or X set, W implies R)
+    const fn is_valid_leaf(&self) -> bool {
+        let r = self.0 & Self::READ.0 != 0;
+        let w = self.0 & Self::WRITE.0 != 0;
+        let x = self.0 & Self::EXECUTE.0 != 0;
+        (r || x) && (r || !w)
+    }
+}
+
+impl core::ops::BitOr for PageFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Virtual memory errors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MmuError {
+    /// No physical frame available for a page table
+    OutOfMemory,
+
+    /// Address or size is not page aligned
+    Misaligned,
+
+    /// Virtual address is outside the Sv39 range
+    InvalidAddress,
+
+    /// Flags do not describe a valid leaf (e.g. write-only)
+    InvalidFlags,
+
+    /// The virtual address is already mapped
+    AlreadyMapped,
+
+    /// The virtual address is not mapped
+    NotMapped,
+
+    /// Another hart did not acknowledge the TLB flush
+    ShootdownFailed,
+}
+
+impl MmuError {
+    /// Get a short description of the error
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MmuError::OutOfMemory => "Out of memory for page tables",
+            MmuError::Misaligned => "Address not page aligned",
+            MmuError::InvalidAddress => "Virtual address out of Sv39 range",
+            MmuError::InvalidFlags => "Invalid page permissions",
+            MmuError::AlreadyMapped => "Address already mapped",
+            MmuError::NotMapped => "Address not mapped",
+            MmuError::ShootdownFailed => "Remote TLB flush failed",
+        }
+    }
+}
+
+impl core::fmt::Display for MmuError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<frame::FrameError> for MmuError {
+    fn from(_: frame::FrameError) -> Self {
+        MmuError::OutOfMemory
+    }
+}
+
+/// A single Sv39 page table entry
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct PageTableEntry(usize);
+
+impl PageTableEntry {
+    const fn is_valid(&self) -> bool {
+        self.0 & PageFlags::VALID.0 != 0
+    }
+
+    /// Leaves have at least one of R/W/X set; other valid entries point to tables
+    const fn is_leaf(&self) -> bool {
+        self.0 & (PageFlags::READ.0 | PageFlags::WRITE.0 | PageFlags::EXECUTE.0) != 0
+    }
+
+    const fn addr(&self) -> usize {
+        (self.0 >> 10) << 12
+    }
+
+    const fn flags(&self) -> PageFlags {
+        PageFlags(self.0 & PageFlags::MASK)
+    }
+
+    const fn new(addr: usize, flags: PageFlags) -> Self {
+        Self(((addr >> 12) << 10) | flags.0 | PageFlags::VALID.0)
+    }
+}
+
+/// Size of the region mapped by one entry at `level` (0 = 4 KiB)
+const fn level_size(level: usize) -> usize {
+    PAGE_SIZE << (9 * level)
+}
+
+/// Index into the table at `level` for a virtual address
+const fn vpn(vaddr: usize, level: usize) -> usize {
+    (vaddr >> (12 + 9 * level)) & (ENTRIES - 1)
+}
+
+/// Get a pointer to entry `index` of the table at physical address `table`
+fn entry_ptr(table: usize, index: usize) -> *mut PageTableEntry {
+    (table as *mut PageTableEntry).wrapping_add(index)
+}
+
+/// Allocate a zeroed page table page
+fn alloc_table() -> Result<usize, MmuError> {
+    let table = frame::alloc_frame()?;
+    unsafe {
+        core::ptr::write_bytes(table as *mut u8, 0, PAGE_SIZE);
+    }
+    Ok(table)
+}
+
+/// An Sv39 address space rooted at a physical page table page
+pub struct PageTable {
+    root: usize,
+}
+
+impl PageTable {
+    /// Create an empty page table (no root allocated yet)
+    pub const fn empty() -> Self {
+        Self { root: 0 }
+    }
+
+    /// Create a new address space with an empty root table
+    ///
+    /// # Returns
+    /// The page table, or `MmuError::OutOfMemory`
+    pub fn new() -> Result<Self, MmuError> {
+        Ok(Self {
+            root: alloc_table()?,
+        })
+    }
+
+    /// Get the physical address of the root table
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    /// Build the `satp` value selecting this address space
+    ///
+    /// # Arguments
+    /// * `asid` - Address space identifier
+    pub fn satp(&self, asid: u16) -> usize {
+        bits::SATP_MODE_SV39
+            | ((asid as usize) << bits::SATP_ASID_SHIFT)
+            | ((self.root >> 12) & bits::SATP_PPN_MASK)
+    }
+
+    /// Walk to the entry mapping `vaddr` at `level`
+    ///
+    /// Missing intermediate tables are created when `create` is set, and
+    /// larger leaves on the way are split into next-level tables.
+    fn walk(
+        &mut self,
+        vaddr: usize,
+        level: usize,
+        create: bool,
+    ) -> Result<*mut PageTableEntry, MmuError> {
+        let mut table = self.root;
+        for current in (level + 1..LEVELS).rev() {
+            let entry = entry_ptr(table, vpn(vaddr, current));
+            let pte = unsafe { *entry };
+
+            if !pte.is_valid() {
+                if !create {
+                    return Err(MmuError::NotMapped);
+                }
+                let next = alloc_table()?;
+                unsafe { *entry = PageTableEntry::new(next, PageFlags::NONE) };
+            } else if pte.is_leaf() {
+                split(entry, current)?;
+            }
+
+            table = unsafe { (*entry).addr() };
+        }
+
+        Ok(entry_ptr(table, vpn(vaddr, level)))
+    }
+
+    /// Find the leaf mapping `vaddr` and its level
+    fn find_leaf(&self, vaddr: usize) -> Option<(*mut PageTableEntry, usize)> {
+        let mut table = self.root;
+        for level in (0..LEVELS).rev() {
+            let entry = entry_ptr(table, vpn(vaddr, level));
+            let pte = unsafe { *entry };
+            if !pte.is_valid() {
+                return None;
+            }
+            if pte.is_leaf() {
+                return Some((entry, level));
+            }
+            table = pte.addr();
+        }
+        None
+    }
+
+    /// Map a physically contiguous range
+    ///
+    /// The largest page size permitted by the alignment of `vaddr`/`paddr`
+    /// and the remaining length is used for each step.
+    ///
+    /// # Arguments
+    /// * `vaddr` - Virtual start address (page aligned)
+    /// * `paddr` - Physical start address (page aligned)
+    /// * `size` - Length in bytes (multiple of the page size)
+    /// * `flags` - Leaf permissions (VALID is implied)
+    ///
+    /// # Returns
+    /// `Ok(())` on success; on error the range may be partially mapped
+    pub fn map(
+        &mut self,
+        vaddr: usize,
+        paddr: usize,
+        size: usize,
+        flags: PageFlags,
+    ) -> Result<(), MmuError> {
+        check_range(vaddr, size)?;
+        if paddr % PAGE_SIZE != 0 {
+            return Err(MmuError::Misaligned);
+        }
+        if !flags.is_valid_leaf() {
+            return Err(MmuError::InvalidFlags);
+        }
+
+        let mut offset = 0;
+        while offset < size {
+            let va = vaddr + offset;
+            let pa = paddr + offset;
+            let level = (0..LEVELS)
+                .rev()
+                .find(|&level| {
+                    let page = level_size(level);
+                    va % page == 0 && pa % page == 0 && size - offset >= page
+                })
+                .unwrap_or(0);
+
+            let entry = self.walk(va, level, true)?;
+            if unsafe { (*entry).is_valid() } {
+                return Err(MmuError::AlreadyMapped);
+            }
+            unsafe { *entry = PageTableEntry::new(pa, flags) };
+
+            offset += level_size(level);
+        }
+
+        Ok(())
+    }
+
+    /// Get the leaf covering `vaddr` as a page of at most `remaining` bytes
+    ///
+    /// Leaves that extend beyond the requested range are split first.
+    fn leaf_within(
+        &mut self,
+        vaddr: usize,
+        remaining: usize,
+    ) -> Result<(*mut PageTableEntry, usize), MmuError> {
+        loop {
+            let (entry, level) = self.find_leaf(vaddr).ok_or(MmuError::NotMapped)?;
+            let page = level_size(level);
+            if vaddr % page == 0 && remaining >= page {
+                return Ok((entry, page));
+            }
+            split(entry, level)?;
+        }
+    }
+
+    /// Remove the mappings for a range
+    ///
+    /// Page table pages are kept for reuse. The TLB is flushed on this hart.
+    ///
+    /// # Arguments
+    /// * `vaddr` - Virtual start address (page aligned)
+    /// * `size` - Length in bytes (multiple of the page size)
+    pub fn unmap(&mut self, vaddr: usize, size: usize) -> Result<(), MmuError> {
+        check_range(vaddr, size)?;
+
+        let mut offset = 0;
+        while offset < size {
+            let (entry, page) = self.leaf_within(vaddr + offset, size - offset)?;
+            unsafe { *entry = PageTableEntry(0) };
+            csr::sfence_vma(vaddr + offset);
+            offset += page;
+        }
+
+        Ok(())
+    }
+
+    /// Change the permissions of a mapped range
+    ///
+    /// # Arguments
+    /// * `vaddr` - Virtual start address (page aligned)
+    /// * `size` - Length in bytes (multiple of the page size)
+    /// * `flags` - New leaf permissions
+    pub fn protect(&mut self, vaddr: usize, size: usize, flags: PageFlags) -> Result<(), MmuError> {
+        check_range(vaddr, size)?;
+        if !flags.is_valid_leaf() {
+            return Err(MmuError::InvalidFlags);
+        }
+
+        let mut offset = 0;
+        while offset < size {
+            let (entry, page) = self.leaf_within(vaddr + offset, size - offset)?;
+            unsafe { *entry = PageTableEntry::new((*entry).addr(), flags) };
+            csr::sfence_vma(vaddr + offset);
+            offset += page;
+        }
+
+        Ok(())
+    }
+
+    /// Translate a virtual address
+    ///
+    /// # Returns
+    /// The physical address and leaf flags, or `None` if unmapped
+    pub fn translate(&self, vaddr: usize) -> Option<(usize, PageFlags)> {
+        if self.root == 0 || vaddr >= VA_LIMIT {
+            return None;
+        }
+
+        let (entry, level) = self.find_leaf(vaddr)?;
+        let pte = unsafe { *entry };
+        let offset = vaddr & (level_size(level) - 1);
+        Some((pte.addr() + offset, pte.flags()))
+    }
+
+    /// Switch this hart to the address space
+    ///
+    /// # Safety
+    /// The table must map everything the caller touches afterwards,
+    /// including the current code and stack.
+    pub unsafe fn activate(&self, asid: u16) {
+        csr::write_satp(self.satp(asid));
+        csr::sfence_vma_all();
+    }
+}
+
+/// Replace the leaf `entry` at `level` with a table of next-level leaves
+fn split(entry: *mut PageTableEntry, level: usize) -> Result<(), MmuError> {
+    let pte = unsafe { *entry };
+    let table = alloc_table()?;
+    let child = level_size(level - 1);
+
+    for index in 0..ENTRIES {
+        unsafe {
+            *entry_ptr(table, index) = PageTableEntry::new(pte.addr() + index * child, pte.flags());
+        }
+    }
+    unsafe { *entry = PageTableEntry::new(table, PageFlags::NONE) };
+    Ok(())
+}
+
+/// Validate the alignment and range of a virtual region
+fn check_range(vaddr: usize, size: usize) -> Result<(), MmuError> {
+    if vaddr % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+        return Err(MmuError::Misaligned);
+    }
+    match vaddr.checked_add(size) {
+        Some(end) if end <= VA_LIMIT => Ok(()),
+        _ => Err(MmuError::InvalidAddress),
+    }
+}
+
+/// The kernel address space
+static KERNEL_PAGE_TABLE: SpinLock<PageTable> = SpinLock::new(PageTable::empty());
+
+/// Build the kernel identity mapping and enable Sv39 on this hart
+///
+/// Must be called after the frame allocator has been initialized.
+///
+/// # Returns
+/// `Ok(())` on success, or the first mapping error
+pub fn init() -> Result<(), MmuError> {
+    let mut table = KERNEL_PAGE_TABLE.lock();
+    if table.root != 0 {
+        return Err(MmuError::AlreadyMapped);
+    }
+    let mut new_table = PageTable::new()?;
+
+    let layout = memory::layout();
+    let page_up = |addr: usize| addr.next_multiple_of(PAGE_SIZE);
+    let page_down = |addr: usize| addr / PAGE_SIZE * PAGE_SIZE;
+
+    // All RAM read/write, then tighten the kernel image
+    for region in platform::boot_info().memory_regions() {
+        let start = page_up(region.base);
+        let end = page_down(region.end());
+        new_table.map(start, start, end - start, PageFlags::KERNEL_DATA)?;
+    }
+
+    // Text and rodata may live in RAM (tightened) or in ROM (mapped fresh)
+    for (region, flags) in [
+        (layout.text, PageFlags::KERNEL_TEXT),
+        (layout.rodata, PageFlags::KERNEL_RODATA),
+    ] {
+        let (start, end) = (page_down(region.start), page_up(region.end));
+        if start == end {
+            continue;
+        }
+        if platform::is_ram(start) {
+            new_table.protect(start, end - start, flags)?;
+        } else {
+            new_table.map(start, start, end - start, flags)?;
+        }
+    }
+
+    // Device windows
+    let info = platform::boot_info();
+    for device in [info.uart, info.clint, info.plic] {
+        if device.size != 0 {
+            let start = page_down(device.base);
+            let end = page_up(device.base + device.size);
+            new_table.map(start, start, end - start, PageFlags::MMIO)?;
+        }
+    }
+
+    // Stack guard pages stay unmapped so an overflow page-faults
+    for hart in 0..MAX_HARTS {
+        let (guard, size) = memory::hart_stack_guard(hart);
+        new_table.unmap(guard, size)?;
+    }
+
+    unsafe {
+        new_table.activate(0);
+    }
+    *table = new_table;
+    drop(table);
+
+    // Harts brought up before paging switch over too
+    let others = other_online_harts();
+    if others != 0 && crate::smp::ipi::call_function_mask(others, activate_on_hart, 0).is_err() {
+        crate::println!("⚠ Not all harts switched to the kernel address space");
+    }
+    Ok(())
+}
+
+/// IPI target: switch the receiving hart to the kernel address space
+fn activate_on_hart(_arg: usize) {
+    let _ = activate_kernel();
+}
+
+/// Bitmask of the online harts other than the calling one
+fn other_online_harts() -> usize {
+    crate::smp::online_mask() & !(1 << csr::read_mhartid() as usize)
+}
+
+/// Flush a changed range from the TLBs of the other online harts
+///
+/// The calling hart has already flushed its own TLB.
+///
+/// # Arguments
+/// * `vaddr` - Virtual start address
+/// * `size` - Length in bytes; ranges larger than a page flush everything
+fn shootdown(vaddr: usize, size: usize) -> Result<(), MmuError> {
+    let others = other_online_harts();
+    if others == 0 {
+        return Ok(());
+    }
+    let addr = if size <= PAGE_SIZE { Some(vaddr) } else { None };
+    crate::smp::ipi::flush_tlb_mask(others, addr).map_err(|_| MmuError::ShootdownFailed)
+}
+
+/// Switch the calling hart to the kernel address space
+///
+/// Called by secondary harts that start after the primary has run [`init`].
+/// Harts online during [`init`] are switched by it.
+///
+/// # Returns
+/// `Err(MmuError::NotMapped)` if the kernel table has not been built
+pub fn activate_kernel() -> Result<(), MmuError> {
+    let table = KERNEL_PAGE_TABLE.lock();
+    if table.root == 0 {
+        return Err(MmuError::NotMapped);
+    }
+    unsafe {
+        table.activate(0);
+    }
+    Ok(())
+}
+
+/// Map a range into the kernel address space
+///
+/// # Arguments
+/// * `vaddr` - Virtual start address (page aligned)
+/// * `paddr` - Physical start address (page aligned)
+/// * `size` - Length in bytes
+/// * `flags` - Leaf permissions
+pub fn kernel_map(
+    vaddr: usize,
+    paddr: usize,
+    size: usize,
+    flags: PageFlags,
+) -> Result<(), MmuError> {
+    KERNEL_PAGE_TABLE.lock().map(vaddr, paddr, size, flags)
+}
+
+/// Unmap a range from the kernel address space on every online hart
+pub fn kernel_unmap(vaddr: usize, size: usize) -> Result<(), MmuError> {
+    KERNEL_PAGE_TABLE.lock().unmap(vaddr, size)?;
+    shootdown(vaddr, size)
+}
+
+/// Change permissions of a range in the kernel address space on every
+/// online hart
+pub fn kernel_protect(vaddr: usize, size: usize, flags: PageFlags) -> Result<(), MmuError> {
+    KERNEL_PAGE_TABLE.lock().protect(vaddr, size, flags)?;
+    shootdown(vaddr, size)
+}
+
+/// Translate a kernel virtual address
+pub fn kernel_translate(vaddr: usize) -> Option<(usize, PageFlags)> {
+    KERNEL_PAGE_TABLE.lock().translate(vaddr)
+}
+
+/// Check whether Sv39 translation is enabled on this hart
+pub fn is_enabled() -> bool {
+    csr::read_satp() & bits::SATP_MODE_MASK == bits::SATP_MODE_SV39
+}
+
+/// Print the current translation state
+pub fn show_status() {
+    crate::println!("=== VIRTUAL MEMORY (Sv39) ===");
+    let satp = csr::read_satp();
+    crate::println!("satp: {}", hex(satp));
+    if is_enabled() {
+        crate::println!(
+            "Paging: enabled, root table {}, ASID {}",
+            hex((satp & bits::SATP_PPN_MASK) << 12),
+            num(((satp >> bits::SATP_ASID_SHIFT) & 0xffff) as u64)
+        );
+    } else {
+        crate::println!("Paging: disabled (bare)");
+    }
+    if cfg!(not(feature = "smode")) {
+        crate::println!("Note: M-mode accesses bypass translation");
+    }
+}
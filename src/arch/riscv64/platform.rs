@@ -0,0 +1,463 @@
+// src/arch/riscv64/platform.rs
+//! RISC-V Platform Description
+//!
+//! This module discovers the machine configuration at boot from the
+//! devicetree blob passed in `a1`: memory regions, the console UART, the
+//! CLINT and PLIC, the timer `timebase-frequency`, the hart count and the
+//! `/chosen` node. The result is stored in a [`BootInfo`] that the rest of
+//! the kernel queries instead of hardcoded addresses.
+//!
+//! When no valid devicetree is available, the QEMU virt defaults from
+//! [`memory_map`] are used so the kernel still boots on the default machine.
+
+use super::{memory_map, timer::TIMER_FREQ, MAX_HARTS};
+use crate::console::{hex, num};
+use crate::fdt::{self, Fdt, FdtError, FdtEvent};
+
+/// Maximum number of memory regions recorded from the devicetree
+pub const MAX_MEMORY_REGIONS: usize = 4;
+
+/// Maximum devicetree nesting depth tracked by the parser
+const MAX_DEPTH: usize = 8;
+
+/// Physical memory region
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    /// Start address of the region
+    pub base: usize,
+
+    /// Size of the region in bytes
+    pub size: usize,
+}
+
+impl MemoryRegion {
+    /// Create an empty memory region
+    pub const fn empty() -> Self {
+        Self { base: 0, size: 0 }
+    }
+
+    /// Get the end address (exclusive) of the region
+    pub const fn end(&self) -> usize {
+        self.base + self.size
+    }
+
+    /// Check if an address lies within the region
+    pub const fn contains(&self, addr: usize) -> bool {
+        addr >= self.base && addr < self.end()
+    }
+}
+
+/// Memory-mapped device description
+#[derive(Debug, Clone, Copy)]
+pub struct MmioDevice {
+    /// Base address of the device registers
+    pub base: usize,
+
+    /// Size of the register window in bytes
+    pub size: usize,
+
+    /// Interrupt source number, if the device has one
+    pub irq: Option<u32>,
+}
+
+/// Where the boot information came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BootInfoSource {
+    /// Parsed from the devicetree blob
+    DeviceTree,
+
+    /// Compiled-in QEMU virt defaults
+    Defaults,
+}
+
+/// Platform description discovered at boot
+#[derive(Debug, Clone, Copy)]
+pub struct BootInfo {
+    /// Origin of this information
+    pub source: BootInfoSource,
+
+    /// Physical address of the devicetree blob (0 if none)
+    pub dtb_addr: usize,
+
+    /// Size of the devicetree blob in bytes
+    pub dtb_size: usize,
+
+    /// Usable RAM regions
+    pub memory: [MemoryRegion; MAX_MEMORY_REGIONS],
+
+    /// Number of valid entries in `memory`
+    pub memory_count: usize,
+
+    /// Console UART (ns16550a)
+    pub uart: MmioDevice,
+
+    /// Core-Local Interruptor
+    pub clint: MmioDevice,
+
+    /// Platform-Level Interrupt Controller
+    pub plic: MmioDevice,
+
+    /// Number of PLIC interrupt sources (`riscv,ndev`)
+    pub plic_ndev: u32,
+
+    /// Timer frequency in Hz (`/cpus/timebase-frequency`)
+    pub timebase_frequency: u64,
+
+    /// Bitmask of hart IDs found under `/cpus`
+    pub hart_mask: usize,
+
+    /// Kernel command line (`/chosen/bootargs`)
+    pub bootargs: Option<&'static str>,
+
+    /// Console path (`/chosen/stdout-path`)
+    pub stdout_path: Option<&'static str>,
+}
+
+impl BootInfo {
+    /// Create boot information from the compiled-in QEMU virt defaults
+    pub const fn defaults() -> Self {
+        let mut memory = [MemoryRegion::empty(); MAX_MEMORY_REGIONS];
+        memory[0] = MemoryRegion {
+            base: memory_map::RAM_START,
+            size: memory_map::RAM_SIZE,
+        };
+
+        Self {
+            source: BootInfoSource::Defaults,
+            dtb_addr: 0,
+            dtb_size: 0,
+            memory,
+            memory_count: 1,
+            uart: MmioDevice {
+                base: memory_map::UART0_BASE,
+                size: memory_map::UART0_SIZE,
+                irq: Some(memory_map::UART0_IRQ),
+            },
+            clint: MmioDevice {
+                base: memory_map::CLINT_BASE,
+                size: memory_map::CLINT_SIZE,
+                irq: None,
+            },
+            plic: MmioDevice {
+                base: memory_map::PLIC_BASE,
+                size: memory_map::PLIC_SIZE,
+                irq: None,
+            },
+            plic_ndev: memory_map::PLIC_NDEV,
+            timebase_frequency: TIMER_FREQ,
+            hart_mask: (1 << MAX_HARTS) - 1,
+            bootargs: None,
+            stdout_path: None,
+        }
+    }
+
+    /// Get the valid memory regions
+    pub fn memory_regions(&self) -> &[MemoryRegion] {
+        &self.memory[..self.memory_count]
+    }
+
+    /// Get the number of harts described by the platform
+    pub fn hart_count(&self) -> usize {
+        self.hart_mask.count_ones() as usize
+    }
+}
+
+/// Global boot information (written once by the primary hart in `init`)
+static mut BOOT_INFO: BootInfo = BootInfo::defaults();
+
+/// Get the platform description
+///
+/// # Returns
+/// The boot information discovered by [`init`], or the defaults if `init`
+/// has not run or no devicetree was found
+pub fn boot_info() -> &'static BootInfo {
+    unsafe { &*core::ptr::addr_of!(BOOT_INFO) }
+}
+
+/// Discover the platform from the devicetree
+///
+/// Must be called by the primary hart before any secondary hart is
+/// released. On failure the defaults remain in effect.
+///
+/// # Arguments
+/// * `dtb_addr` - Devicetree address passed by firmware in `a1`
+///
+/// # Returns
+/// `Ok(())` if the devicetree was parsed, or the parse error
+pub fn init(dtb_addr: usize) -> Result<(), FdtError> {
+    let fdt = unsafe { Fdt::from_addr(dtb_addr)? };
+    let info = parse(&fdt, dtb_addr)?;
+
+    unsafe {
+        *core::ptr::addr_of_mut!(BOOT_INFO) = info;
+    }
+    Ok(())
+}
+
+/// Per-node state collected while walking the tree
+#[derive(Clone, Copy)]
+struct NodeState<'a> {
+    name: &'a str,
+    compatible: &'a [u8],
+    device_type: &'a [u8],
+    reg: &'a [u8],
+    interrupts: Option<u32>,
+    disabled: bool,
+    ndev: Option<u32>,
+    timebase: Option<u64>,
+    bootargs: Option<&'a str>,
+    stdout_path: Option<&'a str>,
+    address_cells: usize,
+    size_cells: usize,
+}
+
+impl<'a> NodeState<'a> {
+    const fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            compatible: &[],
+            device_type: &[],
+            reg: &[],
+            interrupts: None,
+            disabled: false,
+            ndev: None,
+            timebase: None,
+            bootargs: None,
+            stdout_path: None,
+            // Devicetree defaults for nodes without #address-cells/#size-cells
+            address_cells: 2,
+            size_cells: 1,
+        }
+    }
+
+    fn is_compatible(&self, names: &[&str]) -> bool {
+        names
+            .iter()
+            .any(|name| fdt::prop_contains(self.compatible, name))
+    }
+}
+
+/// Build boot information from a devicetree
+fn parse(fdt: &Fdt<'static>, dtb_addr: usize) -> Result<BootInfo, FdtError> {
+    let mut info = BootInfo::defaults();
+    info.source = BootInfoSource::DeviceTree;
+    info.dtb_addr = dtb_addr;
+    info.dtb_size = fdt.total_size();
+    info.memory_count = 0;
+    info.hart_mask = 0;
+
+    let mut found_uart = false;
+    let mut stack = [NodeState::new(""); MAX_DEPTH];
+    let mut level = 0usize;
+
+    fdt.walk(|event| match event {
+        FdtEvent::BeginNode(name) => {
+            if level < MAX_DEPTH {
+                stack[level] = NodeState::new(name);
+            }
+            level += 1;
+        }
+        FdtEvent::Property(name, value) => {
+            if level == 0 || level > MAX_DEPTH {
+                return;
+            }
+            let node = &mut stack[level - 1];
+            match name {
+                "compatible" => node.compatible = value,
+                "device_type" => node.device_type = value,
+                "reg" => node.reg = value,
+                "status" => node.disabled = fdt::prop_str(value) == Some("disabled"),
+                "interrupts" => node.interrupts = fdt::be32(value, 0),
+                "riscv,ndev" => node.ndev = fdt::be32(value, 0),
+                "timebase-frequency" => node.timebase = fdt::prop_number(value),
+                "bootargs" => node.bootargs = fdt::prop_str(value),
+                "stdout-path" => node.stdout_path = fdt::prop_str(value),
+                "#address-cells" => node.address_cells = fdt::be32(value, 0).unwrap_or(2) as usize,
+                "#size-cells" => node.size_cells = fdt::be32(value, 0).unwrap_or(1) as usize,
+                _ => {}
+            }
+        }
+        FdtEvent::EndNode => {
+            if level == 0 {
+                return;
+            }
+            level -= 1;
+            if level == 0 || level >= MAX_DEPTH {
+                return;
+            }
+
+            let node = stack[level];
+            let parent = stack[level - 1];
+            let reg = |index: usize| {
+                let cells = parent.address_cells + parent.size_cells;
+                let base = fdt::read_cells(node.reg, index * cells, parent.address_cells)?;
+                let size = fdt::read_cells(
+                    node.reg,
+                    index * cells + parent.address_cells,
+                    parent.size_cells,
+                )?;
+                Some((base as usize, size as usize))
+            };
+
+            if node.disabled {
+                return;
+            }
+
+            if level == 1 && node.name == "chosen" {
+                info.bootargs = node.bootargs;
+                info.stdout_path = node.stdout_path;
+            } else if level == 1 && node.name == "cpus" {
+                if let Some(freq) = node.timebase {
+                    info.timebase_frequency = freq;
+                }
+            } else if fdt::prop_str(node.device_type) == Some("cpu") {
+                // cpu@N: reg is the hart ID
+                if let Some(hart) = fdt::read_cells(node.reg, 0, parent.address_cells) {
+                    if hart < usize::BITS as u64 {
+                        info.hart_mask |= 1 << hart;
+                    }
+                }
+                if let Some(freq) = node.timebase {
+                    info.timebase_frequency = freq;
+                }
+            } else if fdt::prop_str(node.device_type) == Some("memory") {
+                let mut index = 0;
+                while let Some((base, size)) = reg(index) {
+                    if info.memory_count < MAX_MEMORY_REGIONS && size > 0 {
+                        info.memory[info.memory_count] = MemoryRegion { base, size };
+                        info.memory_count += 1;
+                    }
+                    index += 1;
+                }
+            } else if !found_uart && node.is_compatible(&["ns16550a", "ns16550"]) {
+                if let Some((base, size)) = reg(0) {
+                    info.uart = MmioDevice {
+                        base,
+                        size,
+                        irq: node.interrupts,
+                    };
+                    found_uart = true;
+                }
+            } else if node.is_compatible(&["riscv,clint0", "sifive,clint0"]) {
+                if let Some((base, size)) = reg(0) {
+                    info.clint = MmioDevice {
+                        base,
+                        size,
+                        irq: None,
+                    };
+                }
+            } else if node.is_compatible(&["riscv,plic0", "sifive,plic-1.0.0"]) {
+                if let Some((base, size)) = reg(0) {
+                    info.plic = MmioDevice {
+                        base,
+                        size,
+                        irq: None,
+                    };
+                }
+                if let Some(ndev) = node.ndev {
+                    info.plic_ndev = ndev;
+                }
+            }
+        }
+    })?;
+
+    // Keep usable defaults for anything the tree did not describe
+    if info.memory_count == 0 {
+        info.memory = BootInfo::defaults().memory;
+        info.memory_count = 1;
+    }
+    if info.hart_mask == 0 {
+        info.hart_mask = 1;
+    }
+
+    Ok(info)
+}
+
+/// Get the start address of the primary RAM region
+pub fn ram_start() -> usize {
+    boot_info().memory[0].base
+}
+
+/// Get the end address (exclusive) of the primary RAM region
+pub fn ram_end() -> usize {
+    boot_info().memory[0].end()
+}
+
+/// Check if an address lies in any RAM region
+pub fn is_ram(addr: usize) -> bool {
+    boot_info()
+        .memory_regions()
+        .iter()
+        .any(|region| region.contains(addr))
+}
+
+/// Get the console UART base address
+pub fn uart_base() -> usize {
+    boot_info().uart.base
+}
+
+/// Get the CLINT base address
+pub fn clint_base() -> usize {
+    boot_info().clint.base
+}
+
+/// Get the PLIC base address
+pub fn plic_base() -> usize {
+    boot_info().plic.base
+}
+
+/// Get the timer frequency in Hz
+pub fn timebase_frequency() -> u64 {
+    boot_info().timebase_frequency
+}
+
+/// Print the discovered platform description
+pub fn show_boot_info() {
+    let info = boot_info();
+
+    crate::println!("=== PLATFORM DESCRIPTION ===");
+    match info.source {
+        BootInfoSource::DeviceTree => {
+            crate::println!(
+                "Source: devicetree at {} ({} bytes)",
+                hex(info.dtb_addr),
+                num(info.dtb_size as u64)
+            );
+        }
+        BootInfoSource::Defaults => crate::println!("Source: built-in QEMU virt defaults"),
+    }
+
+    crate::println!("Memory regions:");
+    for region in info.memory_regions() {
+        crate::println!(
+            "  {} - {} ({} MB)",
+            hex(region.base),
+            hex(region.end()),
+            num((region.size / (1024 * 1024)) as u64)
+        );
+    }
+
+    crate::println!("Devices:");
+    crate::println!("  UART:  {}", hex(info.uart.base));
+    crate::println!("  CLINT: {}", hex(info.clint.base));
+    crate::println!(
+        "  PLIC:  {} ({} sources)",
+        hex(info.plic.base),
+        num(info.plic_ndev as u64)
+    );
+    crate::println!("Timebase: {} Hz", num(info.timebase_frequency));
+    crate::println!(
+        "Harts: {} (mask {})",
+        num(info.hart_count() as u64),
+        hex(info.hart_mask)
+    );
+
+    if let Some(bootargs) = info.bootargs {
+        crate::print!("Bootargs: ");
+        crate::println!(bootargs);
+    }
+    if let Some(stdout_path) = info.stdout_path {
+        crate::print!("Stdout:   ");
+        crate::println!(stdout_path);
+    }
+}
@@ -0,0 +1,324 @@
+// src/arch/riscv64/plic.rs
+//! RISC-V Platform-Level Interrupt Controller (PLIC) Driver
+//!
+//! The PLIC routes device interrupts (UART, virtio, ...) to hart contexts.
+//! Each source has a priority (0 = never interrupts), each context has an
+//! enable bit per source and a priority threshold, and the highest-priority
+//! pending source is claimed and later completed through the context's
+//! claim/complete register.
+//!
+//! On QEMU virt every hart has two contexts: `2 * hart` for M-mode and
+//! `2 * hart + 1` for S-mode. The kernel uses the one matching the privilege
+//! level it runs at. The base address and source count come from the
+//! platform description.
+
+use super::{csr, platform, RiscvError, MAX_HARTS};
+use crate::arch::InterruptController;
+use crate::console::{hex, num};
+
+/// Offset of the per-source priority registers
+const PRIORITY_OFFSET: usize = 0x0;
+
+/// Offset of the pending bit array
+const PENDING_OFFSET: usize = 0x1000;
+
+/// Offset of the per-context enable bit arrays
+const ENABLE_OFFSET: usize = 0x2000;
+
+/// Stride between two contexts' enable arrays
+const ENABLE_STRIDE: usize = 0x80;
+
+/// Offset of the per-context threshold and claim/complete registers
+const CONTEXT_OFFSET: usize = 0x20_0000;
+
+/// Stride between two contexts' threshold registers
+const CONTEXT_STRIDE: usize = 0x1000;
+
+/// Offset of the claim/complete register within a context
+const CLAIM_OFFSET: usize = 0x4;
+
+/// Highest priority supported by the QEMU virt PLIC
+pub const MAX_PRIORITY: u32 = 7;
+
+/// PLIC context of a hart for the kernel's privilege level
+#[cfg(not(feature = "smode"))]
+pub const fn context(hart: usize) -> usize {
+    hart * 2
+}
+
+/// PLIC context of a hart for the kernel's privilege level
+#[cfg(feature = "smode")]
+pub const fn context(hart: usize) -> usize {
+    hart * 2 + 1
+}
+
+/// RISC-V PLIC implementation
+///
+/// All register addresses are derived from the discovered platform PLIC;
+/// claim and complete always use the calling hart's context.
+pub struct Plic;
+
+impl Plic {
+    /// Create a new PLIC instance
+    pub const fn new() -> Self {
+        Self
+    }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (platform::plic_base() + offset) as *mut u32
+    }
+
+    /// Number of interrupt sources (source 0 is reserved)
+    pub fn source_count(&self) -> u32 {
+        platform::boot_info().plic_ndev
+    }
+
+    /// Check a source number
+    fn check_source(&self, source: u32) -> Result<(), RiscvError> {
+        if source == 0 || source > self.source_count() {
+            Err(RiscvError::InvalidAddress)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check a hart ID
+    fn check_hart(&self, hart: usize) -> Result<(), RiscvError> {
+        if hart >= MAX_HARTS {
+            Err(RiscvError::InvalidAddress)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn enable_reg(&self, hart: usize, source: u32) -> (*mut u32, u32) {
+        let offset = ENABLE_OFFSET + context(hart) * ENABLE_STRIDE + (source as usize / 32) * 4;
+        (self.reg(offset), 1 << (source % 32))
+    }
+
+    fn threshold_reg(&self, hart: usize) -> *mut u32 {
+        self.reg(CONTEXT_OFFSET + context(hart) * CONTEXT_STRIDE)
+    }
+
+    fn claim_reg(&self, hart: usize) -> *mut u32 {
+        self.reg(CONTEXT_OFFSET + context(hart) * CONTEXT_STRIDE + CLAIM_OFFSET)
+    }
+
+    /// Set the priority of a source
+    ///
+    /// # Arguments
+    /// * `source` - Interrupt source number
+    /// * `priority` - 0 (disabled) to [`MAX_PRIORITY`]
+    ///
+    /// # Returns
+    /// `Ok(())` on success, `Err(RiscvError::InvalidAddress)` for an unknown
+    /// source or out-of-range priority
+    pub fn set_priority(&self, source: u32, priority: u32) -> Result<(), RiscvError> {
+        self.check_source(source)?;
+        if priority > MAX_PRIORITY {
+            return Err(RiscvError::InvalidAddress);
+        }
+
+        unsafe {
+            core::ptr::write_volatile(self.reg(PRIORITY_OFFSET + source as usize * 4), priority);
+        }
+        Ok(())
+    }
+
+    /// Get the priority of a source (0 for an unknown source)
+    pub fn priority(&self, source: u32) -> u32 {
+        if self.check_source(source).is_err() {
+            return 0;
+        }
+        unsafe { core::ptr::read_volatile(self.reg(PRIORITY_OFFSET + source as usize * 4)) }
+    }
+
+    /// Check whether a source is pending
+    pub fn is_pending(&self, source: u32) -> bool {
+        if self.check_source(source).is_err() {
+            return false;
+        }
+        let word = unsafe {
+            core::ptr::read_volatile(self.reg(PENDING_OFFSET + (source as usize / 32) * 4))
+        };
+        word & (1 << (source % 32)) != 0
+    }
+
+    /// Route a source to a hart
+    ///
+    /// # Returns
+    /// `Ok(())` on success, `Err(RiscvError::InvalidAddress)` for an unknown
+    /// source or hart
+    pub fn enable_source(&self, hart: usize, source: u32) -> Result<(), RiscvError> {
+        self.check_source(source)?;
+        self.check_hart(hart)?;
+
+        let (reg, bit) = self.enable_reg(hart, source);
+        unsafe {
+            core::ptr::write_volatile(reg, core::ptr::read_volatile(reg) | bit);
+        }
+        Ok(())
+    }
+
+    /// Stop routing a source to a hart
+    ///
+    /// # Returns
+    /// `Ok(())` on success, `Err(RiscvError::InvalidAddress)` for an unknown
+    /// source or hart
+    pub fn disable_source(&self, hart: usize, source: u32) -> Result<(), RiscvError> {
+        self.check_source(source)?;
+        self.check_hart(hart)?;
+
+        let (reg, bit) = self.enable_reg(hart, source);
+        unsafe {
+            core::ptr::write_volatile(reg, core::ptr::read_volatile(reg) & !bit);
+        }
+        Ok(())
+    }
+
+    /// Check whether a source is routed to a hart
+    pub fn is_source_enabled(&self, hart: usize, source: u32) -> bool {
+        if self.check_source(source).is_err() || self.check_hart(hart).is_err() {
+            return false;
+        }
+        let (reg, bit) = self.enable_reg(hart, source);
+        unsafe { core::ptr::read_volatile(reg) & bit != 0 }
+    }
+
+    /// Set a hart's priority threshold
+    ///
+    /// Only sources with a priority above the threshold interrupt the hart.
+    pub fn set_threshold(&self, hart: usize, threshold: u32) -> Result<(), RiscvError> {
+        self.check_hart(hart)?;
+        if threshold > MAX_PRIORITY {
+            return Err(RiscvError::InvalidAddress);
+        }
+
+        unsafe {
+            core::ptr::write_volatile(self.threshold_reg(hart), threshold);
+        }
+        Ok(())
+    }
+
+    /// Get a hart's priority threshold
+    pub fn threshold(&self, hart: usize) -> u32 {
+        if self.check_hart(hart).is_err() {
+            return 0;
+        }
+        unsafe { core::ptr::read_volatile(self.threshold_reg(hart)) }
+    }
+
+    /// Claim the highest-priority pending source for the calling hart
+    ///
+    /// # Returns
+    /// The claimed source, or `None` if nothing is pending (or another hart
+    /// claimed it first)
+    pub fn claim(&self) -> Option<u32> {
+        let hart = csr::read_mhartid() as usize;
+        self.check_hart(hart).ok()?;
+
+        let source = unsafe { core::ptr::read_volatile(self.claim_reg(hart)) };
+        (source != 0).then_some(source)
+    }
+
+    /// Signal that the calling hart finished handling a claimed source
+    pub fn complete(&self, source: u32) {
+        let hart = csr::read_mhartid() as usize;
+        if self.check_hart(hart).is_err() {
+            return;
+        }
+        unsafe {
+            core::ptr::write_volatile(self.claim_reg(hart), source);
+        }
+    }
+
+    /// Reset all source priorities and prepare the calling hart's context
+    ///
+    /// # Safety
+    /// Must run before any driver configures the PLIC; sources configured
+    /// earlier are silenced.
+    pub unsafe fn init(&self) {
+        for source in 1..=self.source_count() {
+            let _ = self.set_priority(source, 0);
+        }
+        self.init_hart(csr::read_mhartid() as usize);
+    }
+
+    /// Disable every source for a hart and accept all priorities
+    pub fn init_hart(&self, hart: usize) {
+        if self.check_hart(hart).is_err() {
+            return;
+        }
+
+        let words = self.source_count() as usize / 32 + 1;
+        for word in 0..words {
+            let offset = ENABLE_OFFSET + context(hart) * ENABLE_STRIDE + word * 4;
+            unsafe {
+                core::ptr::write_volatile(self.reg(offset), 0);
+            }
+        }
+        let _ = self.set_threshold(hart, 0);
+    }
+
+    /// Print the configured sources and the calling hart's context
+    pub fn show_status(&self) {
+        let hart = csr::read_mhartid() as usize;
+
+        crate::println!("=== PLIC Status ===");
+        crate::println!("Base: {}", hex(platform::plic_base()));
+        crate::println!("Sources: {}", num(self.source_count() as u64));
+        crate::println!(
+            "Hart {} context {}: threshold {}",
+            num(hart as u64),
+            num(context(hart) as u64),
+            num(self.threshold(hart) as u64)
+        );
+
+        for source in 1..=self.source_count() {
+            let priority = self.priority(source);
+            if priority == 0 && !self.is_source_enabled(hart, source) {
+                continue;
+            }
+            crate::print!(
+                "  Source {}: priority {}",
+                num(source as u64),
+                num(priority as u64)
+            );
+            if self.is_source_enabled(hart, source) {
+                crate::print!(" enabled");
+            }
+            if self.is_pending(source) {
+                crate::print!(" pending");
+            }
+            crate::println!();
+        }
+    }
+}
+
+impl InterruptController for Plic {
+    type Error = RiscvError;
+
+    /// Enable external interrupts on the calling hart (MEIE / SEIE)
+    unsafe fn enable(&self) -> Result<(), Self::Error> {
+        csr::enable_machine_external_interrupt()
+    }
+
+    /// Disable external interrupts on the calling hart
+    unsafe fn disable(&self) -> Result<(), Self::Error> {
+        csr::write_mie(csr::read_mie() & !csr::bits::IE_EXT);
+
+        if csr::read_mie() & csr::bits::IE_EXT == 0 {
+            Ok(())
+        } else {
+            Err(RiscvError::HardwareFault)
+        }
+    }
+
+    /// Check whether external interrupts are enabled on the calling hart
+    fn is_enabled(&self) -> bool {
+        csr::read_mie() & csr::bits::IE_EXT != 0
+    }
+}
+
+/// Global PLIC instance
+pub static PLIC: Plic = Plic::new();
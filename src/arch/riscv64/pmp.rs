@@ -0,0 +1,448 @@
+// src/arch/riscv64/pmp.rs
+//! RISC-V Physical Memory Protection (PMP)
+//!
+//! This module programs the per-hart `pmpcfg`/`pmpaddr` CSRs. Each entry
+//! describes a region by top-of-range (TOR) or naturally aligned
+//! power-of-two (NAPOT) address matching, and the lowest-numbered matching
+//! entry decides the R/W/X permissions of an access.
+//!
+//! Entries only constrain M-mode when they are locked, and a locked entry
+//! cannot be changed again until reset. The kernel therefore builds its
+//! layout once per hart with [`protect_kernel`]: text R+X, rodata R,
+//! data/bss/stacks R+W without execute, and a no-access guard page at the
+//! bottom of every hart stack. Memory outside these regions (the frame
+//! allocator's RAM, MMIO) stays unrestricted.
+//!
+//! PMP is owned by the SBI firmware in S-mode, so this module is only built
+//! for M-mode kernels.
+
+use super::csr::bits;
+use super::MAX_HARTS;
+use crate::console::{hex, num, str};
+use crate::memory;
+
+/// Number of PMP entries implemented on QEMU virt
+pub const PMP_ENTRIES: usize = 16;
+
+/// Smallest NAPOT region in bytes
+const NAPOT_MIN: usize = 8;
+
+/// Read permission
+pub const PMP_R: u8 = 1 << 0;
+
+/// Write permission
+pub const PMP_W: u8 = 1 << 1;
+
+/// Execute permission
+pub const PMP_X: u8 = 1 << 2;
+
+/// Lock bit: the entry also applies to M-mode and becomes read-only
+pub const PMP_L: u8 = 1 << 7;
+
+/// Shift of the address-matching (A) field in a `pmpcfg` byte
+const A_SHIFT: u8 = 3;
+
+/// Address-matching mode of a PMP entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PmpMode {
+    /// Entry disabled
+    Off = 0,
+
+    /// Top of range: matches `pmpaddr[i-1] <= addr < pmpaddr[i]`
+    Tor = 1,
+
+    /// Naturally aligned four-byte region
+    Na4 = 2,
+
+    /// Naturally aligned power-of-two region (8 bytes or more)
+    Napot = 3,
+}
+
+impl PmpMode {
+    fn from_cfg(cfg: u8) -> Self {
+        match (cfg >> A_SHIFT) & 3 {
+            0 => PmpMode::Off,
+            1 => PmpMode::Tor,
+            2 => PmpMode::Na4,
+            _ => PmpMode::Napot,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            PmpMode::Off => "OFF",
+            PmpMode::Tor => "TOR",
+            PmpMode::Na4 => "NA4",
+            PmpMode::Napot => "NAPOT",
+        }
+    }
+}
+
+/// PMP configuration errors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PmpError {
+    /// All PMP entries are in use
+    NoFreeEntry,
+
+    /// Region bounds are not 4-byte aligned (TOR) or not naturally aligned (NAPOT)
+    Misaligned,
+
+    /// NAPOT size is not a power of two of at least 8 bytes
+    InvalidSize,
+
+    /// The entry is locked with a different configuration
+    Locked,
+}
+
+impl PmpError {
+    /// Get a short description of the error
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PmpError::NoFreeEntry => "No free PMP entry",
+            PmpError::Misaligned => "PMP region misaligned",
+            PmpError::InvalidSize => "Invalid PMP region size",
+            PmpError::Locked => "PMP entry locked",
+        }
+    }
+}
+
+impl core::fmt::Display for PmpError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Expand a runtime entry index into the matching literal CSR access
+macro_rules! pmpaddr_access {
+    (@read $n:literal, $val:expr) => {
+        core::arch::asm!(concat!("csrr {}, pmpaddr", stringify!($n)), out(reg) $val)
+    };
+    (@write $n:literal, $val:expr) => {
+        core::arch::asm!(concat!("csrw pmpaddr", stringify!($n), ", {}"), in(reg) $val)
+    };
+    ($index:expr, $op:ident, $val:expr, [$($n:literal),*]) => {
+        match $index {
+            $($n => pmpaddr_access!(@$op $n, $val),)*
+            _ => unreachable!(),
+        }
+    };
+}
+
+/// Read a `pmpaddr` register
+fn read_pmpaddr(index: usize) -> usize {
+    let val: usize;
+    unsafe {
+        pmpaddr_access!(
+            index,
+            read,
+            val,
+            [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+        );
+    }
+    val
+}
+
+/// Write a `pmpaddr` register
+unsafe fn write_pmpaddr(index: usize, val: usize) {
+    pmpaddr_access!(
+        index,
+        write,
+        val,
+        [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+    );
+}
+
+/// Read the `pmpcfg` register holding entry `index` (RV64: pmpcfg0/pmpcfg2)
+fn read_pmpcfg(index: usize) -> usize {
+    let val: usize;
+    unsafe {
+        if index < 8 {
+            core::arch::asm!("csrr {}, pmpcfg0", out(reg) val);
+        } else {
+            core::arch::asm!("csrr {}, pmpcfg2", out(reg) val);
+        }
+    }
+    val
+}
+
+/// Write the `pmpcfg` register holding entry `index`
+unsafe fn write_pmpcfg(index: usize, val: usize) {
+    if index < 8 {
+        core::arch::asm!("csrw pmpcfg0, {}", in(reg) val);
+    } else {
+        core::arch::asm!("csrw pmpcfg2, {}", in(reg) val);
+    }
+}
+
+/// Read the configuration byte of an entry
+pub fn read_cfg(index: usize) -> u8 {
+    (read_pmpcfg(index) >> ((index % 8) * 8)) as u8
+}
+
+/// Program a single PMP entry
+///
+/// # Arguments
+/// * `index` - Entry number (0..`PMP_ENTRIES`)
+/// * `addr` - Raw `pmpaddr` value (physical address >> 2, NAPOT-encoded if needed)
+/// * `cfg` - Configuration byte (permissions, mode and lock)
+///
+/// # Returns
+/// `Err(PmpError::Locked)` if the entry is locked with a different setting
+///
+/// # Safety
+/// Locked entries restrict M-mode immediately and cannot be undone.
+pub unsafe fn write_entry(index: usize, addr: usize, cfg: u8) -> Result<(), PmpError> {
+    if index >= PMP_ENTRIES {
+        return Err(PmpError::NoFreeEntry);
+    }
+
+    let current = read_cfg(index);
+    if current & PMP_L != 0 {
+        // Locked entries are left as they are; identical settings are fine
+        // (e.g. after a soft reset)
+        return if current == cfg && read_pmpaddr(index) == addr {
+            Ok(())
+        } else {
+            Err(PmpError::Locked)
+        };
+    }
+
+    // Disable the entry while its address changes, then enable it
+    let shift = (index % 8) * 8;
+    let cleared = read_pmpcfg(index) & !(0xff << shift);
+    write_pmpcfg(index, cleared);
+    write_pmpaddr(index, addr);
+    write_pmpcfg(index, cleared | ((cfg as usize) << shift));
+    Ok(())
+}
+
+/// Encode a NAPOT region as a `pmpaddr` value
+///
+/// # Arguments
+/// * `base` - Region base (aligned to `size`)
+/// * `size` - Region size (power of two, at least 8 bytes)
+pub fn encode_napot(base: usize, size: usize) -> Result<usize, PmpError> {
+    if size < NAPOT_MIN || !size.is_power_of_two() {
+        return Err(PmpError::InvalidSize);
+    }
+    if base % size != 0 {
+        return Err(PmpError::Misaligned);
+    }
+    Ok((base | (size / 2 - 1)) >> 2)
+}
+
+/// Decode the address range matched by an entry
+///
+/// # Returns
+/// `(start, end, mode)`; `start == end` for disabled entries
+pub fn decode(index: usize) -> (usize, usize, PmpMode) {
+    let cfg = read_cfg(index);
+    let addr = read_pmpaddr(index);
+    let mode = PmpMode::from_cfg(cfg);
+
+    match mode {
+        PmpMode::Off => (0, 0, mode),
+        PmpMode::Tor => {
+            let start = if index == 0 {
+                0
+            } else {
+                read_pmpaddr(index - 1) << 2
+            };
+            (start, addr << 2, mode)
+        }
+        PmpMode::Na4 => (addr << 2, (addr << 2) + 4, mode),
+        PmpMode::Napot => {
+            // Trailing ones encode the size: size = 2^(ones + 3)
+            let ones = addr.trailing_ones() as usize;
+            let size = 1usize << (ones + 3);
+            let start = (addr & !((1 << ones) - 1)) << 2;
+            (start, start + size, mode)
+        }
+    }
+}
+
+/// Find the entry that decides accesses to an address
+///
+/// # Returns
+/// The lowest-numbered matching entry, or `None` if no entry matches
+pub fn find_entry(addr: usize) -> Option<usize> {
+    (0..PMP_ENTRIES).find(|&index| {
+        let (start, end, mode) = decode(index);
+        mode != PmpMode::Off && addr >= start && addr < end
+    })
+}
+
+/// Builder that allocates consecutive PMP entries
+pub struct PmpConfig {
+    next: usize,
+    /// End address of the previous entry, reused as a TOR base
+    last_end: Option<usize>,
+}
+
+impl PmpConfig {
+    /// Start allocating at entry 0
+    pub const fn new() -> Self {
+        Self {
+            next: 0,
+            last_end: None,
+        }
+    }
+
+    /// Number of entries used so far
+    pub fn used(&self) -> usize {
+        self.next
+    }
+
+    fn take(&mut self) -> Result<usize, PmpError> {
+        if self.next >= PMP_ENTRIES {
+            return Err(PmpError::NoFreeEntry);
+        }
+        self.next += 1;
+        Ok(self.next - 1)
+    }
+
+    /// Add a TOR region `[start, end)`
+    ///
+    /// An extra disabled entry holds `start` unless the previous entry
+    /// already ends there.
+    ///
+    /// # Safety
+    /// See [`write_entry`].
+    pub unsafe fn tor(&mut self, start: usize, end: usize, perms: u8) -> Result<(), PmpError> {
+        if start % 4 != 0 || end % 4 != 0 || end < start {
+            return Err(PmpError::Misaligned);
+        }
+
+        if self.last_end != Some(start) && !(self.next == 0 && start == 0) {
+            let base = self.take()?;
+            // Lock the base entry along with the region so it cannot move
+            write_entry(base, start >> 2, perms & PMP_L)?;
+        }
+        let index = self.take()?;
+        write_entry(index, end >> 2, perms | ((PmpMode::Tor as u8) << A_SHIFT))?;
+        self.last_end = Some(end);
+        Ok(())
+    }
+
+    /// Add a NAPOT region
+    ///
+    /// # Safety
+    /// See [`write_entry`].
+    pub unsafe fn napot(&mut self, base: usize, size: usize, perms: u8) -> Result<(), PmpError> {
+        let addr = encode_napot(base, size)?;
+        let index = self.take()?;
+        write_entry(index, addr, perms | ((PmpMode::Napot as u8) << A_SHIFT))?;
+        self.last_end = Some(base + size);
+        Ok(())
+    }
+}
+
+/// Apply the kernel memory protection layout on the calling hart
+///
+/// Every hart has its own PMP registers, so this runs on the primary hart
+/// during boot and on each secondary hart as it comes online.
+///
+/// # Returns
+/// The number of entries used
+pub fn protect_kernel() -> Result<usize, PmpError> {
+    let page_up = |addr: usize| addr.next_multiple_of(super::PAGE_SIZE);
+    let layout = memory::layout();
+    let text_start = layout.text.start;
+    let rodata_start = layout.rodata.start;
+    let rodata_end = page_up(layout.rodata.end);
+    let data_start = layout.data.start;
+    let stacks_end = layout.stacks.end;
+
+    let mut config = PmpConfig::new();
+    unsafe {
+        // Guards first: the lowest-numbered match wins over the stack region
+        for hart in 0..MAX_HARTS {
+            let (guard, size) = memory::hart_stack_guard(hart);
+            config.napot(guard, size, PMP_L)?;
+        }
+
+        // Text R+X, rodata R (link.ld page-aligns .rodata and .data)
+        config.tor(text_start, rodata_start, PMP_R | PMP_X | PMP_L)?;
+        config.tor(rodata_start, rodata_end, PMP_R | PMP_L)?;
+
+        // .data, .bss and the hart stacks: R+W, never executable
+        config.tor(data_start, stacks_end, PMP_R | PMP_W | PMP_L)?;
+    }
+
+    Ok(config.used())
+}
+
+/// Describe the permissions of a configuration byte (e.g. "R-X L")
+fn perms_str(cfg: u8) -> &'static str {
+    const NAMES: [&str; 16] = [
+        "---", "R--", "-W-", "RW-", "--X", "R-X", "-WX", "RWX", "--- L", "R-- L", "-W- L", "RW- L",
+        "--X L", "R-X L", "-WX L", "RWX L",
+    ];
+    NAMES[((cfg & 7) | ((cfg & PMP_L) >> 4)) as usize]
+}
+
+/// Print all active PMP entries of the calling hart
+pub fn show_config() {
+    crate::println!("=== PMP CONFIGURATION ===");
+    for index in 0..PMP_ENTRIES {
+        let cfg = read_cfg(index);
+        let (start, end, mode) = decode(index);
+        if mode == PmpMode::Off {
+            continue;
+        }
+        crate::println!(
+            "  pmp{}: {} {} - {} {}",
+            num(index as u64),
+            str(mode.as_str()),
+            hex(start),
+            hex(end),
+            str(perms_str(cfg))
+        );
+    }
+}
+
+/// Explain an access fault in terms of the PMP configuration
+///
+/// # Arguments
+/// * `code` - Exception code (instruction, load or store access fault)
+/// * `mepc` - Faulting instruction address
+/// * `mtval` - Faulting data or instruction address
+pub fn report_fault(code: usize, mepc: usize, mtval: usize) {
+    let access = match code {
+        bits::EXCEPTION_INSTR_ACCESS_FAULT => "execute",
+        bits::EXCEPTION_LOAD_ACCESS_FAULT => "read",
+        _ => "write",
+    };
+
+    crate::println!("=== PMP ACCESS FAULT ===");
+    crate::println!("Access:  {} of {}", str(access), hex(mtval));
+    crate::println!("PC:      {}", hex(mepc));
+
+    match find_entry(mtval) {
+        Some(index) => {
+            let cfg = read_cfg(index);
+            let (start, end, _) = decode(index);
+            crate::println!(
+                "Region:  pmp{} {} - {} ({})",
+                num(index as u64),
+                hex(start),
+                hex(end),
+                str(perms_str(cfg))
+            );
+
+            let guard = (0..MAX_HARTS).find(|&hart| {
+                let (base, size) = memory::hart_stack_guard(hart);
+                mtval >= base && mtval < base + size
+            });
+            if let Some(hart) = guard {
+                crate::println!(
+                    "Cause:   stack guard of hart {} hit (stack overflow)",
+                    num(hart as u64)
+                );
+            } else {
+                crate::println!("Cause:   {} not permitted by this region", str(access));
+            }
+        }
+        None => crate::println!("Cause:   no PMP entry matches (bus error or unmapped device)"),
+    }
+}
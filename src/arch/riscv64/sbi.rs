@@ -0,0 +1,196 @@
+// src/arch/riscv64/sbi.rs
+//! RISC-V Supervisor Binary Interface (SBI) Calls
+//!
+//! This module is only built for S-mode kernels (`smode` feature). It wraps
+//! the SBI v0.2+ calling convention used to reach M-mode firmware such as
+//! OpenSBI: the extension ID goes in `a7`, the function ID in `a6`, and the
+//! firmware returns an error code in `a0` and a value in `a1`.
+
+use super::RiscvError;
+
+/// Base extension
+const EXT_BASE: usize = 0x10;
+
+/// Timer extension ("TIME")
+const EXT_TIME: usize = 0x5449_4D45;
+
+/// IPI extension ("sPI")
+const EXT_IPI: usize = 0x0073_5049;
+
+/// Hart State Management extension ("HSM")
+const EXT_HSM: usize = 0x0048_534D;
+
+/// SBI error codes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SbiError {
+    /// Generic failure
+    Failed,
+
+    /// The extension or function is not implemented
+    NotSupported,
+
+    /// A parameter is invalid
+    InvalidParam,
+
+    /// The request was denied
+    Denied,
+
+    /// An address parameter is invalid
+    InvalidAddress,
+
+    /// The target is already available (e.g. hart already started)
+    AlreadyAvailable,
+
+    /// Any other error code
+    Unknown(isize),
+}
+
+impl SbiError {
+    fn from_code(code: isize) -> Self {
+        match code {
+            -1 => SbiError::Failed,
+            -2 => SbiError::NotSupported,
+            -3 => SbiError::InvalidParam,
+            -4 => SbiError::Denied,
+            -5 => SbiError::InvalidAddress,
+            -6 => SbiError::AlreadyAvailable,
+            other => SbiError::Unknown(other),
+        }
+    }
+}
+
+impl core::fmt::Display for SbiError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            SbiError::Failed => write!(f, "SBI call failed"),
+            SbiError::NotSupported => write!(f, "SBI call not supported"),
+            SbiError::InvalidParam => write!(f, "Invalid SBI parameter"),
+            SbiError::Denied => write!(f, "SBI call denied"),
+            SbiError::InvalidAddress => write!(f, "Invalid SBI address"),
+            SbiError::AlreadyAvailable => write!(f, "SBI target already available"),
+            SbiError::Unknown(code) => write!(f, "Unknown SBI error {}", code),
+        }
+    }
+}
+
+impl From<SbiError> for RiscvError {
+    fn from(error: SbiError) -> Self {
+        match error {
+            SbiError::InvalidParam | SbiError::InvalidAddress => RiscvError::InvalidAddress,
+            SbiError::Denied => RiscvError::InvalidPrivilege,
+            _ => RiscvError::HardwareFault,
+        }
+    }
+}
+
+/// Perform an SBI call
+///
+/// # Arguments
+/// * `ext` - Extension ID (`a7`)
+/// * `fid` - Function ID (`a6`)
+/// * `arg0`..`arg2` - Call arguments (`a0`..`a2`)
+///
+/// # Returns
+/// The value returned in `a1`, or the SBI error from `a0`
+fn call(ext: usize, fid: usize, arg0: usize, arg1: usize, arg2: usize) -> Result<usize, SbiError> {
+    let error: usize;
+    let value: usize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") arg0 => error,
+            inlateout("a1") arg1 => value,
+            in("a2") arg2,
+            in("a6") fid,
+            in("a7") ext,
+        );
+    }
+
+    if error == 0 {
+        Ok(value)
+    } else {
+        Err(SbiError::from_code(error as isize))
+    }
+}
+
+/// Get the implemented SBI specification version
+///
+/// # Returns
+/// The version as `(major << 24) | minor`
+pub fn get_spec_version() -> Result<usize, SbiError> {
+    call(EXT_BASE, 0, 0, 0, 0)
+}
+
+/// Check whether the firmware implements an extension
+///
+/// # Arguments
+/// * `ext` - The extension ID to probe
+///
+/// # Returns
+/// `true` if the extension is available
+pub fn probe_extension(ext: usize) -> bool {
+    matches!(call(EXT_BASE, 3, ext, 0, 0), Ok(value) if value != 0)
+}
+
+/// Program the calling hart's timer
+///
+/// Also clears any pending supervisor timer interrupt.
+///
+/// # Arguments
+/// * `when` - Absolute `time` value at which to raise the interrupt
+pub fn set_timer(when: u64) -> Result<(), SbiError> {
+    call(EXT_TIME, 0, when as usize, 0, 0).map(|_| ())
+}
+
+/// Send a supervisor software interrupt to a set of harts
+///
+/// # Arguments
+/// * `hart_mask` - Bitmask of target harts, relative to `hart_mask_base`
+/// * `hart_mask_base` - Hart ID of bit 0 in `hart_mask`
+pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> Result<(), SbiError> {
+    call(EXT_IPI, 0, hart_mask, hart_mask_base, 0).map(|_| ())
+}
+
+/// Start a stopped hart
+///
+/// The hart begins executing at `start_addr` in S-mode with `a0` = hart ID
+/// and `a1` = `opaque`, with the MMU and interrupts disabled.
+///
+/// # Arguments
+/// * `hart` - The hart to start
+/// * `start_addr` - Physical entry address
+/// * `opaque` - Value passed to the hart in `a1`
+pub fn hart_start(hart: usize, start_addr: usize, opaque: usize) -> Result<(), SbiError> {
+    call(EXT_HSM, 0, hart, start_addr, opaque).map(|_| ())
+}
+
+/// Get the HSM state of a hart
+///
+/// # Returns
+/// 0 = started, 1 = stopped, 2 = start pending, 3 = stop pending, ...
+pub fn hart_get_status(hart: usize) -> Result<usize, SbiError> {
+    call(EXT_HSM, 2, hart, 0, 0)
+}
+
+/// Print SBI firmware information
+pub fn show_info() {
+    crate::println!("=== SBI FIRMWARE ===");
+    match get_spec_version() {
+        Ok(version) => crate::println!(
+            "SBI specification: v{}.{}",
+            crate::console::num((version >> 24) as u64),
+            crate::console::num((version & 0xff_ffff) as u64)
+        ),
+        Err(_) => crate::println!("SBI specification: legacy (v0.1)"),
+    }
+
+    for (name, ext) in [("TIME", EXT_TIME), ("IPI", EXT_IPI), ("HSM", EXT_HSM)] {
+        crate::print!("  ");
+        crate::print!(name);
+        if probe_extension(ext) {
+            crate::println!(": available");
+        } else {
+            crate::println!(": missing");
+        }
+    }
+}
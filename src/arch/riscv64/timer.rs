@@ -4,13 +4,22 @@
 //! This module provides the complete RISC-V timer implementation using the
 //! Core-Local Interruptor (CLINT) for QEMU virt machine. All timer functionality
 //! is consolidated here for clean architecture.
+//!
+//! In S-mode builds (`smode` feature) the CLINT belongs to the SBI firmware:
+//! time is read from the `time` CSR and alarms are programmed with the SBI
+//! TIME extension, behind the same `Timer` interface.
 
-use super::{memory_map, RiscvError};
+use super::{csr, memory_map, platform, RiscvError};
+#[cfg(feature = "smode")]
+use super::{sbi, MAX_HARTS};
 use crate::arch::Timer;
 use crate::console::{hex, num, str};
-use crate::UART0;
+#[cfg(feature = "smode")]
+use core::sync::atomic::{AtomicU64, Ordering};
 
-/// RISC-V timer frequency for QEMU virt machine (10 MHz)
+/// Default RISC-V timer frequency for QEMU virt machine (10 MHz)
+///
+/// Used when the devicetree does not provide `timebase-frequency`.
 pub const TIMER_FREQ: u64 = 10_000_000;
 
 /// Timer duration type (64-bit tick count)
@@ -20,41 +29,48 @@ pub type TimerDuration = u64;
 ///
 /// This structure provides access to the RISC-V Core-Local Interruptor
 /// timer functionality, including MTIME and MTIMECMP registers.
-pub struct ClintTimer {
-    /// Base address of MTIME register
-    mtime_addr: *const u64,
-
-    /// Base address of MTIMECMP register
-    mtimecmp_addr: *mut u64,
-
-    /// Timer frequency in Hz
-    frequency: u64,
-}
-
-// Safety: In a bare-metal single-core environment, sharing raw pointers
-// between threads is not a concern as there are no threads. The hardware
-// registers are memory-mapped and safe to access from the single execution context.
-unsafe impl Sync for ClintTimer {}
+///
+/// Register addresses and the timer frequency are taken from the platform
+/// description at runtime, and MTIMECMP accesses always target the
+/// calling hart's own comparator.
+pub struct ClintTimer;
 
 impl ClintTimer {
     /// Create a new CLINT timer instance
     ///
     /// # Returns
-    /// A new `ClintTimer` instance configured for QEMU virt machine
+    /// A new `ClintTimer` instance using the discovered platform CLINT
     pub const fn new() -> Self {
-        Self {
-            mtime_addr: memory_map::MTIME_ADDR as *const u64,
-            mtimecmp_addr: memory_map::MTIMECMP_BASE as *mut u64,
-            frequency: TIMER_FREQ,
-        }
+        Self
+    }
+
+    /// Get the MTIME register address
+    pub fn mtime_addr(&self) -> *const u64 {
+        (platform::clint_base() + memory_map::MTIME_OFFSET) as *const u64
+    }
+
+    /// Get the calling hart's MTIMECMP register address
+    pub fn mtimecmp_addr(&self) -> *mut u64 {
+        let hart = csr::read_mhartid() as usize;
+        (platform::clint_base() + memory_map::MTIMECMP_OFFSET + hart * 8) as *mut u64
     }
 
     /// Read the MTIME register directly
     ///
     /// # Returns
     /// Current value of the MTIME register
+    #[cfg(not(feature = "smode"))]
     pub fn read_mtime(&self) -> u64 {
-        unsafe { core::ptr::read_volatile(self.mtime_addr) }
+        unsafe { core::ptr::read_volatile(self.mtime_addr()) }
+    }
+
+    /// Read the MTIME register through the `time` CSR
+    ///
+    /// # Returns
+    /// Current value of the MTIME register
+    #[cfg(feature = "smode")]
+    pub fn read_mtime(&self) -> u64 {
+        csr::read_time()
     }
 
     /// Write to the MTIMECMP register directly
@@ -65,16 +81,50 @@ impl ClintTimer {
     /// # Safety
     /// This function is unsafe because writing to MTIMECMP affects
     /// timer interrupt generation.
+    #[cfg(not(feature = "smode"))]
+    pub unsafe fn write_mtimecmp(&self, value: u64) {
+        core::ptr::write_volatile(self.mtimecmp_addr(), value);
+    }
+
+    /// Program the calling hart's comparator through SBI TIME
+    ///
+    /// # Arguments
+    /// * `value` - The value to write to MTIMECMP
+    ///
+    /// # Safety
+    /// This function is unsafe because writing to MTIMECMP affects
+    /// timer interrupt generation.
+    #[cfg(feature = "smode")]
     pub unsafe fn write_mtimecmp(&self, value: u64) {
-        core::ptr::write_volatile(self.mtimecmp_addr, value);
+        let hart = csr::read_mhartid() as usize;
+        if sbi::set_timer(value).is_ok() && hart < MAX_HARTS {
+            SBI_DEADLINE[hart].store(value, Ordering::Release);
+        }
     }
 
     /// Read the MTIMECMP register directly
     ///
     /// # Returns
     /// Current value of the MTIMECMP register
+    #[cfg(not(feature = "smode"))]
+    pub fn read_mtimecmp(&self) -> u64 {
+        unsafe { core::ptr::read_volatile(self.mtimecmp_addr()) }
+    }
+
+    /// Read the last deadline programmed through SBI TIME
+    ///
+    /// MTIMECMP itself is not readable from S-mode.
+    ///
+    /// # Returns
+    /// The calling hart's current deadline
+    #[cfg(feature = "smode")]
     pub fn read_mtimecmp(&self) -> u64 {
-        unsafe { core::ptr::read_volatile(self.mtimecmp_addr) }
+        let hart = csr::read_mhartid() as usize;
+        if hart < MAX_HARTS {
+            SBI_DEADLINE[hart].load(Ordering::Acquire)
+        } else {
+            u64::MAX
+        }
     }
 
     /// Check if the timer is properly accessible
@@ -117,7 +167,7 @@ impl ClintTimer {
 
         // Set MTIMECMP to far future to prevent immediate interrupts
         let current_time = self.read_mtime();
-        let safe_future = current_time + (self.frequency * 3600); // 1 hour from now
+        let safe_future = current_time + (self.frequency() * 3600); // 1 hour from now
 
         crate::println!("Setting timer to safe state...");
         unsafe {
@@ -178,27 +228,31 @@ impl Timer for ClintTimer {
 
     /// Get timer frequency in Hz
     fn frequency(&self) -> u64 {
-        self.frequency
+        platform::timebase_frequency()
     }
 
     /// Convert timer ticks to milliseconds
     fn ticks_to_ms(&self, ticks: Self::Duration) -> u64 {
-        ticks / (self.frequency / 1000)
+        ticks / (self.frequency() / 1000)
     }
 
     /// Convert milliseconds to timer ticks
     fn ms_to_ticks(&self, ms: u64) -> Self::Duration {
-        ms * (self.frequency / 1000)
+        ms * (self.frequency() / 1000)
     }
 }
 
 /// Global CLINT timer instance
 pub static CLINT_TIMER: ClintTimer = ClintTimer::new();
 
+/// Per-hart deadlines programmed through SBI TIME
+#[cfg(feature = "smode")]
+static SBI_DEADLINE: [AtomicU64; MAX_HARTS] = [const { AtomicU64::new(u64::MAX) }; MAX_HARTS];
+
 /// Timer statistics tracking
 #[derive(Debug, Clone, Copy)]
 pub struct TimerStats {
-    /// Number of timer interrupts handled
+    /// Number of timer interrupts handled on all harts (from `trap::stats`)
     pub interrupts: u64,
 
     /// Number of times timer alarm was set
@@ -222,11 +276,6 @@ impl TimerStats {
         }
     }
 
-    /// Record a timer interrupt
-    fn record_interrupt(&mut self) {
-        self.interrupts = self.interrupts.wrapping_add(1);
-    }
-
     /// Record an alarm being set
     fn record_alarm_set(&mut self) {
         self.alarms_set = self.alarms_set.wrapping_add(1);
@@ -248,17 +297,17 @@ static mut TIMER_STATS: TimerStats = TimerStats::new();
 
 /// Get current timer statistics
 pub fn get_timer_stats() -> TimerStats {
-    unsafe { TIMER_STATS }
+    let mut stats = unsafe { TIMER_STATS };
+    stats.interrupts = crate::trap::stats::cause_count(
+        csr::bits::MCAUSE_INTERRUPT_BIT | csr::bits::INTERRUPT_TIMER,
+    );
+    stats
 }
 
 /// Handle timer interrupt (called from trap handler)
 ///
 /// This function processes timer interrupts and sets up the next interrupt.
 pub fn handle_timer_interrupt() {
-    unsafe {
-        TIMER_STATS.record_interrupt();
-    }
-
     // Set next timer interrupt (10 seconds interval)
     let current_time = CLINT_TIMER.now();
     let next_interrupt = current_time + (CLINT_TIMER.frequency() * 10);
@@ -268,25 +317,14 @@ pub fn handle_timer_interrupt() {
             TIMER_STATS.record_error();
         }
     }
+}
 
-    // Simple output for interrupt indication
-    unsafe {
-        let interrupts = TIMER_STATS.interrupts;
-
-        // Output tick marker
-        core::ptr::write_volatile(UART0, b'T');
-        core::ptr::write_volatile(UART0, b'K');
-
-        // Output interrupt count in hex (last digit)
-        let tick_low = interrupts & 0xF;
-        let hex_char = if tick_low < 10 {
-            b'0' + tick_low as u8
-        } else {
-            b'a' + (tick_low - 10) as u8
-        };
-        core::ptr::write_volatile(UART0, hex_char);
-        core::ptr::write_volatile(UART0, b'\n');
-    }
+/// Print the timer tick marker
+///
+/// Called from deferred work after the interrupt has been handled, so it
+/// may use the console like any other kernel code.
+pub fn report_tick() {
+    crate::println!("[TIM] tick {}", num(get_timer_stats().interrupts));
 }
 
 /// Timer utility functions
@@ -353,8 +391,18 @@ pub mod system {
 
         // Hardware information
         crate::println!("Hardware:");
-        crate::println!("  MTIME address: {}", hex(memory_map::MTIME_ADDR));
-        crate::println!("  MTIMECMP address: {}", hex(memory_map::MTIMECMP_BASE));
+        if cfg!(feature = "smode") {
+            crate::println!("  Backend: time CSR + SBI TIME extension");
+        } else {
+            crate::println!(
+                "  MTIME address: {}",
+                hex(CLINT_TIMER.mtime_addr() as usize)
+            );
+            crate::println!(
+                "  MTIMECMP address: {}",
+                hex(CLINT_TIMER.mtimecmp_addr() as usize)
+            );
+        }
         crate::println!("  Frequency: {} Hz", num(CLINT_TIMER.frequency()));
 
         // Current state
@@ -0,0 +1,631 @@
+// src/arch/riscv64/trap.rs
+//! RISC-V Trap Frame
+//!
+//! The trap entry in `asm/trap.s` saves the complete interrupted register
+//! state into a [`TrapFrame`] on the stack and passes it to Rust as
+//! `&mut TrapFrame`. On return the assembly restores every register, `mepc`
+//! and `mstatus` from the frame, so handlers can read syscall arguments,
+//! write return values, and redirect execution by editing the frame.
+//!
+//! In S-mode builds the CSR fields hold `sepc`, `sstatus`, `scause` and
+//! `stval`.
+//!
+//! [`RiscvTrapHandler`] implements the [`TrapHandler`] HAL trait with a
+//! runtime registry of per-cause handlers, so subsystems can hook specific
+//! exception and interrupt codes without editing the kernel trap path.
+
+use super::{csr, RiscvContext, MAX_HARTS};
+use crate::arch::{Address, TrapHandler};
+use crate::console::hex;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// Size of the trap frame in bytes (must match `TRAP_FRAME_SIZE` in `trap.s`)
+pub const TRAP_FRAME_SIZE: usize = core::mem::size_of::<TrapFrame>();
+
+/// ABI names of the general purpose registers, indexed by register number
+pub const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// Register numbers of frequently used registers
+pub mod reg {
+    pub const RA: usize = 1;
+    pub const SP: usize = 2;
+    pub const GP: usize = 3;
+    pub const TP: usize = 4;
+    pub const FP: usize = 8;
+    pub const A0: usize = 10;
+    pub const A7: usize = 17;
+}
+
+/// Interrupted processor state saved by the trap entry
+///
+/// `regs[n]` holds register `xn`; `regs[0]` is always zero. The layout is
+/// shared with `asm/trap.s` and must not change without updating it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TrapFrame {
+    /// General purpose registers x0..x31 (x0 slot unused)
+    pub regs: [usize; 32],
+
+    /// Exception program counter (return address)
+    pub mepc: usize,
+
+    /// Status register at trap time (restored on return)
+    pub mstatus: usize,
+
+    /// Trap cause
+    pub mcause: usize,
+
+    /// Trap value (faulting address or instruction)
+    pub mtval: usize,
+}
+
+// The assembly uses fixed offsets: regs at 0, then mepc/mstatus/mcause/mtval
+const _: () = assert!(TRAP_FRAME_SIZE == 36 * 8 && TRAP_FRAME_SIZE % 16 == 0);
+
+impl TrapFrame {
+    /// Read a general purpose register by number
+    ///
+    /// # Arguments
+    /// * `index` - Register number (0..32); x0 always reads as 0
+    pub fn reg(&self, index: usize) -> usize {
+        if index == 0 {
+            0
+        } else {
+            self.regs[index]
+        }
+    }
+
+    /// Write a general purpose register by number
+    ///
+    /// Writes to x0 are ignored, as in hardware.
+    ///
+    /// # Arguments
+    /// * `index` - Register number (0..32)
+    /// * `value` - Value restored into the register on trap return
+    pub fn set_reg(&mut self, index: usize, value: usize) {
+        if index != 0 {
+            self.regs[index] = value;
+        }
+    }
+
+    /// Get function/syscall argument `n` (a0..a7)
+    pub fn arg(&self, n: usize) -> usize {
+        self.regs[reg::A0 + n]
+    }
+
+    /// Set the return value seen by the interrupted code (a0)
+    pub fn set_return(&mut self, value: usize) {
+        self.regs[reg::A0] = value;
+    }
+
+    /// Stack pointer of the interrupted code
+    pub fn sp(&self) -> usize {
+        self.regs[reg::SP]
+    }
+
+    /// Check if the trap was caused by an interrupt
+    pub fn is_interrupt(&self) -> bool {
+        self.mcause & csr::bits::MCAUSE_INTERRUPT_BIT != 0
+    }
+
+    /// Exception or interrupt code (without the interrupt bit)
+    pub fn cause_code(&self) -> usize {
+        self.mcause & csr::bits::MCAUSE_EXCEPTION_MASK
+    }
+
+    /// Length in bytes of the instruction at `mepc`
+    ///
+    /// # Safety
+    /// `mepc` must point to readable instruction memory.
+    pub unsafe fn instruction_length(&self) -> usize {
+        let low = ptr::read_volatile(self.mepc as *const u16);
+        if low & 0b11 == 0b11 {
+            4
+        } else {
+            2
+        }
+    }
+
+    /// Skip the trapping instruction
+    ///
+    /// # Arguments
+    /// * `len` - Instruction length in bytes (2 for compressed, 4 otherwise)
+    pub fn skip_instruction(&mut self, len: usize) {
+        self.mepc = self.mepc.wrapping_add(len);
+    }
+
+    /// Build a `RiscvContext` from the saved state
+    ///
+    /// `mtvec`, `mie` and `mip` are not part of the frame and are read from
+    /// the live CSRs.
+    pub fn context(&self) -> RiscvContext {
+        RiscvContext {
+            mstatus: self.mstatus,
+            mcause: self.mcause,
+            mepc: self.mepc,
+            mtvec: csr::read_mtvec(),
+            mie: csr::read_mie(),
+            mip: csr::read_mip(),
+        }
+    }
+
+    /// Print all registers and trap CSRs
+    pub fn print(&self) {
+        crate::println!("mepc: {}  mstatus: {}", hex(self.mepc), hex(self.mstatus));
+        crate::println!("mcause: {}  mtval: {}", hex(self.mcause), hex(self.mtval));
+        for index in (1..32).step_by(2) {
+            crate::print!("  ");
+            crate::print!(REG_NAMES[index]);
+            crate::print!("={}  ", hex(self.regs[index]));
+            if index + 1 < 32 {
+                crate::print!(REG_NAMES[index + 1]);
+                crate::print!("={}", hex(self.regs[index + 1]));
+            }
+            crate::println!();
+        }
+    }
+}
+
+/// Standard RISC-V synchronous exceptions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionMisaligned,
+    InstructionAccessFault,
+    IllegalInstruction,
+    Breakpoint,
+    LoadMisaligned,
+    LoadAccessFault,
+    StoreMisaligned,
+    StoreAccessFault,
+    EcallFromU,
+    EcallFromS,
+    EcallFromM,
+    InstructionPageFault,
+    LoadPageFault,
+    StorePageFault,
+    /// Reserved or custom exception code
+    Unknown(usize),
+}
+
+/// What `mtval` holds for an exception
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapValue {
+    /// Faulting virtual address
+    Address,
+    /// Faulting instruction bits (0 if not provided by the hardware)
+    Instruction,
+    /// Nothing meaningful (zero)
+    None,
+}
+
+impl Exception {
+    /// Decode an exception code (`mcause` without the interrupt bit)
+    pub fn from_code(code: usize) -> Self {
+        match code {
+            csr::bits::EXCEPTION_INSTR_MISALIGNED => Exception::InstructionMisaligned,
+            csr::bits::EXCEPTION_INSTR_ACCESS_FAULT => Exception::InstructionAccessFault,
+            csr::bits::EXCEPTION_ILLEGAL_INSTR => Exception::IllegalInstruction,
+            csr::bits::EXCEPTION_BREAKPOINT => Exception::Breakpoint,
+            csr::bits::EXCEPTION_LOAD_MISALIGNED => Exception::LoadMisaligned,
+            csr::bits::EXCEPTION_LOAD_ACCESS_FAULT => Exception::LoadAccessFault,
+            csr::bits::EXCEPTION_STORE_MISALIGNED => Exception::StoreMisaligned,
+            csr::bits::EXCEPTION_STORE_ACCESS_FAULT => Exception::StoreAccessFault,
+            csr::bits::EXCEPTION_ECALL_UMODE => Exception::EcallFromU,
+            csr::bits::EXCEPTION_ECALL_SMODE => Exception::EcallFromS,
+            csr::bits::EXCEPTION_ECALL_MMODE => Exception::EcallFromM,
+            csr::bits::EXCEPTION_INSTR_PAGE_FAULT => Exception::InstructionPageFault,
+            csr::bits::EXCEPTION_LOAD_PAGE_FAULT => Exception::LoadPageFault,
+            csr::bits::EXCEPTION_STORE_PAGE_FAULT => Exception::StorePageFault,
+            other => Exception::Unknown(other),
+        }
+    }
+
+    /// Exception code of this exception
+    pub fn code(&self) -> usize {
+        match self {
+            Exception::InstructionMisaligned => csr::bits::EXCEPTION_INSTR_MISALIGNED,
+            Exception::InstructionAccessFault => csr::bits::EXCEPTION_INSTR_ACCESS_FAULT,
+            Exception::IllegalInstruction => csr::bits::EXCEPTION_ILLEGAL_INSTR,
+            Exception::Breakpoint => csr::bits::EXCEPTION_BREAKPOINT,
+            Exception::LoadMisaligned => csr::bits::EXCEPTION_LOAD_MISALIGNED,
+            Exception::LoadAccessFault => csr::bits::EXCEPTION_LOAD_ACCESS_FAULT,
+            Exception::StoreMisaligned => csr::bits::EXCEPTION_STORE_MISALIGNED,
+            Exception::StoreAccessFault => csr::bits::EXCEPTION_STORE_ACCESS_FAULT,
+            Exception::EcallFromU => csr::bits::EXCEPTION_ECALL_UMODE,
+            Exception::EcallFromS => csr::bits::EXCEPTION_ECALL_SMODE,
+            Exception::EcallFromM => csr::bits::EXCEPTION_ECALL_MMODE,
+            Exception::InstructionPageFault => csr::bits::EXCEPTION_INSTR_PAGE_FAULT,
+            Exception::LoadPageFault => csr::bits::EXCEPTION_LOAD_PAGE_FAULT,
+            Exception::StorePageFault => csr::bits::EXCEPTION_STORE_PAGE_FAULT,
+            Exception::Unknown(code) => *code,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Exception::InstructionMisaligned => "Instruction address misaligned",
+            Exception::InstructionAccessFault => "Instruction access fault",
+            Exception::IllegalInstruction => "Illegal instruction",
+            Exception::Breakpoint => "Breakpoint",
+            Exception::LoadMisaligned => "Load address misaligned",
+            Exception::LoadAccessFault => "Load access fault",
+            Exception::StoreMisaligned => "Store/AMO address misaligned",
+            Exception::StoreAccessFault => "Store/AMO access fault",
+            Exception::EcallFromU => "Environment call from U-mode",
+            Exception::EcallFromS => "Environment call from S-mode",
+            Exception::EcallFromM => "Environment call from M-mode",
+            Exception::InstructionPageFault => "Instruction page fault",
+            Exception::LoadPageFault => "Load page fault",
+            Exception::StorePageFault => "Store/AMO page fault",
+            Exception::Unknown(_) => "Unknown exception",
+        }
+    }
+
+    /// Meaning of `mtval` for this exception
+    pub fn trap_value(&self) -> TrapValue {
+        match self {
+            Exception::IllegalInstruction => TrapValue::Instruction,
+            Exception::InstructionMisaligned
+            | Exception::InstructionAccessFault
+            | Exception::LoadMisaligned
+            | Exception::LoadAccessFault
+            | Exception::StoreMisaligned
+            | Exception::StoreAccessFault
+            | Exception::InstructionPageFault
+            | Exception::LoadPageFault
+            | Exception::StorePageFault => TrapValue::Address,
+            // mtval holds the ebreak address on most cores; mepc already does
+            _ => TrapValue::None,
+        }
+    }
+
+    /// Check if the fault happened while fetching the instruction at `mepc`
+    ///
+    /// Such faults cannot be resumed by skipping the instruction.
+    pub fn is_fetch_fault(&self) -> bool {
+        matches!(
+            self,
+            Exception::InstructionMisaligned
+                | Exception::InstructionAccessFault
+                | Exception::InstructionPageFault
+        )
+    }
+
+    /// Check if this is an environment call
+    pub fn is_ecall(&self) -> bool {
+        matches!(
+            self,
+            Exception::EcallFromU | Exception::EcallFromS | Exception::EcallFromM
+        )
+    }
+}
+
+/// Human-readable name of an interrupt code
+pub fn interrupt_name(code: usize) -> &'static str {
+    match code {
+        csr::bits::INTERRUPT_SW_SUPERVISOR => "Supervisor software interrupt",
+        csr::bits::INTERRUPT_SW_MACHINE => "Machine software interrupt",
+        csr::bits::INTERRUPT_TIMER_SUPERVISOR => "Supervisor timer interrupt",
+        csr::bits::INTERRUPT_TIMER_MACHINE => "Machine timer interrupt",
+        csr::bits::INTERRUPT_EXT_SUPERVISOR => "Supervisor external interrupt",
+        csr::bits::INTERRUPT_EXT_MACHINE => "Machine external interrupt",
+        _ => "Unknown interrupt",
+    }
+}
+
+/// Trap vector mode (`mtvec` MODE field)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapMode {
+    /// All traps enter at the base address
+    Direct,
+    /// Interrupts enter at base + 4 * cause; exceptions at the base
+    Vectored,
+}
+
+impl TrapMode {
+    /// Encode a trap vector base address with this mode
+    ///
+    /// # Arguments
+    /// * `base` - Trap vector base (4-byte aligned)
+    pub fn tvec(self, base: usize) -> usize {
+        let mode = match self {
+            TrapMode::Direct => csr::bits::TVEC_MODE_DIRECT,
+            TrapMode::Vectored => csr::bits::TVEC_MODE_VECTORED,
+        };
+        (base & !csr::bits::TVEC_MODE_MASK) | mode
+    }
+
+    /// Decode the mode of an `mtvec` value
+    pub fn from_tvec(tvec: usize) -> Self {
+        if tvec & csr::bits::TVEC_MODE_MASK == csr::bits::TVEC_MODE_VECTORED {
+            TrapMode::Vectored
+        } else {
+            TrapMode::Direct
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrapMode::Direct => "direct",
+            TrapMode::Vectored => "vectored",
+        }
+    }
+}
+
+/// Handler called with the saved state of the interrupted code
+pub type TrapHandlerFn = fn(&mut TrapFrame);
+
+/// Number of exception and interrupt codes covered by the registry
+///
+/// Covers every standard and custom code the privileged spec defines
+/// below 64.
+pub const MAX_TRAP_CODES: usize = 64;
+
+/// Trap handler registry errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapError {
+    /// Exception or interrupt code is out of range
+    InvalidCode,
+    /// A handler is already registered for this code
+    AlreadyRegistered,
+    /// Handler address is null or misaligned
+    InvalidHandler,
+}
+
+impl TrapError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrapError::InvalidCode => "Invalid trap code",
+            TrapError::AlreadyRegistered => "Handler already registered",
+            TrapError::InvalidHandler => "Invalid handler address",
+        }
+    }
+}
+
+impl core::fmt::Display for TrapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// RISC-V trap handler registry
+///
+/// Handlers are stored as function addresses in atomics, so registration
+/// and dispatch are safe from any hart without locking, including from
+/// inside a trap.
+pub struct RiscvTrapHandler {
+    exceptions: [AtomicUsize; MAX_TRAP_CODES],
+    interrupts: [AtomicUsize; MAX_TRAP_CODES],
+    fallback: AtomicUsize,
+}
+
+/// Trap frame being handled on each hart (null outside of a trap)
+static CURRENT_FRAME: [AtomicPtr<TrapFrame>; MAX_HARTS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; MAX_HARTS];
+
+impl RiscvTrapHandler {
+    /// Create an empty registry
+    pub const fn new() -> Self {
+        Self {
+            exceptions: [const { AtomicUsize::new(0) }; MAX_TRAP_CODES],
+            interrupts: [const { AtomicUsize::new(0) }; MAX_TRAP_CODES],
+            fallback: AtomicUsize::new(0),
+        }
+    }
+
+    fn slot(&self, interrupt: bool, code: usize) -> Result<&AtomicUsize, TrapError> {
+        let table = if interrupt {
+            &self.interrupts
+        } else {
+            &self.exceptions
+        };
+        table.get(code).ok_or(TrapError::InvalidCode)
+    }
+
+    fn install(slot: &AtomicUsize, handler: TrapHandlerFn) -> Result<(), TrapError> {
+        slot.compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+            .map(|_| ())
+            .map_err(|_| TrapError::AlreadyRegistered)
+    }
+
+    fn load(slot: &AtomicUsize) -> Option<TrapHandlerFn> {
+        match slot.load(Ordering::Acquire) {
+            0 => None,
+            // Only ever stores `TrapHandlerFn` addresses (see `install`)
+            addr => Some(unsafe { core::mem::transmute::<usize, TrapHandlerFn>(addr) }),
+        }
+    }
+
+    /// Register a handler for an exception code
+    ///
+    /// # Arguments
+    /// * `code` - Exception code (`mcause` without the interrupt bit)
+    /// * `handler` - Function called with the trap frame
+    ///
+    /// # Returns
+    /// `Ok(())` on success, or an error if the code is out of range or
+    /// already has a handler
+    pub fn register_exception(&self, code: usize, handler: TrapHandlerFn) -> Result<(), TrapError> {
+        Self::install(self.slot(false, code)?, handler)
+    }
+
+    /// Register a handler for an interrupt code
+    ///
+    /// # Arguments
+    /// * `code` - Interrupt code (`mcause` without the interrupt bit)
+    /// * `handler` - Function called with the trap frame
+    ///
+    /// # Returns
+    /// `Ok(())` on success, or an error if the code is out of range or
+    /// already has a handler
+    pub fn register_interrupt(&self, code: usize, handler: TrapHandlerFn) -> Result<(), TrapError> {
+        Self::install(self.slot(true, code)?, handler)
+    }
+
+    /// Remove the handler for an exception code
+    pub fn unregister_exception(&self, code: usize) -> Result<(), TrapError> {
+        self.slot(false, code)?.store(0, Ordering::Release);
+        Ok(())
+    }
+
+    /// Remove the handler for an interrupt code
+    pub fn unregister_interrupt(&self, code: usize) -> Result<(), TrapError> {
+        self.slot(true, code)?.store(0, Ordering::Release);
+        Ok(())
+    }
+
+    /// Set or clear the handler for traps the kernel does not handle itself
+    pub fn set_fallback(&self, handler: Option<TrapHandlerFn>) {
+        let addr = handler.map_or(0, |handler| handler as usize);
+        self.fallback.store(addr, Ordering::Release);
+    }
+
+    /// Get the handler registered for a trap cause
+    ///
+    /// # Arguments
+    /// * `mcause` - Raw cause register value
+    pub fn handler_for(&self, mcause: usize) -> Option<TrapHandlerFn> {
+        let interrupt = mcause & csr::bits::MCAUSE_INTERRUPT_BIT != 0;
+        let code = mcause & csr::bits::MCAUSE_EXCEPTION_MASK;
+        self.slot(interrupt, code).ok().and_then(Self::load)
+    }
+
+    /// Run the handler registered for the frame's cause
+    ///
+    /// # Returns
+    /// `true` if a handler was registered and has run
+    pub fn dispatch(&self, frame: &mut TrapFrame) -> bool {
+        match self.handler_for(frame.mcause) {
+            Some(handler) => {
+                handler(frame);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run the handler registered for a known interrupt code
+    ///
+    /// Used by the vectored entry stubs, which know the cause without
+    /// decoding `mcause`.
+    ///
+    /// # Returns
+    /// `true` if a handler was registered and has run
+    pub fn dispatch_interrupt(&self, code: usize, frame: &mut TrapFrame) -> bool {
+        match self.interrupts.get(code).and_then(Self::load) {
+            Some(handler) => {
+                handler(frame);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run the fallback handler, if one is set
+    ///
+    /// # Returns
+    /// `true` if a fallback handler was set and has run
+    pub fn dispatch_fallback(&self, frame: &mut TrapFrame) -> bool {
+        match Self::load(&self.fallback) {
+            Some(handler) => {
+                handler(frame);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Record the frame being handled on the calling hart
+    ///
+    /// Called by the trap path before dispatch so `get_context` can return
+    /// the saved state.
+    ///
+    /// # Returns
+    /// The previously recorded frame, to be passed to [`Self::end_trap`]
+    pub fn begin_trap(&self, frame: &mut TrapFrame) -> *mut TrapFrame {
+        match CURRENT_FRAME.get(current_hart()) {
+            Some(current) => current.swap(frame, Ordering::AcqRel),
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Restore the frame recorded before [`Self::begin_trap`]
+    pub fn end_trap(&self, previous: *mut TrapFrame) {
+        if let Some(current) = CURRENT_FRAME.get(current_hart()) {
+            current.store(previous, Ordering::Release);
+        }
+    }
+
+    /// Saved state of the trap being handled on the calling hart
+    ///
+    /// # Returns
+    /// The trap frame, or `None` outside of a trap handler
+    pub fn current_frame(&self) -> Option<&TrapFrame> {
+        let frame = CURRENT_FRAME.get(current_hart())?.load(Ordering::Acquire);
+        // The frame lives on this hart's stack until `end_trap`
+        unsafe { frame.as_ref() }
+    }
+
+    /// Print all registered handlers
+    pub fn show_handlers(&self) {
+        crate::println!("=== Trap Handlers ===");
+        for (name, table) in [
+            ("exception", &self.exceptions),
+            ("interrupt", &self.interrupts),
+        ] {
+            for (code, slot) in table.iter().enumerate() {
+                let addr = slot.load(Ordering::Acquire);
+                if addr != 0 {
+                    crate::print!("  ");
+                    crate::print!(name);
+                    crate::println!(" {}: {}", crate::console::num(code as u64), hex(addr));
+                }
+            }
+        }
+        match self.fallback.load(Ordering::Acquire) {
+            0 => crate::println!("  fallback: (built-in)"),
+            addr => crate::println!("  fallback: {}", hex(addr)),
+        }
+    }
+}
+
+impl Default for RiscvTrapHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TrapHandler for RiscvTrapHandler {
+    type Context = RiscvContext;
+
+    /// Register `handler` (the address of a [`TrapHandlerFn`]) as the
+    /// fallback for traps without a specific handler
+    unsafe fn register(&self, handler: Address) -> Result<(), &'static str> {
+        if handler == 0 || handler % 2 != 0 {
+            return Err(TrapError::InvalidHandler.as_str());
+        }
+        self.fallback.store(handler, Ordering::Release);
+        Ok(())
+    }
+
+    /// The saved context of the trap being handled, or the live CSRs
+    /// outside of a trap handler
+    fn get_context(&self) -> RiscvContext {
+        match self.current_frame() {
+            Some(frame) => frame.context(),
+            None => RiscvContext::capture(),
+        }
+    }
+}
+
+fn current_hart() -> usize {
+    csr::read_mhartid() as usize
+}
+
+/// Global trap handler registry
+pub static TRAP_HANDLER: RiscvTrapHandler = RiscvTrapHandler::new();
@@ -10,8 +10,24 @@
 //! - Format macro support with `{}` placeholders
 //! - Emergency output for panic situations
 //! - Type-safe output functions
+//! - Polled input for interactive debugging
 
-use crate::UART0;
+use crate::arch::current::platform;
+
+/// Get the console UART transmit register
+///
+/// The address comes from the platform description so the console follows
+/// the devicetree; before discovery it is the QEMU virt default.
+#[inline]
+fn uart() -> *mut u8 {
+    platform::uart_base() as *mut u8
+}
+
+/// Line status register offset (ns16550a)
+const UART_LSR: usize = 5;
+
+/// Line status bit: received data is ready
+const LSR_DATA_READY: u8 = 0x01;
 
 /// Output a single byte to the UART console
 ///
@@ -27,7 +43,7 @@ use crate::UART0;
 #[inline]
 pub fn put_char(c: u8) {
     unsafe {
-        core::ptr::write_volatile(UART0, c);
+        core::ptr::write_volatile(uart(), c);
     }
 }
 
@@ -49,6 +65,36 @@ pub fn put_str(s: &str) {
     }
 }
 
+/// Read a byte from the UART console if one is available
+///
+/// Polls the line status register; never blocks and does not rely on
+/// UART interrupts, so it works inside trap handlers.
+///
+/// # Returns
+/// The received byte, or `None` if no input is pending
+pub fn try_get_char() -> Option<u8> {
+    unsafe {
+        let lsr = core::ptr::read_volatile(uart().add(UART_LSR));
+        if lsr & LSR_DATA_READY != 0 {
+            Some(core::ptr::read_volatile(uart()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Wait for a byte from the UART console
+///
+/// Busy-waits on [`try_get_char`].
+pub fn get_char() -> u8 {
+    loop {
+        if let Some(c) = try_get_char() {
+            return c;
+        }
+        core::hint::spin_loop();
+    }
+}
+
 /// Output a newline character to the console
 ///
 /// Outputs a Unix-style line feed character (0x0A).
@@ -443,7 +489,7 @@ macro_rules! debug_hex {
 pub fn panic_put_str_safe(s: &str) {
     for byte in s.bytes() {
         unsafe {
-            core::ptr::write_volatile(UART0, byte);
+            core::ptr::write_volatile(uart(), byte);
         }
     }
 }
@@ -453,7 +499,7 @@ pub fn panic_put_str_safe(s: &str) {
 /// Outputs a newline character directly to UART during panic conditions.
 pub fn panic_put_newline_safe() {
     unsafe {
-        core::ptr::write_volatile(UART0, b'\n');
+        core::ptr::write_volatile(uart(), b'\n');
     }
 }
 
@@ -466,7 +512,7 @@ pub fn panic_put_newline_safe() {
 pub fn panic_put_number_safe(num: u64) {
     if num == 0 {
         unsafe {
-            core::ptr::write_volatile(UART0, b'0');
+            core::ptr::write_volatile(uart(), b'0');
         }
         return;
     }
@@ -484,7 +530,7 @@ pub fn panic_put_number_safe(num: u64) {
     while pos > 0 {
         pos -= 1;
         unsafe {
-            core::ptr::write_volatile(UART0, buffer[pos]);
+            core::ptr::write_volatile(uart(), buffer[pos]);
         }
     }
 }
@@ -499,13 +545,13 @@ pub fn panic_put_hex_safe(num: usize) {
     const HEX_CHARS: &[u8] = b"0123456789abcdef";
 
     unsafe {
-        core::ptr::write_volatile(UART0, b'0');
-        core::ptr::write_volatile(UART0, b'x');
+        core::ptr::write_volatile(uart(), b'0');
+        core::ptr::write_volatile(uart(), b'x');
     }
 
     if num == 0 {
         unsafe {
-            core::ptr::write_volatile(UART0, b'0');
+            core::ptr::write_volatile(uart(), b'0');
         }
         return;
     }
@@ -523,7 +569,7 @@ pub fn panic_put_hex_safe(num: usize) {
     while pos > 0 {
         pos -= 1;
         unsafe {
-            core::ptr::write_volatile(UART0, buffer[pos]);
+            core::ptr::write_volatile(uart(), buffer[pos]);
         }
     }
 }
@@ -1,7 +1,11 @@
 // Advanced Debug & Recovery System
 // スタックトレース、メモリプロテクション、ソフトリセット
 
-use crate::{arch::csr, print, println, println_hex, println_number, UART0};
+use crate::arch::{csr, csr::bits, current::ipi, current::CLINT_TIMER, Timer};
+use crate::console::{num, str};
+use crate::interrupt::InterruptGuard;
+use crate::memory::{self, frame, heap};
+use crate::{print, println, println_hex, println_number, trap};
 
 /// デバッグ情報の詳細レベル
 #[derive(Clone, Copy, PartialEq)]
@@ -23,54 +27,47 @@ pub enum RecoveryOption {
 
 /// 簡易スタックトレース（リターンアドレスを辿る）
 pub fn print_stack_trace(max_depth: usize) {
+    print_stack_trace_from(get_frame_pointer(), get_return_address(), max_depth);
+}
+
+/// 指定したフレームポインタとPCからスタックトレースを出力
+///
+/// RISC-Vの標準フレームレイアウト（fp-8に戻りアドレス、fp-16に呼び出し元のfp）を
+/// 前提とする。トラップフレームのs0/mepcを渡せば、トラップ発生箇所から辿れる。
+pub fn print_stack_trace_from(fp: usize, pc: usize, max_depth: usize) {
     println!("=== STACK TRACE ===");
 
-    let mut ra = get_return_address();
-    let mut fp = get_frame_pointer();
+    let layout = memory::layout();
+    let mut fp = fp;
+    let mut pc = pc;
     let mut depth = 0;
 
     println!("Call stack (approximate):");
 
-    while depth < max_depth && is_valid_address(ra) && is_valid_address(fp) {
+    while depth < max_depth && is_valid_address(pc) {
         print!("  #");
         print_number!(depth as u64);
         print!(": ");
-        print_hex!(ra);
+        print_hex!(pc);
 
         // 関数名の推定（簡易版）
-        if let Some(name) = guess_function_name(ra) {
+        if let Some(name) = guess_function_name(pc) {
             print!(" <");
             print!(name);
             print!(">");
         }
         println!();
+        depth += 1;
 
-        // 次のフレームに移動（簡易版）
-        if fp >= 0x80000000 && fp < 0x80100000 {
-            // フレームポインタからリターンアドレスを取得
-            let next_ra = unsafe {
-                if fp + 8 < 0x80100000 {
-                    core::ptr::read_volatile((fp + 8) as *const usize)
-                } else {
-                    0
-                }
-            };
-
-            let next_fp = unsafe {
-                if fp < 0x80100000 {
-                    core::ptr::read_volatile(fp as *const usize)
-                } else {
-                    0
-                }
-            };
-
-            ra = next_ra;
-            fp = next_fp;
-        } else {
+        // 次のフレームに移動（fpがスタック内にある場合のみ）
+        if fp < 16 || fp % 8 != 0 || !layout.is_stack(fp - 16) || !layout.is_stack(fp - 1) {
             break;
         }
+        pc = unsafe { core::ptr::read_volatile((fp - 8) as *const usize) };
+        let next_fp = unsafe { core::ptr::read_volatile((fp - 16) as *const usize) };
 
-        depth += 1;
+        // 呼び出し元のフレームは必ず上位アドレスにある（ループ防止）
+        fp = if next_fp > fp { next_fp } else { 0 };
     }
 
     if depth == 0 {
@@ -100,8 +97,8 @@ fn get_frame_pointer() -> usize {
 
 /// アドレスの妥当性チェック
 fn is_valid_address(addr: usize) -> bool {
-    // RAM範囲内かチェック
-    addr >= 0x80000000 && addr < 0x88000000 && addr % 4 == 0
+    // RAMまたはカーネルイメージ内かチェック（圧縮命令があるので2バイト境界）
+    memory::layout().is_kernel_address(addr) && addr % 2 == 0
 }
 
 /// 関数名の推定（既知のアドレス範囲から）
@@ -116,15 +113,16 @@ fn guess_function_name(addr: usize) -> Option<&'static str> {
 
     let start_addr = _start as usize;
     let main_addr = rust_main as usize;
+    let text = memory::layout().text;
 
     // 簡易的な範囲推定
     if addr >= start_addr && addr < start_addr + 0x100 {
         Some("_start")
     } else if addr >= main_addr && addr < main_addr + 0x1000 {
         Some("rust_main")
-    } else if addr >= 0x80000000 && addr < 0x80001000 {
+    } else if addr >= text.start && addr < text.start + 0x1000 {
         Some("boot_section")
-    } else if addr >= 0x80001000 && addr < 0x80010000 {
+    } else if text.contains(addr) {
         Some("kernel_code")
     } else {
         None
@@ -183,8 +181,8 @@ fn calculate_checksum(start: usize, end: usize) -> u32 {
     let mut addr = start;
 
     while addr < end && addr + 4 <= end {
-        if is_valid_address(addr) {
-            let value = unsafe { core::ptr::read_volatile(addr as *const u32) };
+        // 読めないアドレス（ガードページ、未実装領域）は飛ばす
+        if let Ok(value) = trap::probe_read::<u32>(addr) {
             sum = sum.wrapping_add(value);
         }
         addr += 4;
@@ -201,21 +199,31 @@ fn analyze_memory_corruption(start: usize, end: usize) {
     let mut total_bytes = 0;
 
     for addr in (start..end).step_by(4) {
-        if addr + 4 <= end && is_valid_address(addr) {
-            total_bytes += 4;
-
-            // 簡易的な破損検出（ゼロでない予期しない値）
-            let value = unsafe { core::ptr::read_volatile(addr as *const u32) };
+        if addr + 4 > end {
+            continue;
+        }
 
-            // 特定のパターンを破損として検出
-            if value == 0xDEADBEEF || value == 0xBADCAFE0 {
-                corrupted_bytes += 4;
-                print!("Corruption at ");
+        let value = match trap::probe_read::<u32>(addr) {
+            Ok(value) => value,
+            Err(fault) => {
+                print!("Unreadable at ");
                 print_hex!(addr);
                 print!(": ");
-                print_hex!(value as usize);
-                println!();
+                println!(fault.as_str());
+                continue;
             }
+        };
+
+        total_bytes += 4;
+
+        // 簡易的な破損検出（特定のパターンを破損として検出）
+        if value == 0xDEADBEEF || value == 0xBADCAFE0 {
+            corrupted_bytes += 4;
+            print!("Corruption at ");
+            print_hex!(addr);
+            print!(": ");
+            print_hex!(value as usize);
+            println!();
         }
     }
 
@@ -266,8 +274,11 @@ pub fn soft_reset() -> ! {
         // mepcに_startアドレスを設定
         csr::write_mepc(reset_addr);
 
-        // mretで_startにジャンプ
+        // mret/sretで_startにジャンプ
+        #[cfg(not(feature = "smode"))]
         core::arch::asm!("mret");
+        #[cfg(feature = "smode")]
+        core::arch::asm!("sret");
     }
 
     // ここには到達しないはず
@@ -281,16 +292,10 @@ pub fn soft_reset() -> ! {
 /// 全ハードウェアの安全停止
 fn stop_all_hardware() {
     // タイマの停止
-    unsafe {
-        let mtimecmp_addr = 0x2004000 as *mut u64;
-        core::ptr::write_volatile(mtimecmp_addr, u64::MAX);
-    }
+    let _ = unsafe { CLINT_TIMER.stop() };
 
-    // MSIPのクリア
-    unsafe {
-        let msip_addr = 0x2000000 as *mut u32;
-        core::ptr::write_volatile(msip_addr, 0);
-    }
+    // ソフトウェア割り込みのクリア
+    let _ = ipi::clear(csr::read_mhartid() as usize);
 
     // その他のペリフェラル（必要に応じて追加）
 }
@@ -306,7 +311,7 @@ fn clear_temporary_memory() {
         sp
     };
 
-    let stack_top = 0x80100000;
+    let (_, stack_top) = memory::hart_stack(csr::read_mhartid() as usize);
 
     if current_sp < stack_top {
         let clear_size = stack_top - current_sp;
@@ -325,8 +330,8 @@ fn reinitialize_csrs() {
         // 基本的なCSRをクリア
         csr::write_mepc(0);
 
-        // mstatusを初期状態に
-        let initial_mstatus = (3 << 11); // MPP = Machine mode
+        // mstatusを初期状態に（xPP = カーネルの特権レベル）
+        let initial_mstatus = bits::STATUS_PP_MASK << bits::STATUS_PP_SHIFT;
         csr::write_mstatus(initial_mstatus);
 
         // mieをクリア
@@ -338,10 +343,8 @@ fn reinitialize_csrs() {
 pub fn enter_safe_mode() {
     println!("=== ENTERING SAFE MODE ===");
 
-    // 割り込みを無効化
-    unsafe {
-        csr::disable_global_interrupts();
-    }
+    // 割り込みを無効化（セーフモードを抜けると元の状態に戻る）
+    let _interrupts = InterruptGuard::new();
 
     // タイマを停止
     stop_all_hardware();
@@ -391,7 +394,7 @@ fn safe_mode_shell() {
                 break;
             }
             _ => {
-                println!("Unknown command: {}", cmd);
+                println!("Unknown command: {}", str(cmd));
             }
         }
 
@@ -420,8 +423,7 @@ fn show_safe_mode_status() {
     println_hex!("mie:     ", mie);
     println_hex!("mtvec:   ", mtvec);
 
-    let global_ie = (mstatus >> 3) & 1;
-    if global_ie == 0 {
+    if mstatus & bits::STATUS_IE == 0 {
         println!("✓ Interrupts safely disabled");
     } else {
         println!("⚠ Interrupts still enabled");
@@ -437,7 +439,8 @@ fn show_safe_mode_status() {
 
     println_hex!("Current SP: ", current_sp);
 
-    if current_sp >= 0x80000000 && current_sp < 0x80100000 {
+    let (stack_bottom, stack_top) = memory::hart_stack(csr::read_mhartid() as usize);
+    if current_sp > stack_bottom + memory::STACK_GUARD_SIZE && current_sp <= stack_top {
         println!("✓ Stack in valid range");
     } else {
         println!("⚠ Stack may be corrupted");
@@ -445,21 +448,13 @@ fn show_safe_mode_status() {
 }
 
 /// メモリ情報の表示（セーフモード用）
-fn show_memory_info() {
+pub fn show_memory_info() {
     println!("=== MEMORY INFORMATION ===");
 
-    extern "C" {
-        static __bss_start: u8;
-        static __bss_end: u8;
-        static __data_start: u8;
-        static __data_end: u8;
-    }
-
-    unsafe {
-        let bss_start = &__bss_start as *const u8 as usize;
-        let bss_end = &__bss_end as *const u8 as usize;
-        let data_start = &__data_start as *const u8 as usize;
-        let data_end = &__data_end as *const u8 as usize;
+    let layout = memory::layout();
+    {
+        let (bss_start, bss_end) = (layout.bss.start, layout.bss.end);
+        let (data_start, data_end) = (layout.data.start, layout.data.end);
 
         println_hex!(".data start: ", data_start);
         println_hex!(".data end:   ", data_end);
@@ -479,9 +474,38 @@ fn show_memory_info() {
         sp
     };
 
-    let stack_used = 0x80100000 - current_sp;
-    println_number!("Stack used:  ", stack_used as u64);
-    println!(" bytes");
+    let stack = layout.hart_stack(csr::read_mhartid() as usize);
+    let (stack_bottom, stack_top) = (stack.start, stack.end);
+    if current_sp > stack_bottom && current_sp <= stack_top {
+        println!(
+            "Stack used:  {} / {} bytes",
+            num((stack_top - current_sp) as u64),
+            num((stack_top - stack_bottom) as u64)
+        );
+    } else {
+        println_hex!("Stack pointer outside hart stack: ", current_sp);
+    }
+
+    // 物理フレームの使用状況
+    if frame::is_initialized() {
+        let stats = frame::stats();
+        let kib = |frames: usize| (frames * frame::FRAME_SIZE / 1024) as u64;
+        println!("Physical free: {} KiB", num(kib(stats.free_frames)));
+        println!("Physical used: {} KiB", num(kib(stats.used_frames)));
+        println!("Reserved:      {} KiB", num(kib(stats.reserved_frames)));
+    } else {
+        println!("Physical memory: frame allocator not initialized");
+    }
+
+    if heap::is_initialized() {
+        let stats = heap::stats();
+        println!(
+            "Heap in use:   {} / {} bytes (peak {})",
+            num(stats.in_use as u64),
+            num(stats.heap_size as u64),
+            num(stats.peak as u64)
+        );
+    }
 }
 
 /// 復旧オプションの決定
@@ -560,15 +584,18 @@ pub fn execute_recovery_action(action: RecoveryOption, message: &str) -> ! {
 pub fn print_debug_info(level: DebugLevel, context: &str) {
     match level {
         DebugLevel::Minimal => {
-            println!("DEBUG: {}", context);
+            print!("DEBUG: ");
+            println!(context);
         }
         DebugLevel::Standard => {
-            println!("DEBUG: {}", context);
+            print!("DEBUG: ");
+            println!(context);
             let mstatus = csr::read_mstatus();
             println_hex!("mstatus: ", mstatus);
         }
         DebugLevel::Verbose => {
-            println!("DEBUG: {}", context);
+            print!("DEBUG: ");
+            println!(context);
             let mstatus = csr::read_mstatus();
             let mepc = csr::read_mepc();
             let mcause = csr::read_mcause();
@@ -577,7 +604,8 @@ pub fn print_debug_info(level: DebugLevel, context: &str) {
             println_hex!("mcause:  ", mcause);
         }
         DebugLevel::Full => {
-            println!("DEBUG: {}", context);
+            print!("DEBUG: ");
+            println!(context);
             crate::system_diagnostics();
             print_stack_trace(5);
         }
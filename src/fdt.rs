@@ -0,0 +1,311 @@
+//! Flattened Device Tree (FDT) Parser
+//!
+//! This module provides a minimal, allocation-free parser for the devicetree
+//! blob (DTB) that firmware or QEMU passes to the kernel at boot. It validates
+//! the header, walks the structure block and reports nodes and properties to
+//! a visitor closure, leaving interpretation to the caller.
+//!
+//! All multi-byte values in a DTB are big-endian.
+
+/// FDT header magic number
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// Oldest structure block version this parser understands
+const FDT_MIN_COMPAT_VERSION: u32 = 16;
+
+/// Size of the FDT header in bytes
+const FDT_HEADER_SIZE: usize = 40;
+
+// Structure block tokens
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// FDT parsing errors
+#[derive(Debug, Clone, Copy)]
+pub enum FdtError {
+    /// No devicetree pointer was provided
+    NullPointer,
+
+    /// The blob pointer is not 8-byte aligned
+    Misaligned,
+
+    /// The header magic number is wrong
+    BadMagic,
+
+    /// The blob uses an incompatible format version
+    UnsupportedVersion,
+
+    /// An offset or length points outside the blob
+    Truncated,
+
+    /// An unknown token was found in the structure block
+    BadToken(u32),
+}
+
+impl core::fmt::Display for FdtError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            FdtError::NullPointer => write!(f, "No devicetree pointer"),
+            FdtError::Misaligned => write!(f, "Misaligned devicetree pointer"),
+            FdtError::BadMagic => write!(f, "Bad devicetree magic"),
+            FdtError::UnsupportedVersion => write!(f, "Unsupported devicetree version"),
+            FdtError::Truncated => write!(f, "Truncated devicetree"),
+            FdtError::BadToken(token) => write!(f, "Bad devicetree token {:#x}", token),
+        }
+    }
+}
+
+impl FdtError {
+    /// Get a static description of the error
+    ///
+    /// # Returns
+    /// A human-readable error string usable with the console macros
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FdtError::NullPointer => "No devicetree pointer",
+            FdtError::Misaligned => "Misaligned devicetree pointer",
+            FdtError::BadMagic => "Bad devicetree magic",
+            FdtError::UnsupportedVersion => "Unsupported devicetree version",
+            FdtError::Truncated => "Truncated devicetree",
+            FdtError::BadToken(_) => "Bad devicetree token",
+        }
+    }
+}
+
+/// Structure block event reported while walking the tree
+#[derive(Debug, Clone, Copy)]
+pub enum FdtEvent<'a> {
+    /// Start of a node (the root node has an empty name)
+    BeginNode(&'a str),
+
+    /// End of the most recently opened node
+    EndNode,
+
+    /// Property of the currently open node
+    Property(&'a str, &'a [u8]),
+}
+
+/// Parsed devicetree blob
+pub struct Fdt<'a> {
+    data: &'a [u8],
+    struct_offset: usize,
+    struct_size: usize,
+    strings_offset: usize,
+    strings_size: usize,
+    rsvmap_offset: usize,
+}
+
+impl Fdt<'static> {
+    /// Create a parser for a devicetree blob in memory
+    ///
+    /// # Arguments
+    /// * `addr` - Physical address of the blob (as passed in `a1` at boot)
+    ///
+    /// # Returns
+    /// A validated `Fdt`, or an error if the blob is missing or malformed
+    ///
+    /// # Safety
+    /// The caller must guarantee that `addr` points to readable memory that
+    /// remains valid for the lifetime of the kernel.
+    pub unsafe fn from_addr(addr: usize) -> Result<Self, FdtError> {
+        if addr == 0 {
+            return Err(FdtError::NullPointer);
+        }
+        if addr % 8 != 0 {
+            return Err(FdtError::Misaligned);
+        }
+
+        // Read the magic and total size before trusting the length
+        let header = core::slice::from_raw_parts(addr as *const u8, FDT_HEADER_SIZE);
+        if be32(header, 0) != Some(FDT_MAGIC) {
+            return Err(FdtError::BadMagic);
+        }
+        let total_size = be32(header, 4).ok_or(FdtError::Truncated)? as usize;
+
+        Fdt::new(core::slice::from_raw_parts(addr as *const u8, total_size))
+    }
+}
+
+impl<'a> Fdt<'a> {
+    /// Create a parser over a devicetree blob
+    ///
+    /// # Arguments
+    /// * `data` - The complete blob, at least `totalsize` bytes long
+    ///
+    /// # Returns
+    /// A validated `Fdt`, or an error if the header is invalid
+    pub fn new(data: &'a [u8]) -> Result<Self, FdtError> {
+        if data.len() < FDT_HEADER_SIZE {
+            return Err(FdtError::Truncated);
+        }
+        if be32(data, 0) != Some(FDT_MAGIC) {
+            return Err(FdtError::BadMagic);
+        }
+
+        let field = |index: usize| be32(data, index * 4).unwrap_or(0) as usize;
+        let total_size = field(1);
+        let struct_offset = field(2);
+        let strings_offset = field(3);
+        let rsvmap_offset = field(4);
+        let last_comp_version = field(6) as u32;
+        let strings_size = field(8);
+        let struct_size = field(9);
+
+        if last_comp_version > 17 || field(5) < FDT_MIN_COMPAT_VERSION as usize {
+            return Err(FdtError::UnsupportedVersion);
+        }
+        if total_size > data.len()
+            || struct_offset + struct_size > total_size
+            || strings_offset + strings_size > total_size
+            || rsvmap_offset >= total_size
+        {
+            return Err(FdtError::Truncated);
+        }
+
+        Ok(Self {
+            data: &data[..total_size],
+            struct_offset,
+            struct_size,
+            strings_offset,
+            strings_size,
+            rsvmap_offset,
+        })
+    }
+
+    /// Get the total size of the blob in bytes
+    pub fn total_size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Walk the structure block, reporting every node and property
+    ///
+    /// # Arguments
+    /// * `visit` - Closure called for each structure block event in order
+    ///
+    /// # Returns
+    /// `Ok(())` when the end token is reached, or an error on malformed data
+    pub fn walk<F: FnMut(FdtEvent<'a>)>(&self, mut visit: F) -> Result<(), FdtError> {
+        let end = self.struct_offset + self.struct_size;
+        let mut pos = self.struct_offset;
+
+        while pos < end {
+            let token = be32(self.data, pos).ok_or(FdtError::Truncated)?;
+            pos += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = c_str(self.data, pos).ok_or(FdtError::Truncated)?;
+                    pos = align4(pos + name.len() + 1);
+                    visit(FdtEvent::BeginNode(name));
+                }
+                FDT_END_NODE => visit(FdtEvent::EndNode),
+                FDT_PROP => {
+                    let len = be32(self.data, pos).ok_or(FdtError::Truncated)? as usize;
+                    let name_offset = be32(self.data, pos + 4).ok_or(FdtError::Truncated)?;
+                    pos += 8;
+
+                    let value = self.data.get(pos..pos + len).ok_or(FdtError::Truncated)?;
+                    let name = self.string(name_offset as usize)?;
+                    pos = align4(pos + len);
+                    visit(FdtEvent::Property(name, value));
+                }
+                FDT_NOP => {}
+                FDT_END => return Ok(()),
+                other => return Err(FdtError::BadToken(other)),
+            }
+        }
+
+        Err(FdtError::Truncated)
+    }
+
+    /// Visit every entry of the memory reservation block
+    ///
+    /// # Arguments
+    /// * `visit` - Closure called with `(address, size)` for each reservation
+    pub fn for_each_reservation<F: FnMut(u64, u64)>(&self, mut visit: F) {
+        let mut pos = self.rsvmap_offset;
+
+        while let (Some(addr), Some(size)) = (be64(self.data, pos), be64(self.data, pos + 8)) {
+            if addr == 0 && size == 0 {
+                break;
+            }
+            visit(addr, size);
+            pos += 16;
+        }
+    }
+
+    /// Look up a property name in the strings block
+    fn string(&self, offset: usize) -> Result<&'a str, FdtError> {
+        if offset >= self.strings_size {
+            return Err(FdtError::Truncated);
+        }
+        c_str(self.data, self.strings_offset + offset).ok_or(FdtError::Truncated)
+    }
+}
+
+/// Read a big-endian u32 at the given offset
+pub fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read a big-endian u64 at the given offset
+pub fn be64(data: &[u8], offset: usize) -> Option<u64> {
+    let high = be32(data, offset)? as u64;
+    let low = be32(data, offset + 4)? as u64;
+    Some((high << 32) | low)
+}
+
+/// Read a value made of `cells` 32-bit cells starting at cell `index`
+///
+/// # Arguments
+/// * `value` - Raw property value
+/// * `index` - Index of the first cell
+/// * `cells` - Number of cells (1 or 2)
+///
+/// # Returns
+/// The combined value, or `None` if the property is too short
+pub fn read_cells(value: &[u8], index: usize, cells: usize) -> Option<u64> {
+    let mut result = 0u64;
+    for i in 0..cells {
+        result = (result << 32) | be32(value, (index + i) * 4)? as u64;
+    }
+    Some(result)
+}
+
+/// Interpret a property value as a single u32 or u64 number
+pub fn prop_number(value: &[u8]) -> Option<u64> {
+    match value.len() {
+        4 => be32(value, 0).map(|v| v as u64),
+        8 => be64(value, 0),
+        _ => None,
+    }
+}
+
+/// Interpret a property value as a NUL-terminated string
+pub fn prop_str(value: &[u8]) -> Option<&str> {
+    let bytes = value.split(|&b| b == 0).next()?;
+    core::str::from_utf8(bytes).ok()
+}
+
+/// Check whether a string-list property (such as `compatible`) contains `name`
+pub fn prop_contains(value: &[u8], name: &str) -> bool {
+    value
+        .split(|&b| b == 0)
+        .any(|entry| entry == name.as_bytes())
+}
+
+/// Read a NUL-terminated string starting at the given offset
+fn c_str(data: &[u8], offset: usize) -> Option<&str> {
+    let tail = data.get(offset..)?;
+    let len = tail.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&tail[..len]).ok()
+}
+
+/// Round an offset up to the next 4-byte boundary
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
@@ -1,19 +1,39 @@
 // RISC-V ソフトウェア割り込み完全実装（修正版）
 // 検証済みMSIPアクセスを基盤とする
 
-use crate::{arch::csr, println, println_hex, println_number, UART0};
+pub mod deferred;
+pub mod guard;
+pub mod irq;
+
+pub use deferred::WorkItem;
+pub use guard::{with_interrupts, without_interrupts, InterruptGuard};
+pub use irq::{free_irq, request_irq, IrqError, IrqHandler, IrqReturn};
+
+use crate::arch::csr::{self, bits};
+use crate::arch::current::ipi;
+use crate::trap::stats;
+use crate::{println, println_hex, println_number};
+
+/// 実行中ハートのID（MSIP/SSIPは自ハートのものを操作する）
+fn current_hart() -> usize {
+    csr::read_mhartid() as usize
+}
 
-// 検証済みCLINTアドレス
-const MSIP_ADDR: *mut u32 = 0x2000000 as *mut u32; // Hart 0のMSIP
+/// 実行中ハートが処理したソフトウェア割り込みの数（トラップ統計から取得）
+fn sw_interrupt_count() -> u64 {
+    stats::cause_stats(
+        current_hart(),
+        bits::MCAUSE_INTERRUPT_BIT | bits::INTERRUPT_SW,
+    )
+    .count
+}
 
-// グローバル状態管理（統計とデバッグ用）
-static mut SW_INTERRUPT_COUNT: u64 = 0;
+// グローバル状態管理（統計とデバッグ用、割り込み回数はtrap::statsが数える）
 static mut YIELD_COUNT: u64 = 0;
 static mut LAST_YIELD_TIME: u64 = 0;
 
 // エラー統計
 static mut MSIP_ERRORS: u64 = 0;
-static mut HANDLER_CALLS: u64 = 0;
 
 /// ソフトウェア割り込みシステムの完全初期化
 pub fn init_software_interrupt() {
@@ -37,7 +57,7 @@ pub fn init_software_interrupt() {
     let mie = csr::read_mie();
     println_hex!("MIE register: ", mie);
 
-    if (mie & (1 << 3)) != 0 {
+    if (mie & bits::IE_SOFT) != 0 {
         println!("✓ Machine Software Interrupt Enable (MSIE) is active");
     } else {
         println!("✗ MSIE not enabled");
@@ -47,7 +67,7 @@ pub fn init_software_interrupt() {
     // Step 3: グローバル割り込み状態の確認
     println!("Step 3: Checking global interrupt state...");
     let mstatus = csr::read_mstatus();
-    let global_ie = (mstatus >> 3) & 1;
+    let global_ie = ((mstatus & bits::STATUS_IE) != 0) as usize;
 
     println_hex!("MSTATUS register: ", mstatus);
     println_number!("Global interrupts (MIE): ", global_ie as u64);
@@ -58,27 +78,17 @@ pub fn init_software_interrupt() {
 
     // Step 4: 統計情報の初期化
     unsafe {
-        SW_INTERRUPT_COUNT = 0;
         YIELD_COUNT = 0;
         LAST_YIELD_TIME = 0;
         MSIP_ERRORS = 0;
-        HANDLER_CALLS = 0;
     }
 
     println!("✓ Software interrupt system fully initialized");
 }
 
-/// 安全なMSIP読み取り
+/// 安全なMSIP読み取り（S-modeではSSIP）
 fn read_msip_safe() -> Result<u32, &'static str> {
-    let val = unsafe { core::ptr::read_volatile(MSIP_ADDR) };
-    if val <= 1 {
-        Ok(val)
-    } else {
-        unsafe {
-            MSIP_ERRORS += 1;
-        }
-        Err("Invalid MSIP value")
-    }
+    Ok(ipi::is_pending(current_hart()) as u32)
 }
 
 /// 安全なMSIP書き込み（改良版）
@@ -87,8 +97,17 @@ fn write_msip_safe(value: u32) -> Result<(), &'static str> {
         return Err("Invalid MSIP value (must be 0 or 1)");
     }
 
-    unsafe {
-        core::ptr::write_volatile(MSIP_ADDR, value);
+    let hart = current_hart();
+    let result = if value == 1 {
+        ipi::send(hart)
+    } else {
+        ipi::clear(hart)
+    };
+    if result.is_err() {
+        unsafe {
+            MSIP_ERRORS += 1;
+        }
+        return Err("MSIP access failed");
     }
 
     // 書き込み後の短い遅延（競合状態回避）
@@ -138,7 +157,7 @@ pub fn clear_software_interrupt() -> Result<(), &'static str> {
 pub fn yield_cpu() -> Result<(), &'static str> {
     unsafe {
         YIELD_COUNT += 1;
-        LAST_YIELD_TIME = SW_INTERRUPT_COUNT;
+        LAST_YIELD_TIME = sw_interrupt_count();
     }
 
     println_number!("yield() #", unsafe { YIELD_COUNT });
@@ -152,13 +171,10 @@ pub fn yield_cpu() -> Result<(), &'static str> {
         return Err("MSIP set failed");
     }
 
-    // Step 2: グローバル割り込み有効化
-    let was_enabled = csr::interrupts_enabled();
-    if !was_enabled {
+    // Step 2: グローバル割り込み有効化（ガードの破棄で元に戻る）
+    let guard = InterruptGuard::enabled();
+    if !guard.interrupts_were_enabled() {
         println!("Enabling global interrupts...");
-        unsafe {
-            csr::enable_global_interrupts();
-        }
     }
 
     // Step 3: 割り込み発生を待つ（短時間）
@@ -197,12 +213,10 @@ pub fn yield_cpu() -> Result<(), &'static str> {
     }
 
     // Step 4: グローバル割り込みを元に戻す
-    if !was_enabled {
+    if !guard.interrupts_were_enabled() {
         println!("Disabling global interrupts...");
-        unsafe {
-            csr::disable_global_interrupts();
-        }
     }
+    drop(guard);
 
     // Step 5: 最終状態確認
     if let Ok(final_msip) = read_msip_safe() {
@@ -223,32 +237,6 @@ pub fn yield_cpu() -> Result<(), &'static str> {
     }
 }
 
-/// ソフトウェア割り込みハンドラ（trap.rsから呼び出される）
-pub fn handle_software_interrupt() {
-    unsafe {
-        SW_INTERRUPT_COUNT += 1;
-        HANDLER_CALLS += 1;
-    }
-
-    // 非常に重要: 割り込みをクリアして無限ループを防ぐ
-    if clear_software_interrupt().is_ok() {
-        // ハンドラ実行の通知（簡潔に）
-        unsafe {
-            core::ptr::write_volatile(UART0, b'S');
-            core::ptr::write_volatile(UART0, b'\n');
-        }
-    } else {
-        // エラーの場合は最小限の出力
-        unsafe {
-            core::ptr::write_volatile(UART0, b'X'); // Error marker
-            core::ptr::write_volatile(UART0, b'\n');
-        }
-    }
-
-    // 将来ここにコンテキストスイッチロジックが入る
-    // 現在はシングルスレッドなので基本処理のみ
-}
-
 /// ソフトウェア割り込み機能の包括的テスト
 pub fn comprehensive_test() {
     println!("=== COMPREHENSIVE SOFTWARE INTERRUPT TEST ===");
@@ -293,14 +281,14 @@ fn test_interrupt_enables() {
     println_hex!("mie: ", mie);
 
     // Global interrupt enable
-    if (mstatus & (1 << 3)) != 0 {
+    if (mstatus & bits::STATUS_IE) != 0 {
         println!("✓ Global interrupts (MIE) enabled");
     } else {
         println!("⚠ Global interrupts (MIE) disabled");
     }
 
     // Software interrupt enable
-    if (mie & (1 << 3)) != 0 {
+    if (mie & bits::IE_SOFT) != 0 {
         println!("✓ Software interrupts (MSIE) enabled");
     } else {
         println!("✗ Software interrupts (MSIE) disabled");
@@ -379,35 +367,26 @@ fn test_stress_operations() {
 pub fn display_statistics() {
     println!("=== SOFTWARE INTERRUPT STATISTICS ===");
 
-    let stats = unsafe { (SW_INTERRUPT_COUNT, YIELD_COUNT, HANDLER_CALLS, MSIP_ERRORS) };
+    let (sw_interrupts, yields, errors) = get_statistics();
 
-    println_number!("Software interrupts handled: ", stats.0);
-    println_number!("Yield calls made: ", stats.1);
-    println_number!("Handler invocations: ", stats.2);
-    println_number!("MSIP errors: ", stats.3);
+    println_number!("Software interrupts handled: ", sw_interrupts);
+    println_number!("Yield calls made: ", yields);
+    println_number!("MSIP errors: ", errors);
 
     // エラー率の計算
-    if stats.2 > 0 {
-        let error_rate = (stats.3 * 100) / stats.2;
+    if sw_interrupts > 0 {
+        let error_rate = (errors * 100) / sw_interrupts;
         println_number!("Error rate: ", error_rate);
         print!("%");
         println!();
     }
 }
 
-/// 統計更新関数（trap handlerから呼ばれる）
-pub fn increment_sw_interrupt_count() {
-    unsafe {
-        SW_INTERRUPT_COUNT += 1;
-        HANDLER_CALLS += 1;
-    }
-}
-
 /// yield()の検証を緩和した版
 pub fn yield_cpu_relaxed() -> Result<(), &'static str> {
     unsafe {
         YIELD_COUNT += 1;
-        LAST_YIELD_TIME = SW_INTERRUPT_COUNT;
+        LAST_YIELD_TIME = sw_interrupt_count();
     }
 
     println_number!("yield() #", unsafe { YIELD_COUNT });
@@ -421,18 +400,15 @@ pub fn yield_cpu_relaxed() -> Result<(), &'static str> {
         return Err("MSIP set failed");
     }
 
-    // Step 2: グローバル割り込み有効化
-    let was_enabled = csr::interrupts_enabled();
-    if !was_enabled {
+    // Step 2: グローバル割り込み有効化（ガードの破棄で元に戻る）
+    let guard = InterruptGuard::enabled();
+    if !guard.interrupts_were_enabled() {
         println!("Enabling global interrupts...");
-        unsafe {
-            csr::enable_global_interrupts();
-        }
     }
 
     // Step 3: 割り込み処理を待つ（検証緩和版）
     println!("Waiting for interrupt...");
-    let initial_count = unsafe { SW_INTERRUPT_COUNT };
+    let initial_count = sw_interrupt_count();
 
     let mut wait_count = 0;
     let max_wait = 5000; // 短縮
@@ -445,7 +421,7 @@ pub fn yield_cpu_relaxed() -> Result<(), &'static str> {
 
         // 統計の変化をチェック（MSIPの状態ではなく）
         if wait_count % 1000 == 0 {
-            let current_count = unsafe { SW_INTERRUPT_COUNT };
+            let current_count = sw_interrupt_count();
             if current_count > initial_count {
                 println!("SW interrupt processed successfully");
                 break;
@@ -454,15 +430,13 @@ pub fn yield_cpu_relaxed() -> Result<(), &'static str> {
     }
 
     // Step 4: グローバル割り込みを元に戻す
-    if !was_enabled {
+    if !guard.interrupts_were_enabled() {
         println!("Disabling global interrupts...");
-        unsafe {
-            csr::disable_global_interrupts();
-        }
     }
+    drop(guard);
 
     // Step 5: 結果確認（緩和版）
-    let final_count = unsafe { SW_INTERRUPT_COUNT };
+    let final_count = sw_interrupt_count();
     if final_count > initial_count {
         println!("yield() completed successfully");
         Ok(())
@@ -472,9 +446,10 @@ pub fn yield_cpu_relaxed() -> Result<(), &'static str> {
     }
 }
 
-/// システム統計の取得
-pub fn get_statistics() -> (u64, u64, u64, u64) {
-    unsafe { (SW_INTERRUPT_COUNT, YIELD_COUNT, HANDLER_CALLS, MSIP_ERRORS) }
+/// システム統計の取得（全ハートのソフトウェア割り込み数、yield回数、MSIPエラー数）
+pub fn get_statistics() -> (u64, u64, u64) {
+    let sw_interrupts = stats::cause_count(bits::MCAUSE_INTERRUPT_BIT | bits::INTERRUPT_SW);
+    unsafe { (sw_interrupts, YIELD_COUNT, MSIP_ERRORS) }
 }
 
 /// 簡単なMSIP動作テスト
@@ -0,0 +1,324 @@
+// src/interrupt/deferred.rs
+// 遅延処理（ボトムハーフ）
+//
+// 割り込みハンドラはトラップコンテキストで最小限の処理だけを行い、残りを
+// WorkItemとしてハートごとのキューに積む（tasklet方式）。キューはロックフリーの
+// スタックで、トラップハンドラからも他のハートからも積める。
+//
+// キューは次のどちらかで実行される。
+// - トラップ出口: 最も外側のトラップで、割り込まれた側が割り込み許可状態かつ
+//   スピンロックを保持していなければ、割り込みを許可して実行する
+// - カーネルワーカー: アイドルループ（worker_loop）やrun_pendingの呼び出し
+//
+// どちらもトラップの処理を終えた後なので、遅延処理はロックの取得や
+// メモリ確保をしてよい。同じWorkItemが複数のハートで同時に実行されることはない。
+// 他のハートで実行中のWorkItemはそのハートのキューへ移し、再スケジュールの
+// IPIで知らせて、実行が終わった後にそのハートで実行させる。
+
+use super::guard::InterruptGuard;
+use crate::arch::csr::{self, bits};
+use crate::arch::current::MAX_HARTS;
+use crate::console::num;
+use crate::println;
+use crate::smp::{self, ipi::IpiMessage};
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+/// 1回の実行でキューを取り直す上限（残りはワーカーが実行する）
+const MAX_ROUNDS: usize = 10;
+
+/// キューに積まれている
+const STATE_QUEUED: usize = 1 << 0;
+
+/// いずれかのハートで実行中
+const STATE_RUNNING: usize = 1 << 1;
+
+/// 実行中のハート番号の位置（STATE_RUNNINGと同時に書く）
+const STATE_OWNER_SHIFT: u32 = 8;
+
+/// 遅延処理の関数（引数はWorkItem作成時に渡した値）
+pub type WorkFn = fn(data: usize);
+
+/// 遅延処理の単位
+///
+/// staticに置いて繰り返しscheduleする。実行前に何度scheduleしても
+/// 実行は1回にまとまる。
+pub struct WorkItem {
+    func: WorkFn,
+    data: usize,
+    state: AtomicUsize,
+    next: AtomicPtr<WorkItem>,
+    runs: AtomicU64,
+}
+
+impl WorkItem {
+    /// 遅延処理を作成
+    pub const fn new(func: WorkFn, data: usize) -> Self {
+        Self {
+            func,
+            data,
+            state: AtomicUsize::new(0),
+            next: AtomicPtr::new(core::ptr::null_mut()),
+            runs: AtomicU64::new(0),
+        }
+    }
+
+    /// 実行中ハートのキューに積む
+    ///
+    /// # Returns
+    /// 積んだ場合true、すでに積まれている場合false
+    pub fn schedule(&'static self) -> bool {
+        self.schedule_on(current_hart())
+    }
+
+    /// 指定したハートのキューに積む
+    ///
+    /// 他のハートに積んだ場合は再スケジュールのIPIで起こす。
+    ///
+    /// # Returns
+    /// 積んだ場合true、すでに積まれているかハートがオンラインでなければfalse
+    pub fn schedule_on(&'static self, hart: usize) -> bool {
+        let Some(queue) = QUEUES.get(hart) else {
+            return false;
+        };
+        if !smp::is_online(hart) {
+            return false;
+        }
+        if self.state.fetch_or(STATE_QUEUED, Ordering::AcqRel) & STATE_QUEUED != 0 {
+            return false;
+        }
+
+        queue.push(self);
+        queue.scheduled.fetch_add(1, Ordering::Relaxed);
+
+        if hart != current_hart() {
+            let _ = smp::ipi::send_ipi(hart, IpiMessage::Reschedule);
+        }
+        true
+    }
+
+    /// キューに積まれていてまだ実行されていないか
+    pub fn is_pending(&self) -> bool {
+        self.state.load(Ordering::Acquire) & STATE_QUEUED != 0
+    }
+
+    /// 実行された回数
+    pub fn run_count(&self) -> u64 {
+        self.runs.load(Ordering::Relaxed)
+    }
+}
+
+/// ハート1つ分の遅延処理キュー
+struct WorkQueue {
+    /// 積まれたWorkItemのスタック（新しいものが先頭）
+    head: AtomicPtr<WorkItem>,
+    /// このハートで実行中（トラップ出口での再入防止）
+    draining: AtomicBool,
+    scheduled: AtomicU64,
+    trap_exit_runs: AtomicU64,
+    worker_runs: AtomicU64,
+}
+
+impl WorkQueue {
+    const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(core::ptr::null_mut()),
+            draining: AtomicBool::new(false),
+            scheduled: AtomicU64::new(0),
+            trap_exit_runs: AtomicU64::new(0),
+            worker_runs: AtomicU64::new(0),
+        }
+    }
+
+    fn push(&self, item: &'static WorkItem) {
+        let item_ptr = item as *const WorkItem as *mut WorkItem;
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            item.next.store(head, Ordering::Relaxed);
+            match self.head.compare_exchange_weak(
+                head,
+                item_ptr,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+
+    /// 積まれているWorkItemをすべて取り出し、積まれた順に並べ替える
+    fn take_all(&self) -> *mut WorkItem {
+        let mut item = self.head.swap(core::ptr::null_mut(), Ordering::Acquire);
+        let mut ordered = core::ptr::null_mut();
+        while let Some(work) = unsafe { item.as_ref() } {
+            item = work.next.swap(ordered, Ordering::Relaxed);
+            ordered = work as *const WorkItem as *mut WorkItem;
+        }
+        ordered
+    }
+
+    /// キューが空になるまで実行する
+    ///
+    /// # Returns
+    /// 実行したWorkItemの数
+    fn drain(&self) -> u64 {
+        if self.draining.swap(true, Ordering::Acquire) {
+            return 0;
+        }
+
+        let mut count = 0;
+        for _ in 0..MAX_ROUNDS {
+            let mut item = self.take_all();
+            if item.is_null() {
+                break;
+            }
+            while let Some(work) = unsafe { item.as_ref() } {
+                // 実行中に再び積まれるとnextが書き換わるので先に読む
+                item = work.next.load(Ordering::Relaxed);
+                if self.run(work) {
+                    count += 1;
+                }
+            }
+        }
+
+        self.draining.store(false, Ordering::Release);
+        count
+    }
+
+    /// WorkItemを1つ実行する
+    ///
+    /// # Returns
+    /// 実行した場合true、他のハートで実行中のためそちらへ移した場合false
+    fn run(&self, work: &'static WorkItem) -> bool {
+        let hart = current_hart();
+        let mut state = work.state.load(Ordering::Acquire);
+        loop {
+            if state & STATE_RUNNING != 0 {
+                forward(work, state >> STATE_OWNER_SHIFT);
+                return false;
+            }
+            let running = state | STATE_RUNNING | (hart << STATE_OWNER_SHIFT);
+            match work.state.compare_exchange_weak(
+                state,
+                running,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(current) => state = current,
+            }
+        }
+
+        // 実行中に積まれた場合はもう一度実行されるよう、先に印を外す
+        work.state.fetch_and(!STATE_QUEUED, Ordering::AcqRel);
+        (work.func)(work.data);
+        work.runs.fetch_add(1, Ordering::Relaxed);
+        work.state.fetch_and(STATE_QUEUED, Ordering::Release);
+        true
+    }
+}
+
+/// 他のハートで実行中のWorkItemをそのハートのキューへ移す
+///
+/// 実行中のハートは今の実行を終えた後にキューを取り直すか、IPIで起きて
+/// 実行する。このハートのキューに積み直すと、このハートがwfiで止まっている
+/// 間は実行されないまま残ることがある。
+fn forward(work: &'static WorkItem, owner: usize) {
+    let Some(queue) = QUEUES.get(owner) else {
+        return;
+    };
+    queue.push(work);
+    let _ = smp::ipi::send_ipi(owner, IpiMessage::Reschedule);
+}
+
+static QUEUES: [WorkQueue; MAX_HARTS] = [const { WorkQueue::new() }; MAX_HARTS];
+
+fn current_hart() -> usize {
+    csr::read_mhartid() as usize
+}
+
+/// トラップ出口での遅延処理（最も外側のトラップハンドラの最後に呼ばれる）
+///
+/// 割り込まれた側が割り込み禁止中だった場合やスピンロックを保持している場合は
+/// 何もせず、ワーカーに任せる。
+///
+/// # Arguments
+/// * `interrupted_status` - 割り込まれた側のmstatus/sstatus（トラップフレームの値）
+pub fn run_on_trap_exit(interrupted_status: usize) {
+    if interrupted_status & bits::STATUS_PIE == 0 || crate::sync::held_locks() != 0 {
+        return;
+    }
+    let Some(queue) = QUEUES.get(current_hart()) else {
+        return;
+    };
+    if queue.is_empty() {
+        return;
+    }
+
+    // 遅延処理の間は割り込みを受け付ける（mstatusはトラップ出口でフレームから戻る）
+    let _interrupts = InterruptGuard::enabled();
+    let count = queue.drain();
+    queue.trap_exit_runs.fetch_add(count, Ordering::Relaxed);
+}
+
+/// 実行中ハートのキューを実行する（カーネルワーカー）
+///
+/// スピンロックを保持していない通常のコンテキストから呼ぶこと。
+///
+/// # Returns
+/// 実行したWorkItemの数
+pub fn run_pending() -> u64 {
+    let Some(queue) = QUEUES.get(current_hart()) else {
+        return 0;
+    };
+    if queue.is_empty() {
+        return 0;
+    }
+
+    let count = queue.drain();
+    queue.worker_runs.fetch_add(count, Ordering::Relaxed);
+    count
+}
+
+/// 遅延処理を実行しながら割り込みを待つ（アイドルループ）
+///
+/// キューに残りがあるうちはwfiで止まらない。wfiの直前に積まれた場合は
+/// 再スケジュールのIPIで起きる。
+pub fn worker_loop() -> ! {
+    loop {
+        run_pending();
+        let pending = QUEUES
+            .get(current_hart())
+            .is_some_and(|queue| !queue.is_empty());
+        if !pending {
+            unsafe {
+                core::arch::asm!("wfi");
+            }
+        }
+    }
+}
+
+/// ハートごとの遅延処理の統計を表示
+pub fn show_status() {
+    println!("=== Deferred Work ===");
+    for (hart, queue) in QUEUES.iter().enumerate() {
+        let scheduled = queue.scheduled.load(Ordering::Relaxed);
+        if scheduled == 0 {
+            continue;
+        }
+        println!(
+            "  hart {}: scheduled {}, run at trap exit {}, run by worker {}",
+            num(hart as u64),
+            num(scheduled),
+            num(queue.trap_exit_runs.load(Ordering::Relaxed)),
+            num(queue.worker_runs.load(Ordering::Relaxed))
+        );
+        if !queue.is_empty() {
+            println!("    work pending");
+        }
+    }
+}
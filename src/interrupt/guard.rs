@@ -0,0 +1,155 @@
+// src/interrupt/guard.rs
+// 割り込み禁止区間（RAIIガードとクリティカルセクション）
+//
+// InterruptGuardは作成時にグローバル割り込み（MSTATUS.MIE / SSTATUS.SIE）を
+// 禁止し、破棄時に作成前の状態へ戻す。入れ子の内側のガードは「禁止中」を
+// 保存するだけなので、最も外側のガードが破棄されたときにだけ割り込みが
+// 再開される。
+//
+// ガードは実行中のハートだけに作用する。ハート間の排他が必要な場合は
+// critical-sectionクレートのインターフェース（critical_section::with）を使う。
+// こちらは割り込み禁止に加えて、全ハートで共有する再帰ロックを取る。
+
+use crate::arch::csr;
+use crate::arch::current::MAX_HARTS;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// ハートごとの割り込み禁止ガードの入れ子の深さ
+static NESTING: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+fn current_hart() -> usize {
+    csr::read_mhartid() as usize
+}
+
+/// 割り込み状態を保存して変更し、破棄時に元へ戻すガード
+///
+/// 作成したハートで破棄する必要があるため、Sendではない
+pub struct InterruptGuard {
+    was_enabled: bool,
+    disabling: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl InterruptGuard {
+    /// 割り込みを禁止する（破棄時に元の状態へ戻す）
+    pub fn new() -> Self {
+        let was_enabled = unsafe { csr::disable_interrupts_save() };
+        if let Some(depth) = NESTING.get(current_hart()) {
+            depth.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Self {
+            was_enabled,
+            disabling: true,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// 割り込みを一時的に許可する（破棄時に元の状態へ戻す）
+    ///
+    /// 割り込み禁止ガードの内側では禁止区間を壊さないよう何もしない
+    pub fn enabled() -> Self {
+        let was_enabled = if nesting_depth() == 0 {
+            unsafe { csr::enable_interrupts_save() }
+        } else {
+            csr::interrupts_enabled()
+        };
+
+        Self {
+            was_enabled,
+            disabling: false,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// ガード作成前に割り込みが許可されていたか
+    pub fn interrupts_were_enabled(&self) -> bool {
+        self.was_enabled
+    }
+}
+
+impl Default for InterruptGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if self.disabling {
+            if let Some(depth) = NESTING.get(current_hart()) {
+                depth.fetch_sub(1, Ordering::Relaxed);
+            }
+        } else if nesting_depth() > 0 {
+            // 禁止区間の内側で作られたガードは何も変更していない
+            return;
+        }
+
+        unsafe {
+            csr::restore_interrupts(self.was_enabled);
+        }
+    }
+}
+
+/// 割り込みを禁止してクロージャを実行する
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let _guard = InterruptGuard::new();
+    f()
+}
+
+/// 割り込みを許可してクロージャを実行する（禁止区間の内側では禁止のまま）
+pub fn with_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let _guard = InterruptGuard::enabled();
+    f()
+}
+
+/// 実行中のハートの割り込み禁止ガードの深さ（0なら禁止区間の外）
+pub fn nesting_depth() -> usize {
+    NESTING
+        .get(current_hart())
+        .map_or(0, |depth| depth.load(Ordering::Relaxed))
+}
+
+/// ロック未保持を表すオーナー値
+const NO_OWNER: usize = usize::MAX;
+
+/// critical-sectionを保持しているハート
+static CS_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+
+/// critical-sectionの再帰の深さ（オーナーのハートだけが更新する）
+static CS_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// critical-sectionクレートの実装（割り込み禁止＋ハート間の再帰ロック）
+struct KernelCriticalSection;
+
+critical_section::set_impl!(KernelCriticalSection);
+
+unsafe impl critical_section::Impl for KernelCriticalSection {
+    unsafe fn acquire() -> critical_section::RawRestoreState {
+        let was_enabled = csr::disable_interrupts_save();
+        let hart = current_hart();
+
+        if CS_OWNER.load(Ordering::Relaxed) == hart {
+            // 同じハートからの入れ子
+            CS_DEPTH.fetch_add(1, Ordering::Relaxed);
+        } else {
+            while CS_OWNER
+                .compare_exchange_weak(NO_OWNER, hart, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                core::hint::spin_loop();
+            }
+            CS_DEPTH.store(1, Ordering::Relaxed);
+        }
+
+        was_enabled
+    }
+
+    unsafe fn release(was_enabled: critical_section::RawRestoreState) {
+        if CS_DEPTH.fetch_sub(1, Ordering::Relaxed) == 1 {
+            CS_OWNER.store(NO_OWNER, Ordering::Release);
+        }
+        csr::restore_interrupts(was_enabled);
+    }
+}
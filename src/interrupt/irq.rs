@@ -0,0 +1,347 @@
+// src/interrupt/irq.rs
+// 外部割り込み（PLIC）のハンドラ登録とディスパッチ
+//
+// ドライバはrequest_irqでPLICのソースにハンドラを登録する。
+// 同じソースに複数のハンドラを登録でき（共有IRQ）、割り込み時は登録順に
+// 呼び出す。各ハンドラは自分のデバイスが要因だったかをIrqReturnで返す。
+//
+// 登録・解除とPLICの配送設定はIRQ_LOCKで直列化する。ディスパッチは
+// ロックを取らず、公開済みのハンドラだけを読む。free_irqはディスパッチ中の
+// ハートがなくなるまで待ってから戻るので、解除後にドライバの状態を破棄してよい。
+
+use super::guard::InterruptGuard;
+use crate::arch::current::plic::MAX_PRIORITY;
+use crate::arch::current::{MAX_HARTS, PLIC};
+use crate::console::{hex, num};
+use crate::println;
+use crate::sync::SpinLock;
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+/// 管理できるIRQ番号の上限（QEMU virtのPLICは1〜95）
+pub const MAX_IRQS: usize = 128;
+
+/// 1つのIRQを共有できるハンドラ数
+pub const MAX_SHARED_HANDLERS: usize = 4;
+
+/// ハンドラの戻り値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    /// 自分のデバイスの割り込みだった（処理済み）
+    Handled,
+    /// 自分のデバイスの割り込みではなかった
+    NotMine,
+}
+
+/// IRQハンドラ（引数はIRQ番号、トラップコンテキストで呼ばれる）
+pub type IrqHandler = fn(irq: u32) -> IrqReturn;
+
+/// IRQ登録エラー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// PLICに存在しないIRQ番号
+    InvalidIrq,
+    /// 優先度が1〜MAX_PRIORITYの範囲外
+    InvalidPriority,
+    /// ハートマスクが空、または存在しないハートを含む
+    InvalidHartMask,
+    /// 同じハンドラが既に登録されている
+    AlreadyRegistered,
+    /// 共有ハンドラの枠が一杯
+    TooManyHandlers,
+    /// 登録されていないハンドラ
+    NotRegistered,
+}
+
+impl IrqError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IrqError::InvalidIrq => "Invalid IRQ number",
+            IrqError::InvalidPriority => "Invalid IRQ priority",
+            IrqError::InvalidHartMask => "Invalid hart mask",
+            IrqError::AlreadyRegistered => "Handler already registered",
+            IrqError::TooManyHandlers => "Too many handlers for IRQ",
+            IrqError::NotRegistered => "Handler not registered",
+        }
+    }
+}
+
+impl core::fmt::Display for IrqError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// IRQごとの統計
+#[derive(Debug, Clone, Copy)]
+pub struct IrqStats {
+    /// ディスパッチした回数
+    pub count: u64,
+    /// どのハンドラも処理しなかった回数
+    pub unhandled: u64,
+    /// 登録されているハンドラ数
+    pub handlers: usize,
+}
+
+/// 登録スロット（ハンドラのアドレス、0は空き）
+struct IrqAction {
+    handler: AtomicUsize,
+    priority: AtomicU32,
+    hart_mask: AtomicUsize,
+}
+
+impl IrqAction {
+    const fn new() -> Self {
+        Self {
+            handler: AtomicUsize::new(0),
+            priority: AtomicU32::new(0),
+            hart_mask: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// IRQ 1つ分の状態
+struct IrqDesc {
+    actions: [IrqAction; MAX_SHARED_HANDLERS],
+    /// ディスパッチ中のハート数
+    in_flight: AtomicUsize,
+    count: AtomicU64,
+    unhandled: AtomicU64,
+}
+
+impl IrqDesc {
+    const fn new() -> Self {
+        Self {
+            actions: [const { IrqAction::new() }; MAX_SHARED_HANDLERS],
+            in_flight: AtomicUsize::new(0),
+            count: AtomicU64::new(0),
+            unhandled: AtomicU64::new(0),
+        }
+    }
+}
+
+static IRQ_TABLE: [IrqDesc; MAX_IRQS] = [const { IrqDesc::new() }; MAX_IRQS];
+
+/// 登録・解除とPLICの配送設定の排他（ディスパッチは取らない）
+static IRQ_LOCK: SpinLock<()> = SpinLock::new(());
+
+/// ハンドラが1つもないIRQの発生回数
+static SPURIOUS_IRQS: AtomicU64 = AtomicU64::new(0);
+
+fn desc(irq: u32) -> Result<&'static IrqDesc, IrqError> {
+    if irq == 0 || irq > PLIC.source_count() {
+        return Err(IrqError::InvalidIrq);
+    }
+    IRQ_TABLE.get(irq as usize).ok_or(IrqError::InvalidIrq)
+}
+
+/// 有効なハートを表すビット
+fn valid_hart_bits() -> usize {
+    (1 << MAX_HARTS) - 1
+}
+
+/// IRQにハンドラを登録し、PLICで有効化する
+///
+/// 共有IRQでは、PLICの優先度は登録中のハンドラの最大値、
+/// 配送先は全ハンドラのハートマスクの和になる。
+/// 配送先ハートでは外部割り込み（MEIE/SEIE）を有効にしておくこと。
+pub fn request_irq(
+    irq: u32,
+    handler: IrqHandler,
+    priority: u32,
+    hart_mask: usize,
+) -> Result<(), IrqError> {
+    let desc = desc(irq)?;
+    if priority == 0 || priority > MAX_PRIORITY {
+        return Err(IrqError::InvalidPriority);
+    }
+    if hart_mask == 0 || hart_mask & !valid_hart_bits() != 0 {
+        return Err(IrqError::InvalidHartMask);
+    }
+
+    let addr = handler as usize;
+    // IRQハンドラから呼ばれても自分のハートでデッドロックしないよう割り込みを止める
+    let _interrupts = InterruptGuard::new();
+    let _registry = IRQ_LOCK.lock();
+
+    if desc
+        .actions
+        .iter()
+        .any(|action| action.handler.load(Ordering::Relaxed) == addr)
+    {
+        return Err(IrqError::AlreadyRegistered);
+    }
+
+    let Some(action) = desc
+        .actions
+        .iter()
+        .find(|action| action.handler.load(Ordering::Relaxed) == 0)
+    else {
+        return Err(IrqError::TooManyHandlers);
+    };
+
+    // ディスパッチはロックを取らないので、設定を書いてからハンドラを公開する
+    action.priority.store(priority, Ordering::Relaxed);
+    action.hart_mask.store(hart_mask, Ordering::Relaxed);
+    action.handler.store(addr, Ordering::Release);
+    update_routing(irq, desc);
+    Ok(())
+}
+
+/// IRQからハンドラを外す（最後のハンドラならPLICで無効化する）
+///
+/// 他のハートで実行中のハンドラが終わるまで待ってから戻る。そのため、
+/// このIRQのハンドラの中から呼んではならない。
+pub fn free_irq(irq: u32, handler: IrqHandler) -> Result<(), IrqError> {
+    let desc = desc(irq)?;
+    let addr = handler as usize;
+    {
+        let _interrupts = InterruptGuard::new();
+        let _registry = IRQ_LOCK.lock();
+
+        let Some(action) = desc
+            .actions
+            .iter()
+            .find(|action| action.handler.load(Ordering::Relaxed) == addr)
+        else {
+            return Err(IrqError::NotRegistered);
+        };
+
+        action.handler.store(0, Ordering::Release);
+        update_routing(irq, desc);
+    }
+
+    // 外したハンドラを読んだかもしれないディスパッチの終了を待つ
+    // （ハンドラがmask_irqでIRQ_LOCKを取れるよう、ロックの外で待つ）
+    fence(Ordering::SeqCst);
+    while desc.in_flight.load(Ordering::Acquire) != 0 {
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+/// 登録中のハンドラからPLICの優先度と配送先を決め直す
+///
+/// PLICのイネーブルワードを読み書きするので、IRQ_LOCKを保持して呼ぶこと。
+fn update_routing(irq: u32, desc: &IrqDesc) {
+    let mut priority = 0;
+    let mut hart_mask = 0;
+    for action in desc.actions.iter() {
+        if action.handler.load(Ordering::Relaxed) != 0 {
+            priority = priority.max(action.priority.load(Ordering::Relaxed));
+            hart_mask |= action.hart_mask.load(Ordering::Relaxed);
+        }
+    }
+
+    let _ = PLIC.set_priority(irq, priority);
+    for hart in 0..MAX_HARTS {
+        if hart_mask & (1 << hart) != 0 {
+            let _ = PLIC.enable_source(hart, irq);
+        } else {
+            let _ = PLIC.disable_source(hart, irq);
+        }
+    }
+}
+
+/// ハンドラのないIRQをハートで無効化する（割り込みの嵐を防ぐ）
+///
+/// トラップコンテキストから呼べる。IRQ_LOCKは割り込み禁止で保持されるため、
+/// 同じハートで保持中に割り込まれることはない。
+pub fn mask_irq(hart: usize, irq: u32) {
+    let _registry = IRQ_LOCK.lock();
+    let _ = PLIC.disable_source(hart, irq);
+}
+
+/// 外部割り込みのディスパッチ（トラップハンドラがclaim後に呼ぶ）
+///
+/// 登録済みの全ハンドラを呼び、ハンドラが1つも無ければfalseを返す
+pub fn handle_irq(irq: u32) -> bool {
+    let Ok(desc) = desc(irq) else {
+        SPURIOUS_IRQS.fetch_add(1, Ordering::Relaxed);
+        return false;
+    };
+
+    // free_irqがハンドラを外した後に読んでいないことを保証する
+    desc.in_flight.fetch_add(1, Ordering::Relaxed);
+    fence(Ordering::SeqCst);
+
+    let mut registered = false;
+    let mut handled = false;
+    for action in desc.actions.iter() {
+        let addr = action.handler.load(Ordering::Acquire);
+        if addr == 0 {
+            continue;
+        }
+        registered = true;
+
+        let handler: IrqHandler = unsafe { core::mem::transmute(addr) };
+        if handler(irq) == IrqReturn::Handled {
+            handled = true;
+        }
+    }
+
+    desc.in_flight.fetch_sub(1, Ordering::Release);
+
+    if !registered {
+        SPURIOUS_IRQS.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+
+    desc.count.fetch_add(1, Ordering::Relaxed);
+    if !handled {
+        desc.unhandled.fetch_add(1, Ordering::Relaxed);
+    }
+    true
+}
+
+/// IRQの統計を取得
+pub fn irq_stats(irq: u32) -> Option<IrqStats> {
+    let desc = desc(irq).ok()?;
+    Some(IrqStats {
+        count: desc.count.load(Ordering::Relaxed),
+        unhandled: desc.unhandled.load(Ordering::Relaxed),
+        handlers: desc
+            .actions
+            .iter()
+            .filter(|action| action.handler.load(Ordering::Relaxed) != 0)
+            .count(),
+    })
+}
+
+/// ハンドラのないIRQの発生回数
+pub fn spurious_count() -> u64 {
+    SPURIOUS_IRQS.load(Ordering::Relaxed)
+}
+
+/// 登録済みIRQと統計を表示
+pub fn show_irqs() {
+    println!("=== IRQ Handlers ===");
+    for irq in 1..=PLIC.source_count() {
+        let Some(stats) = irq_stats(irq) else {
+            continue;
+        };
+        if stats.handlers == 0 && stats.count == 0 {
+            continue;
+        }
+        println!(
+            "  IRQ {}: {} handler(s), priority {}, count {}, unhandled {}",
+            num(irq as u64),
+            num(stats.handlers as u64),
+            num(PLIC.priority(irq) as u64),
+            num(stats.count),
+            num(stats.unhandled)
+        );
+        if let Ok(desc) = desc(irq) {
+            for action in desc.actions.iter() {
+                let addr = action.handler.load(Ordering::Relaxed);
+                if addr != 0 {
+                    println!(
+                        "    handler {} harts {}",
+                        hex(addr),
+                        hex(action.hart_mask.load(Ordering::Relaxed))
+                    );
+                }
+            }
+        }
+    }
+    println!("Spurious IRQs: {}", num(spurious_count()));
+}
@@ -1,28 +1,49 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
 #[macro_use]
 mod console;
 
 mod arch;
+mod debug;
+mod fdt;
 mod interrupt;
+mod memory;
 mod msip_debug;
 mod panic;
+mod smp;
+mod sync;
 mod trap;
 
+/// Default UART0 transmit register (QEMU virt); the console uses the
+/// address discovered by `arch::current::platform` instead
 pub const UART0: *mut u8 = 0x1000_0000 as *mut u8;
 
 use crate::arch::{
+    csr::bits,
+    current::platform,
     current::timer::{system, test, utils, CLINT_TIMER},
     Timer,
 };
 use crate::console::{hex, num, str};
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 #[unsafe(no_mangle)]
-pub extern "C" fn rust_main() -> ! {
+pub extern "C" fn rust_main(hartid: usize, dtb: usize) -> ! {
+    // デバイスツリーからプラットフォーム情報を取得（失敗時はQEMU virtの既定値）
+    let platform_result = platform::init(dtb);
+
+    smp::init_primary(hartid);
+
     println!("RISC-V Unikernel with Unified HAL Timer System");
 
+    // Phase 0: Platform discovery
+    println!("\n=== PHASE 0: PLATFORM DISCOVERY ===");
+    show_platform(platform_result);
+
     // Phase 1: Basic system initialization
     println!("\n=== PHASE 1: BASIC TESTS ===");
     basic_tests();
@@ -35,14 +56,58 @@ pub extern "C" fn rust_main() -> ! {
     println!("\n=== PHASE 2.5: HAL SYSTEM TEST ===");
     test_hal_system();
 
+    // Phase 2.6: Secondary hart bring-up
+    println!("\n=== PHASE 2.6: SECONDARY HART BRING-UP ===");
+    start_secondary_harts(hartid);
+
+    // Phase 2.7: Physical frame allocator
+    println!("\n=== PHASE 2.7: PHYSICAL FRAME ALLOCATOR ===");
+    test_frame_allocator();
+
+    // Phase 2.8: Kernel heap
+    println!("\n=== PHASE 2.8: KERNEL HEAP ===");
+    test_kernel_heap();
+
+    // Phase 2.9: Virtual memory
+    println!("\n=== PHASE 2.9: VIRTUAL MEMORY (Sv39) ===");
+    test_virtual_memory();
+
     // Phase 3: Safe trap initialization
     println!("\n=== PHASE 3: SAFE TRAP INITIALIZATION ===");
     initialize_trap_system();
 
+    // Phase 3.5: Memory protection
+    println!("\n=== PHASE 3.5: MEMORY PROTECTION ===");
+    enable_memory_protection();
+
     // Phase 4: Safe ecall test
     println!("\n=== PHASE 4: SAFE ECALL TEST ===");
     test_ecall_functionality();
 
+    // Phase 4.5: Trap handler registry
+    println!("\n=== PHASE 4.5: TRAP HANDLER REGISTRY ===");
+    test_trap_registry();
+
+    // Phase 4.6: Fault reports
+    println!("\n=== PHASE 4.6: FAULT REPORTS ===");
+    test_fault_reports();
+
+    // Phase 4.7: Misaligned access emulation
+    println!("\n=== PHASE 4.7: MISALIGNED ACCESS EMULATION ===");
+    test_misaligned_access();
+
+    // Phase 4.8: Illegal instruction emulation
+    println!("\n=== PHASE 4.8: ILLEGAL INSTRUCTION EMULATION ===");
+    test_instruction_emulation();
+
+    // Phase 4.9: Fault-tolerant memory probing
+    println!("\n=== PHASE 4.9: SAFE MEMORY PROBING ===");
+    test_memory_probe();
+
+    // Phase 4.10: Kernel breakpoints
+    println!("\n=== PHASE 4.10: KERNEL BREAKPOINTS ===");
+    test_kernel_breakpoints();
+
     // Phase 5: System stability check
     println!("\n=== PHASE 5: SYSTEM STABILITY CHECK ===");
     test_system_stability();
@@ -59,10 +124,34 @@ pub extern "C" fn rust_main() -> ! {
     println!("\n=== PHASE 8: SOFTWARE INTERRUPT SYSTEM ===");
     test_software_interrupt_system();
 
+    // Phase 8.1: Interrupt guards
+    println!("\n=== PHASE 8.1: INTERRUPT GUARDS ===");
+    test_interrupt_guards();
+
+    // Phase 8.2: Trap vector modes
+    println!("\n=== PHASE 8.2: TRAP VECTOR MODES ===");
+    test_trap_vector_modes();
+
+    // Phase 8.3: Platform-level interrupt controller
+    println!("\n=== PHASE 8.3: PLATFORM-LEVEL INTERRUPT CONTROLLER ===");
+    test_plic();
+
+    // Phase 8.4: IRQ registration
+    println!("\n=== PHASE 8.4: IRQ REGISTRATION ===");
+    test_irq_registration();
+
     // Phase 8.5: Unified timer system
     println!("\n=== PHASE 8.5: UNIFIED TIMER SYSTEM ===");
     test_unified_timer_system();
 
+    // Phase 8.6: Inter-processor interrupts
+    println!("\n=== PHASE 8.6: INTER-PROCESSOR INTERRUPTS ===");
+    test_ipi_messages();
+
+    // Phase 8.7: Deferred work
+    println!("\n=== PHASE 8.7: DEFERRED WORK ===");
+    test_deferred_work();
+
     // Phase 9: Simple yield test
     println!("\n=== PHASE 9: SIMPLE YIELD TEST ===");
     test_yield_functionality();
@@ -134,9 +223,9 @@ fn analyze_csr_state() {
     println!("  mepc: {}", hex(mepc));
 
     // Analyze mstatus bits
-    let mie_bit = (mstatus >> 3) & 1;
-    let mpie_bit = (mstatus >> 7) & 1;
-    let mpp_bits = (mstatus >> 11) & 3;
+    let mie_bit = ((mstatus & bits::STATUS_IE) != 0) as usize;
+    let mpie_bit = ((mstatus & bits::STATUS_PIE) != 0) as usize;
+    let mpp_bits = (mstatus >> bits::STATUS_PP_SHIFT) & bits::STATUS_PP_MASK;
 
     println!("mstatus analysis:");
     println!("  MIE: {}", num(mie_bit as u64));
@@ -187,11 +276,9 @@ fn test_csr_abstraction() {
     }
 
     // Test bit field operations
-    use arch::current::csr::bits;
-
-    let global_ie = (mstatus_val & bits::MSTATUS_MIE) != 0;
-    let timer_ie = (mie_val & bits::MIE_MTIE) != 0;
-    let sw_ie = (mie_val & bits::MIE_MSIE) != 0;
+    let global_ie = (mstatus_val & bits::STATUS_IE) != 0;
+    let timer_ie = (mie_val & bits::IE_TIMER) != 0;
+    let sw_ie = (mie_val & bits::IE_SOFT) != 0;
 
     println!("Interrupt enable status:");
     println!(
@@ -208,6 +295,224 @@ fn test_csr_abstraction() {
     );
 }
 
+/// Report the result of devicetree discovery
+fn show_platform(result: Result<(), fdt::FdtError>) {
+    match result {
+        Ok(()) => println!("✓ Devicetree parsed"),
+        Err(e) => {
+            print!("⚠ Devicetree unavailable: ");
+            println!(e.as_str());
+            println!("Falling back to built-in QEMU virt defaults");
+        }
+    }
+
+    platform::show_boot_info();
+
+    #[cfg(feature = "smode")]
+    arch::current::sbi::show_info();
+}
+
+/// Release parked secondary harts
+fn start_secondary_harts(primary: usize) {
+    if cfg!(feature = "smode") {
+        println!("Starting secondary harts via SBI HSM...");
+    } else {
+        println!("Releasing secondary harts via CLINT MSIP...");
+    }
+
+    let hart_mask = platform::boot_info().hart_mask;
+    let started = smp::start_secondary_harts(primary, hart_mask);
+    println!("Secondary harts started: {}", num(started as u64));
+
+    smp::show_status();
+    println!("✓ Secondary hart bring-up completed");
+}
+
+/// Initialize and exercise the physical frame allocator
+fn test_frame_allocator() {
+    use crate::memory::frame;
+
+    memory::show_layout();
+
+    println!("Initializing frame allocator...");
+    if let Err(e) = frame::init() {
+        print!("✗ Frame allocator initialization failed: ");
+        println!(e.as_str());
+        return;
+    }
+    frame::show_stats();
+
+    // 単一フレームの割り当てと解放
+    match frame::alloc_frame() {
+        Ok(addr) => {
+            println!("Allocated frame at {}", hex(addr));
+            if frame::free_frame(addr).is_ok() && frame::free_frame(addr).is_err() {
+                println!("✓ Single frame alloc/free (double free rejected)");
+            } else {
+                println!("✗ Single frame free failed");
+            }
+        }
+        Err(e) => {
+            print!("✗ Single frame allocation failed: ");
+            println!(e.as_str());
+        }
+    }
+
+    // 連続フレームの割り当て（2MiB境界に揃えた16フレーム）
+    match frame::alloc_frames_aligned(16, 512) {
+        Ok(addr) => {
+            println!("Allocated 16 contiguous frames at {}", hex(addr));
+            if addr % (512 * frame::FRAME_SIZE) == 0 && frame::free_frames(addr, 16).is_ok() {
+                println!("✓ Contiguous aligned alloc/free");
+            } else {
+                println!("✗ Contiguous allocation misaligned or free failed");
+            }
+        }
+        Err(e) => {
+            print!("✗ Contiguous allocation failed: ");
+            println!(e.as_str());
+        }
+    }
+
+    // 予約済みフレーム（カーネルイメージ）は解放できない
+    let kernel_frame = memory::layout().text.start / frame::FRAME_SIZE * frame::FRAME_SIZE;
+    if frame::free_frame(kernel_frame) == Err(frame::FrameError::Reserved) {
+        println!("✓ Freeing a reserved frame rejected");
+    } else {
+        println!("✗ Reserved frame was freed");
+    }
+
+    debug::show_memory_info();
+    println!("✓ Frame allocator test completed");
+}
+
+/// Initialize the kernel heap and exercise the `alloc` collections
+fn test_kernel_heap() {
+    use crate::memory::heap;
+    use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+
+    println!("Initializing kernel heap...");
+    if let Err(e) = heap::init() {
+        print!("✗ Kernel heap initialization failed: ");
+        println!(e.as_str());
+        return;
+    }
+
+    let boxed = Box::new(0x1234_5678usize);
+    println!("Box value: {}", hex(*boxed));
+
+    let mut vec = Vec::new();
+    for i in 0..100u64 {
+        vec.push(i * i);
+    }
+    let sum: u64 = vec.iter().sum();
+    println!("Vec sum of squares: {}", num(sum));
+
+    let mut string = String::from("substrix");
+    string.push_str(" heap");
+    print!("String: ");
+    println!(string.as_str());
+
+    let mut map = BTreeMap::new();
+    for hart in 0..4usize {
+        map.insert(hart, hart * 0x1000);
+    }
+    println!("BTreeMap entries: {}", num(map.len() as u64));
+
+    // 大きな割り当てでヒープを拡張
+    let large: Vec<u8> = Vec::with_capacity(512 * 1024);
+    println!("Large allocation: {} bytes", num(large.capacity() as u64));
+
+    let ok = *boxed == 0x1234_5678 && sum == 328350 && string.len() == 13 && map[&3] == 0x3000;
+    drop((boxed, vec, string, map, large));
+
+    heap::show_stats();
+    if ok && heap::stats().in_use == 0 {
+        println!("✓ Kernel heap test completed");
+    } else {
+        println!("✗ Kernel heap test failed");
+    }
+}
+
+/// Build the kernel page table, enable Sv39 and exercise map/protect/unmap
+fn test_virtual_memory() {
+    use crate::arch::current::mmu::{self, PageFlags};
+    use crate::memory::frame;
+
+    println!("Building kernel page table...");
+    if let Err(e) = mmu::init() {
+        print!("✗ Paging initialization failed: ");
+        println!(e.as_str());
+        return;
+    }
+    mmu::show_status();
+
+    // カーネル自身の恒等マッピングを確認
+    let pc = test_virtual_memory as usize;
+    match mmu::kernel_translate(pc) {
+        Some((pa, flags)) if pa == pc && flags.contains(PageFlags::EXECUTE) => {
+            println!("✓ Kernel text identity mapped (R+X)")
+        }
+        _ => println!("✗ Kernel text not mapped as expected"),
+    }
+    match mmu::kernel_translate(platform::uart_base()) {
+        Some((_, flags)) if !flags.contains(PageFlags::EXECUTE) => {
+            println!("✓ UART mapped as non-executable MMIO")
+        }
+        _ => println!("✗ UART not mapped"),
+    }
+
+    // 恒等マッピング外の仮想アドレスにフレームをマップ
+    let Ok(page) = frame::alloc_frame() else {
+        println!("✗ No frame for mapping test");
+        return;
+    };
+    let vaddr = 0x20_0000_0000;
+    let mapped = mmu::kernel_map(vaddr, page, frame::FRAME_SIZE, PageFlags::KERNEL_DATA)
+        .and_then(|_| mmu::kernel_protect(vaddr, frame::FRAME_SIZE, PageFlags::KERNEL_RODATA))
+        .map(|_| mmu::kernel_translate(vaddr + 0x123));
+    match mapped {
+        Ok(Some((pa, flags))) if pa == page + 0x123 && !flags.contains(PageFlags::WRITE) => {
+            println!("✓ Map/protect: {} -> {}", hex(vaddr), hex(page))
+        }
+        _ => println!("✗ Map/protect failed"),
+    }
+
+    if mmu::kernel_unmap(vaddr, frame::FRAME_SIZE).is_ok() && mmu::kernel_translate(vaddr).is_none()
+    {
+        println!("✓ Unmap");
+    } else {
+        println!("✗ Unmap failed");
+    }
+    let _ = frame::free_frame(page);
+
+    println!("✓ Virtual memory test completed");
+}
+
+/// Lock the kernel PMP layout on the primary hart
+#[cfg(not(feature = "smode"))]
+fn enable_memory_protection() {
+    use crate::arch::current::pmp;
+
+    println!("Applying PMP kernel layout...");
+    match pmp::protect_kernel() {
+        Ok(used) => {
+            println!("✓ PMP entries locked: {}", num(used as u64));
+            pmp::show_config();
+        }
+        Err(e) => {
+            print!("✗ PMP setup failed: ");
+            println!(e.as_str());
+        }
+    }
+}
+
+/// PMP is owned by the SBI firmware in S-mode
+#[cfg(feature = "smode")]
+fn enable_memory_protection() {
+    println!("PMP is managed by the SBI firmware in S-mode");
+}
+
 /// Initialize trap system
 fn initialize_trap_system() {
     println!("Initializing trap handler...");
@@ -253,6 +558,390 @@ fn test_ecall_functionality() {
     println!("✓ Ecall test completed successfully");
 }
 
+/// Value a registered breakpoint handler returns to the trapping code in a0
+const BREAKPOINT_MAGIC: usize = 0x5ab5_7a11;
+
+/// Breakpoint handler used by the registry test: skip the ebreak and
+/// return a value through the trap frame
+fn breakpoint_test_handler(frame: &mut arch::current::TrapFrame) {
+    let len = unsafe { frame.instruction_length() };
+    frame.skip_instruction(len);
+    frame.set_return(BREAKPOINT_MAGIC);
+    frame.set_reg(arch::current::trap::reg::A0 + 1, trap::trap_depth());
+}
+
+/// Breakpoint handler that takes a (fixed-up) fault while it runs
+///
+/// Returns in a0 whether the probe faulted (bit 0) and whether the outer
+/// trap was still the current context afterwards (bit 1), i.e. the nested
+/// trap saw this one as `previous` and restored it.
+fn nested_probe_handler(frame: &mut arch::current::TrapFrame) {
+    use crate::arch::TrapHandler;
+    use arch::current::TRAP_HANDLER;
+
+    let len = unsafe { frame.instruction_length() };
+    frame.skip_instruction(len);
+
+    let faulted = trap::probe_read::<u32>(0).is_err();
+    let context = TRAP_HANDLER.get_context();
+    let restored = context.mcause == frame.mcause && context.mepc == frame.mepc;
+    frame.set_return(faulted as usize | (restored as usize) << 1);
+}
+
+/// Test runtime trap handler registration
+fn test_trap_registry() {
+    use crate::arch::TrapHandler;
+    use arch::current::TRAP_HANDLER;
+
+    if let Err(e) =
+        TRAP_HANDLER.register_exception(bits::EXCEPTION_BREAKPOINT, breakpoint_test_handler)
+    {
+        println!(
+            "✗ Breakpoint handler registration failed: {}",
+            str(e.as_str())
+        );
+        return;
+    }
+
+    let result: usize;
+    let depth: usize;
+    unsafe {
+        core::arch::asm!(
+            "ebreak",
+            inlateout("a0") 0usize => result,
+            inlateout("a1") 0usize => depth,
+        );
+    }
+    let _ = TRAP_HANDLER.unregister_exception(bits::EXCEPTION_BREAKPOINT);
+
+    if result == BREAKPOINT_MAGIC {
+        println!("✓ Registered breakpoint handler ran and set a0");
+    } else {
+        println!("✗ Breakpoint handler result: {}", hex(result));
+    }
+    let outside = trap::trap_depth();
+    if depth == 1 && outside == 0 {
+        println!("✓ Trap depth inside handler: 1, outside: 0");
+    } else {
+        println!(
+            "✗ Trap depth inside handler: {}, outside: {}",
+            num(depth as u64),
+            num(outside as u64)
+        );
+    }
+
+    // An exception inside a registered handler is a nested trap
+    if TRAP_HANDLER
+        .register_exception(bits::EXCEPTION_BREAKPOINT, nested_probe_handler)
+        .is_ok()
+    {
+        let flags: usize;
+        unsafe {
+            core::arch::asm!("ebreak", inlateout("a0") 0usize => flags);
+        }
+        let _ = TRAP_HANDLER.unregister_exception(bits::EXCEPTION_BREAKPOINT);
+
+        match flags {
+            0b11 => println!("✓ Fault inside handler nested and restored the outer trap"),
+            0b01 => println!("✗ Outer trap context lost after a nested fault"),
+            _ => println!("✗ Fault inside handler was not caught: {}", hex(flags)),
+        }
+    }
+
+    let context = TRAP_HANDLER.get_context();
+    println!("Context outside trap - mstatus: {}", hex(context.mstatus));
+    TRAP_HANDLER.show_handlers();
+}
+
+/// Test exception decoding, fault reports and the resume action
+fn test_fault_reports() {
+    use arch::current::Exception;
+    use trap::fault::{self, FaultAction};
+
+    println!("Breakpoint without a handler (default action: resume)...");
+    let mode = trap::breakpoint::monitor_mode();
+    trap::breakpoint::set_monitor_mode(trap::breakpoint::MonitorMode::Off);
+    unsafe {
+        core::arch::asm!("ebreak");
+    }
+    trap::breakpoint::set_monitor_mode(mode);
+    println!("✓ Resumed after breakpoint");
+
+    println!("Illegal instruction with action set to resume...");
+    let previous = fault::fault_action(Exception::IllegalInstruction);
+    fault::set_fault_action(Exception::IllegalInstruction, FaultAction::Resume);
+    unsafe {
+        core::arch::asm!("unimp");
+    }
+    fault::set_fault_action(Exception::IllegalInstruction, previous);
+    println!("✓ Resumed after illegal instruction");
+
+    fault::show_policy();
+}
+
+/// Test misaligned loads and stores (emulated if the core traps on them)
+fn test_misaligned_access() {
+    use trap::misaligned;
+
+    misaligned::set_warn_once(true);
+    let before = misaligned::stats();
+
+    let mut buffer = [0u8; 16];
+    let addr = buffer.as_mut_ptr() as usize + 1;
+    let value: usize = 0x1122_3344_5566_7788;
+    let loaded: usize;
+    unsafe {
+        core::arch::asm!(
+            "sd {value}, 0({addr})",
+            "ld {loaded}, 0({addr})",
+            addr = in(reg) addr,
+            value = in(reg) value,
+            loaded = out(reg) loaded,
+        );
+    }
+
+    if loaded == value && buffer[1] == 0x88 && buffer[8] == 0x11 {
+        println!("✓ Misaligned store/load round trip correct");
+    } else {
+        println!("✗ Misaligned round trip read {}", hex(loaded));
+    }
+
+    let after = misaligned::stats();
+    if after.loads == before.loads && after.stores == before.stores {
+        println!("Misaligned accesses handled by hardware (no traps)");
+    }
+    misaligned::show_stats();
+}
+
+/// Value the test emulator writes for the custom instruction
+const CUSTOM_INSN_RESULT: usize = 42;
+
+/// Test emulator: `custom-0` opcode writes a constant to rd
+fn custom_insn_emulator(frame: &mut arch::current::TrapFrame, insn: u32) -> bool {
+    if insn & 0x7f != 0x0b {
+        return false;
+    }
+    frame.set_reg(((insn >> 7) & 0x1f) as usize, CUSTOM_INSN_RESULT);
+    true
+}
+
+/// M-extension test vectors: (name, instruction, rs1, rs2, expected rd)
+///
+/// Every instruction is encoded with rd = a0, rs1 = a1, rs2 = a2.
+const MULDIV_VECTORS: [(&str, u32, u64, u64, u64); 21] = [
+    ("mul", 0x02c5_8533, 7, -3i64 as u64, -21i64 as u64),
+    ("mulh", 0x02c5_9533, -2i64 as u64, 3, u64::MAX),
+    (
+        "mulh",
+        0x02c5_9533,
+        i64::MIN as u64,
+        i64::MIN as u64,
+        0x4000_0000_0000_0000,
+    ),
+    ("mulhsu", 0x02c5_a533, -1i64 as u64, u64::MAX, u64::MAX),
+    ("mulhsu", 0x02c5_a533, 2, u64::MAX, 1),
+    (
+        "mulhu",
+        0x02c5_b533,
+        u64::MAX,
+        u64::MAX,
+        0xffff_ffff_ffff_fffe,
+    ),
+    ("div", 0x02c5_c533, -7i64 as u64, 2, -3i64 as u64),
+    (
+        "div",
+        0x02c5_c533,
+        i64::MIN as u64,
+        -1i64 as u64,
+        i64::MIN as u64,
+    ),
+    ("div", 0x02c5_c533, 5, 0, u64::MAX),
+    ("divu", 0x02c5_d533, 5, 0, u64::MAX),
+    ("rem", 0x02c5_e533, -7i64 as u64, 2, -1i64 as u64),
+    ("rem", 0x02c5_e533, i64::MIN as u64, -1i64 as u64, 0),
+    ("rem", 0x02c5_e533, 5, 0, 5),
+    ("remu", 0x02c5_f533, 5, 0, 5),
+    ("mulw", 0x02c5_853b, 0x7fff_ffff, 2, -2i64 as u64),
+    (
+        "divw",
+        0x02c5_c53b,
+        0x8000_0000,
+        -1i64 as u64,
+        i32::MIN as i64 as u64,
+    ),
+    ("divw", 0x02c5_c53b, 9, 0, u64::MAX),
+    ("divuw", 0x02c5_d53b, 0xffff_ffff, 2, 0x7fff_ffff),
+    ("divuw", 0x02c5_d53b, 0xffff_fffe, 0, u64::MAX),
+    ("remw", 0x02c5_e53b, 0x8000_0000, -1i64 as u64, 0),
+    ("remuw", 0x02c5_f53b, 0x1_0000_0005, 0, 5),
+];
+
+/// Run one instruction through the emulator on a scratch trap frame
+///
+/// # Returns
+/// The value written to a0, or None if the instruction was not emulated
+fn emulate_scratch(insn: u32, rs1: u64, rs2: u64) -> Option<u64> {
+    let mut frame = arch::current::TrapFrame {
+        regs: [0; 32],
+        mepc: 0,
+        mstatus: 0,
+        mcause: 2,
+        mtval: insn as usize,
+    };
+    frame.set_reg(11, rs1 as usize);
+    frame.set_reg(12, rs2 as usize);
+
+    if !trap::emulate::emulate(&mut frame) || frame.mepc != 4 {
+        return None;
+    }
+    Some(frame.reg(10) as u64)
+}
+
+/// Test the built-in M-extension and `rdtime` emulators
+fn test_builtin_emulators() {
+    use trap::emulate;
+
+    let muldiv = emulate::muldiv_emulation();
+    emulate::set_muldiv_emulation(true);
+
+    let mut failures = 0;
+    for (name, insn, rs1, rs2, expected) in MULDIV_VECTORS {
+        match emulate_scratch(insn, rs1, rs2) {
+            Some(value) if value == expected => {}
+            Some(value) => {
+                println!(
+                    "✗ {} {}, {}: got {}, expected {}",
+                    str(name),
+                    hex(rs1 as usize),
+                    hex(rs2 as usize),
+                    hex(value as usize),
+                    hex(expected as usize)
+                );
+                failures += 1;
+            }
+            None => {
+                println!("✗ {} was not emulated", str(name));
+                failures += 1;
+            }
+        }
+    }
+    emulate::set_muldiv_emulation(muldiv);
+
+    if failures == 0 {
+        println!(
+            "✓ M extension emulation: {} vectors passed",
+            num(MULDIV_VECTORS.len() as u64)
+        );
+    }
+
+    // csrrs a0, time, x0
+    #[cfg(not(feature = "smode"))]
+    {
+        let before = CLINT_TIMER.read_mtime();
+        let result = emulate_scratch(0xc010_2573, 0, 0);
+        let after = CLINT_TIMER.read_mtime();
+        match result {
+            Some(time) if before <= time && time <= after => {
+                println!("✓ rdtime emulated: {}", num(time));
+            }
+            Some(time) => println!("✗ rdtime returned {}", num(time)),
+            None => println!("✗ rdtime was not emulated"),
+        }
+    }
+}
+
+/// Test the pluggable illegal-instruction emulation layer
+fn test_instruction_emulation() {
+    use trap::emulate;
+
+    test_builtin_emulators();
+
+    if let Err(e) = emulate::register_emulator(custom_insn_emulator) {
+        println!("✗ Emulator registration failed: {}", str(e.as_str()));
+        return;
+    }
+
+    // custom-0 opcode with rd = a0
+    let result: usize;
+    unsafe {
+        core::arch::asm!(".word 0x0000050b", inlateout("a0") 0usize => result);
+    }
+    emulate::unregister_emulator(custom_insn_emulator);
+
+    if result == CUSTOM_INSN_RESULT {
+        println!("✓ Custom instruction emulated");
+    } else {
+        println!("✗ Custom instruction result: {}", hex(result));
+    }
+
+    emulate::show_stats();
+}
+
+/// Value read back by the memory probe test
+static PROBE_TEST_VALUE: u64 = 0x5AFE_0000_C0DE_0001;
+
+/// Test `probe_read` on readable and unmapped addresses
+fn test_memory_probe() {
+    let addr = core::ptr::addr_of!(PROBE_TEST_VALUE) as usize;
+    match trap::probe_read::<u64>(addr) {
+        Ok(value) if value == PROBE_TEST_VALUE => println!("✓ Probe of kernel data succeeded"),
+        Ok(value) => println!("✗ Probe returned {}", hex(value as usize)),
+        Err(fault) => println!("✗ Probe of kernel data faulted: {}", str(fault.as_str())),
+    }
+
+    // Misaligned probes are split into byte loads
+    match trap::probe_read::<u32>(addr + 1) {
+        Ok(value) => println!("✓ Misaligned probe: {}", hex(value as usize)),
+        Err(fault) => println!("✗ Misaligned probe faulted: {}", str(fault.as_str())),
+    }
+
+    // Nothing is mapped at physical address 0 on the virt machine
+    match trap::probe_read::<u32>(0) {
+        Ok(_) => println!("✗ Probe of address 0 did not fault"),
+        Err(fault) => {
+            println!(
+                "✓ Probe of address 0 caught: {} (address {})",
+                str(fault.as_str()),
+                hex(fault.addr)
+            );
+        }
+    }
+
+    println!("Fixups taken: {}", num(trap::extable::fixup_count()));
+}
+
+/// Test `kbreak!` and break-on-assert (non-interactive, so boot never waits)
+fn test_kernel_breakpoints() {
+    use trap::breakpoint::{self, MonitorMode};
+
+    let mode = breakpoint::monitor_mode();
+    breakpoint::set_monitor_mode(MonitorMode::Report);
+    let before = breakpoint::breakpoint_count();
+
+    kbreak!();
+    println!("✓ Resumed after kbreak!");
+
+    // Outside interactive mode a failed kassert! must still panic
+    panic::set_break_on_assert(true);
+    let resumes = panic::break_on_assert("value == 2", file!(), line!());
+    panic::set_break_on_assert(false);
+    if resumes {
+        println!("✗ Failed kassert! would resume in report mode");
+    } else {
+        println!("✓ Failed kassert! panics unless the monitor is interactive");
+    }
+
+    breakpoint::set_monitor_mode(mode);
+
+    let hits = breakpoint::breakpoint_count() - before;
+    if hits == 1 {
+        println!("✓ kbreak! reached the monitor");
+    } else {
+        println!("✗ Monitor saw {} breakpoints", num(hits));
+    }
+    breakpoint::show_status();
+}
+
 /// Test system stability
 fn test_system_stability() {
     println!("Running stability test with trap handler active...");
@@ -278,12 +967,22 @@ fn test_system_stability() {
 
 /// Test MSIP functionality
 fn test_msip_functionality() {
+    if cfg!(feature = "smode") {
+        println!("Skipped: CLINT is owned by the SBI firmware in S-mode");
+        return;
+    }
+
     println!("Testing MSIP with active trap handler...");
     msip_debug::comprehensive_clint_test();
 }
 
 /// Test MSIP operations
 fn test_msip_operations() {
+    if cfg!(feature = "smode") {
+        println!("Skipped: CLINT is owned by the SBI firmware in S-mode");
+        return;
+    }
+
     println!("Testing safe MSIP operations...");
     match msip_debug::safe_msip_read() {
         Ok(val) => {
@@ -309,6 +1008,349 @@ fn test_software_interrupt_system() {
     }
 }
 
+/// Number of self software interrupts per latency measurement
+const LATENCY_SAMPLES: u64 = 16;
+
+/// Quiet software interrupt handler used while measuring latency
+fn latency_sw_handler(_frame: &mut arch::current::TrapFrame) {
+    let _ = arch::current::ipi::clear(read_mhartid() as usize);
+}
+
+/// Average cycles from raising a self software interrupt until it is handled
+fn measure_software_interrupt_latency() -> Option<u64> {
+    let hart = read_mhartid() as usize;
+    let mut total = 0u64;
+
+    for _ in 0..LATENCY_SAMPLES {
+        let start = arch::csr::read_cycle();
+        arch::current::ipi::send(hart).ok()?;
+
+        let mut timeout = 1_000_000;
+        while arch::current::ipi::is_pending(hart) {
+            timeout -= 1;
+            if timeout == 0 {
+                let _ = arch::current::ipi::clear(hart);
+                return None;
+            }
+        }
+        total += arch::csr::read_cycle() - start;
+    }
+
+    Some(total / LATENCY_SAMPLES)
+}
+
+/// Test nested interrupt guards and the critical-section implementation
+fn test_interrupt_guards() {
+    use interrupt::guard;
+    use interrupt::InterruptGuard;
+
+    let outer = InterruptGuard::enabled();
+    {
+        let _first = InterruptGuard::new();
+        {
+            let _second = InterruptGuard::new();
+            println!("Nesting depth: {}", num(guard::nesting_depth() as u64));
+        }
+        if !arch::csr::interrupts_enabled() {
+            println!("✓ Inner guard kept interrupts disabled");
+        } else {
+            println!("✗ Inner guard re-enabled interrupts");
+        }
+    }
+    if arch::csr::interrupts_enabled() && guard::nesting_depth() == 0 {
+        println!("✓ Outer guard restored interrupts");
+    } else {
+        println!("✗ Interrupt state not restored");
+    }
+
+    let disabled = critical_section::with(|_| !arch::csr::interrupts_enabled());
+    let restored = arch::csr::interrupts_enabled();
+    if disabled && restored {
+        println!("✓ critical_section::with disables and restores interrupts");
+    } else {
+        println!("✗ critical-section implementation failed");
+    }
+
+    drop(outer);
+}
+
+/// Test PLIC source configuration and external interrupt enabling
+fn test_plic() {
+    use arch::current::PLIC;
+    use arch::InterruptController;
+
+    let hart = read_mhartid() as usize;
+    let source = platform::boot_info()
+        .uart
+        .irq
+        .unwrap_or(arch::current::memory_map::UART0_IRQ);
+
+    let configured =
+        PLIC.set_priority(source, 1).is_ok() && PLIC.enable_source(hart, source).is_ok();
+    if configured && PLIC.priority(source) == 1 && PLIC.is_source_enabled(hart, source) {
+        println!(
+            "✓ UART source {} routed to hart {}",
+            num(source as u64),
+            num(hart as u64)
+        );
+    } else {
+        println!("✗ PLIC source configuration failed");
+    }
+
+    match unsafe { PLIC.enable() } {
+        Ok(()) if PLIC.is_enabled() => println!("✓ External interrupts enabled"),
+        _ => println!("✗ Could not enable external interrupts"),
+    }
+
+    PLIC.show_status();
+
+    // No UART driver yet: leave the source silent
+    let _ = PLIC.disable_source(hart, source);
+    let _ = PLIC.set_priority(source, 0);
+}
+
+/// UART interrupt enable register offset (ns16550a)
+const UART_IER: usize = 1;
+
+/// UART IER bit: transmitter holding register empty interrupt
+const UART_IER_THRE: u8 = 0x02;
+
+/// Interrupts handled by the UART test handler
+static UART_IRQ_HITS: AtomicU64 = AtomicU64::new(0);
+
+/// Shared-IRQ test handler that never claims the interrupt
+fn uart_irq_not_mine(_irq: u32) -> interrupt::IrqReturn {
+    interrupt::IrqReturn::NotMine
+}
+
+/// UART test handler: acknowledge the THRE interrupt by disabling it
+fn uart_thre_handler(_irq: u32) -> interrupt::IrqReturn {
+    unsafe {
+        core::ptr::write_volatile((platform::uart_base() + UART_IER) as *mut u8, 0);
+    }
+    UART_IRQ_HITS.fetch_add(1, Ordering::Relaxed);
+    interrupt::IrqReturn::Handled
+}
+
+/// Test request_irq/free_irq with a shared UART interrupt
+fn test_irq_registration() {
+    use arch::current::PLIC;
+    use arch::InterruptController;
+
+    let hart = read_mhartid() as usize;
+    let source = platform::boot_info()
+        .uart
+        .irq
+        .unwrap_or(arch::current::memory_map::UART0_IRQ);
+
+    for (handler, priority) in [
+        (uart_irq_not_mine as interrupt::IrqHandler, 1),
+        (uart_thre_handler, 2),
+    ] {
+        if let Err(e) = interrupt::request_irq(source, handler, priority, 1 << hart) {
+            println!("✗ request_irq failed: {}", str(e.as_str()));
+            return;
+        }
+    }
+    match interrupt::request_irq(source, uart_thre_handler, 2, 1 << hart) {
+        Err(interrupt::IrqError::AlreadyRegistered) => {
+            println!("✓ Duplicate registration rejected")
+        }
+        _ => println!("✗ Duplicate registration not detected"),
+    }
+
+    let interrupts_were_enabled = arch::csr::read_mstatus() & bits::STATUS_IE != 0;
+    let _ = unsafe { PLIC.enable() };
+    let _ = unsafe { arch::csr::enable_global_interrupts() };
+
+    // The THRE interrupt fires as soon as it is enabled on an idle UART
+    unsafe {
+        core::ptr::write_volatile((platform::uart_base() + UART_IER) as *mut u8, UART_IER_THRE);
+    }
+    let mut spins = 0;
+    while UART_IRQ_HITS.load(Ordering::Relaxed) == 0 && spins < 1_000_000 {
+        core::hint::spin_loop();
+        spins += 1;
+    }
+
+    if !interrupts_were_enabled {
+        let _ = unsafe { arch::csr::disable_global_interrupts() };
+    }
+
+    if UART_IRQ_HITS.load(Ordering::Relaxed) > 0 {
+        println!("✓ UART interrupt dispatched to the shared handlers");
+    } else {
+        unsafe {
+            core::ptr::write_volatile((platform::uart_base() + UART_IER) as *mut u8, 0);
+        }
+        println!("✗ UART interrupt not delivered");
+    }
+
+    interrupt::irq::show_irqs();
+
+    let freed = interrupt::free_irq(source, uart_irq_not_mine).is_ok()
+        && interrupt::free_irq(source, uart_thre_handler).is_ok();
+    if freed && !PLIC.is_source_enabled(hart, source) {
+        println!("✓ Handlers freed and source disabled");
+    } else {
+        println!("✗ free_irq did not release the source");
+    }
+}
+
+/// Compare software interrupt latency in direct and vectored trap modes
+fn test_trap_vector_modes() {
+    use arch::current::{TrapMode, TRAP_HANDLER};
+
+    if TRAP_HANDLER
+        .register_interrupt(bits::INTERRUPT_SW, latency_sw_handler)
+        .is_err()
+    {
+        println!("✗ Software interrupt handler already registered");
+        return;
+    }
+
+    let interrupts_were_enabled = arch::csr::read_mstatus() & bits::STATUS_IE != 0;
+    let _ = unsafe { arch::csr::enable_global_interrupts() };
+
+    for mode in [TrapMode::Direct, TrapMode::Vectored] {
+        trap::set_trap_mode(mode);
+        match measure_software_interrupt_latency() {
+            Some(cycles) => println!(
+                "{} mode: {} cycles per software interrupt",
+                str(mode.as_str()),
+                num(cycles)
+            ),
+            None => println!(
+                "✗ {} mode: software interrupt not delivered",
+                str(mode.as_str())
+            ),
+        }
+    }
+
+    if !interrupts_were_enabled {
+        let _ = unsafe { arch::csr::disable_global_interrupts() };
+    }
+    let _ = TRAP_HANDLER.unregister_interrupt(bits::INTERRUPT_SW);
+
+    trap::set_trap_mode(trap::DEFAULT_TRAP_MODE);
+    print!("Trap mode restored: ");
+    println!(trap::trap_mode().as_str());
+}
+
+/// Argument passed to `ipi_call_target`; every hart adds it once
+const IPI_CALL_ARG: usize = 7;
+
+/// Sum of the arguments received by `ipi_call_target`
+static IPI_CALL_SUM: AtomicU64 = AtomicU64::new(0);
+
+/// Function run on every online hart through a call-function IPI
+fn ipi_call_target(arg: usize) {
+    IPI_CALL_SUM.fetch_add(arg as u64, Ordering::Relaxed);
+}
+
+/// Test IPI messages and acknowledgements on every online hart
+fn test_ipi_messages() {
+    use smp::ipi::{self, IpiError, IpiMessage};
+
+    let hart = read_mhartid() as usize;
+    let targets = smp::online_mask();
+    println!("Online harts: {}", hex(targets));
+
+    // With interrupts enabled the self IPI goes through the trap handler
+    let guard = interrupt::InterruptGuard::enabled();
+    match ipi::send_ipi(hart, IpiMessage::Reschedule).and_then(|ticket| ticket.wait()) {
+        Ok(()) => println!("✓ Reschedule IPI acknowledged"),
+        Err(e) => println!("✗ Reschedule IPI failed: {}", str(e.as_str())),
+    }
+    drop(guard);
+
+    // With interrupts disabled the wait processes this hart's mailbox itself
+    let expected = IPI_CALL_ARG as u64 * targets.count_ones() as u64;
+    match ipi::call_function_mask(targets, ipi_call_target, IPI_CALL_ARG) {
+        Ok(()) if IPI_CALL_SUM.load(Ordering::Relaxed) == expected => println!(
+            "✓ Function called on {} hart(s)",
+            num(targets.count_ones() as u64)
+        ),
+        Ok(()) => println!(
+            "✗ Call-function sum {} (expected {})",
+            num(IPI_CALL_SUM.load(Ordering::Relaxed)),
+            num(expected)
+        ),
+        Err(e) => println!("✗ Call-function IPI failed: {}", str(e.as_str())),
+    }
+
+    match ipi::flush_tlb_mask(targets, None) {
+        Ok(()) => println!("✓ TLB flushed on all online harts"),
+        Err(e) => println!("✗ TLB flush IPI failed: {}", str(e.as_str())),
+    }
+
+    if let Some(offline) = (0..arch::current::MAX_HARTS).find(|&other| !smp::is_online(other)) {
+        match ipi::send_ipi(offline, IpiMessage::Reschedule) {
+            Err(IpiError::HartOffline) => println!("✓ IPI to offline hart rejected"),
+            _ => println!("✗ IPI to offline hart not rejected"),
+        }
+    }
+
+    ipi::show_status();
+}
+
+/// Number of times `deferred_work_target` ran
+static DEFERRED_RUNS: AtomicU64 = AtomicU64::new(0);
+
+/// Deferred work used by the deferred work test
+static DEFERRED_TEST_WORK: interrupt::WorkItem = interrupt::WorkItem::new(deferred_work_target, 0);
+
+fn deferred_work_target(_data: usize) {
+    DEFERRED_RUNS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Schedule the test work from trap context (runs as a call-function IPI)
+fn schedule_from_trap(_arg: usize) {
+    DEFERRED_TEST_WORK.schedule();
+}
+
+/// Test deferred work from the worker and at trap exit
+fn test_deferred_work() {
+    use smp::ipi::{self, IpiMessage};
+
+    // Scheduling twice before it runs coalesces into one run
+    let first = DEFERRED_TEST_WORK.schedule();
+    let second = DEFERRED_TEST_WORK.schedule();
+    if first && !second && DEFERRED_TEST_WORK.is_pending() {
+        println!("✓ Work queued once");
+    } else {
+        println!("✗ Work queueing not coalesced");
+    }
+
+    let ran = interrupt::deferred::run_pending();
+    if ran == 1 && DEFERRED_RUNS.load(Ordering::Relaxed) == 1 {
+        println!("✓ Worker ran the queued work");
+    } else {
+        println!("✗ Worker ran {} item(s)", num(ran));
+    }
+
+    // Work scheduled by a trap handler runs when the trap exits, before the
+    // interrupted code continues
+    let hart = read_mhartid() as usize;
+    let guard = interrupt::InterruptGuard::enabled();
+    let result = ipi::send_ipi(hart, IpiMessage::CallFunction(schedule_from_trap, 0))
+        .and_then(|ticket| ticket.wait());
+    drop(guard);
+
+    match result {
+        Ok(()) if DEFERRED_TEST_WORK.run_count() == 2 => {
+            println!("✓ Work scheduled in trap context ran at trap exit")
+        }
+        Ok(()) => println!(
+            "✗ Work did not run at trap exit (run count {})",
+            num(DEFERRED_TEST_WORK.run_count())
+        ),
+        Err(e) => println!("✗ IPI failed: {}", str(e.as_str())),
+    }
+
+    interrupt::deferred::show_status();
+}
+
 /// Test unified timer system
 fn test_unified_timer_system() {
     println!("Testing unified HAL timer system...");
@@ -402,7 +1444,7 @@ fn test_timer_interrupt_system() {
     }
 
     let mie = arch::csr::read_mie();
-    if (mie & (1 << 7)) != 0 {
+    if (mie & bits::IE_TIMER) != 0 {
         println!("✓ Machine Timer Interrupt Enable (MTIE) active");
     } else {
         println!("✗ MTIE not enabled");
@@ -437,13 +1479,13 @@ fn test_integrated_interrupt_system() {
     println!("  mie: {}", hex(mie));
 
     // Check interrupt enable status
-    if (mstatus & (1 << 3)) != 0 {
+    if (mstatus & bits::STATUS_IE) != 0 {
         println!("  ✓ Global interrupts (MIE) enabled");
     }
-    if (mie & (1 << 3)) != 0 {
+    if (mie & bits::IE_SOFT) != 0 {
         println!("  ✓ Software interrupts (MSIE) enabled");
     }
-    if (mie & (1 << 7)) != 0 {
+    if (mie & bits::IE_TIMER) != 0 {
         println!("  ✓ Timer interrupts (MTIE) enabled");
     }
 
@@ -546,11 +1588,11 @@ fn test_stack_monitoring() {
     let current_sp = get_current_sp();
     println!("Current SP: {}", hex(current_sp));
 
-    let stack_base = 0x80100000;
+    let (stack_bottom, stack_base) = memory::hart_stack(read_mhartid() as usize);
     let stack_used = stack_base - current_sp;
     println!("Stack used: {} bytes", num(stack_used as u64));
 
-    if current_sp >= 0x80000000 && current_sp < 0x80100000 {
+    if current_sp >= stack_bottom && current_sp < stack_base {
         println!("✓ Stack pointer in valid range");
     } else {
         println!("✗ Stack pointer out of range");
@@ -566,10 +1608,7 @@ fn test_memory_checking() {
 
     println!("Test array address: {}", hex(ptr));
 
-    let ram_start = 0x80000000;
-    let ram_end = 0x88000000;
-
-    if ptr >= ram_start && ptr < ram_end {
+    if memory::layout().ram.contains(ptr) {
         println!("✓ Address in valid RAM range");
     } else {
         println!("⚠ Address outside RAM range (stack/heap)");
@@ -589,9 +1628,9 @@ fn test_csr_dumping() {
     println!("  mie: {}", hex(mie));
     println!("  mtvec: {}", hex(mtvec));
 
-    let global_ie = (mstatus >> 3) & 1;
-    let mtie = (mie >> 7) & 1;
-    let msie = (mie >> 3) & 1;
+    let global_ie = ((mstatus & bits::STATUS_IE) != 0) as usize;
+    let mtie = ((mie & bits::IE_TIMER) != 0) as usize;
+    let msie = ((mie & bits::IE_SOFT) != 0) as usize;
 
     println!("bit field analysis:");
     println!("  global ie: {}", num(global_ie as u64));
@@ -610,6 +1649,9 @@ fn main_system_loop() -> ! {
     loop {
         integrated_counter = integrated_counter.wrapping_add(1);
 
+        // Kernel worker: run deferred work the trap exits left behind
+        interrupt::deferred::run_pending();
+
         if integrated_counter % 10000000 == 0 {
             println!("Loop count: {}", num(integrated_counter));
 
@@ -715,11 +1757,7 @@ fn main_system_loop() -> ! {
 
 /// Helper functions
 fn read_mhartid() -> u64 {
-    let mut val: u64;
-    unsafe {
-        core::arch::asm!("csrr {}, mhartid", out(reg) val);
-    }
-    val
+    arch::csr::read_mhartid()
 }
 
 fn get_current_sp() -> usize {
@@ -750,18 +1788,27 @@ pub fn system_diagnostics() {
 
     // Memory information
     let current_sp = get_current_sp();
-    let stack_used = 0x80100000 - current_sp;
+    let layout = memory::layout();
+    let stack = layout.hart_stack(mhartid as usize);
+    let stack_used = stack.end - current_sp;
     println!("Memory status:");
-    println!("  Stack used: {} bytes", num(stack_used as u64));
+    println!(
+        "  Stack used: {} / {} bytes",
+        num(stack_used as u64),
+        num(layout.hart_stack_size as u64)
+    );
+    println!("  RAM: {} - {}", hex(layout.ram.start), hex(layout.ram.end));
 
     // Interrupt statistics
-    let (sw_interrupts, yields, handlers, errors) = interrupt::get_statistics();
+    let (sw_interrupts, yields, errors) = interrupt::get_statistics();
     println!("Interrupt status:");
     println!("  SW interrupts: {}", num(sw_interrupts));
     println!("  Yield calls: {}", num(yields));
-    println!("  Handler calls: {}", num(handlers));
     println!("  Errors: {}", num(errors));
 
+    // Trap accounting (all causes, all harts)
+    trap::stats::show();
+
     println!("=== DIAGNOSTICS COMPLETE ===");
 }
 
@@ -1,8 +1,25 @@
+// Early boot memory initialization
+// BSSクリアと.dataの再配置（ROM/XIPイメージ対応）
+
+pub mod frame;
+pub mod heap;
+
 unsafe extern "C" {
     unsafe static __bss_start: u8;
     unsafe static __bss_end: u8;
     unsafe static __data_start: u8;
     unsafe static __data_end: u8;
+    unsafe static __data_load_start: u8;
+}
+
+/// 早期初期化（boot.sの_startからrust_mainより前に呼ばれる）
+///
+/// BSSをクリアし、.dataをロードアドレスから実行アドレスへコピーする。
+/// この関数が戻るまでRustのstatic変数に触れてはならない。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn early_init() {
+    zero_bss();
+    init_data();
 }
 
 pub unsafe fn zero_bss() {
@@ -16,7 +33,181 @@ pub unsafe fn zero_bss() {
 }
 
 pub unsafe fn init_data() {
-    // データセクションは既にRAMに配置されているので、
-    // この関数は何もしません（QEMU virtマシンの場合）
-    // 必要に応じて初期化コードをここに追加
+    let start = &__data_start as *const u8 as *mut u8;
+    let end = &__data_end as *const u8;
+    let load = &__data_load_start as *const u8;
+    let len = end as usize - start as usize;
+
+    // RAMイメージではLMAとVMAが一致するのでコピー不要
+    // ROM/XIPイメージではフラッシュ上の初期値をRAMへコピーする
+    if len > 0 && load != start as *const u8 {
+        core::ptr::copy_nonoverlapping(load, start, len);
+    }
+}
+
+/// スタック最下部のガード領域のサイズ（スタック領域に含まれる）
+pub const STACK_GUARD_SIZE: usize = crate::arch::current::PAGE_SIZE;
+
+/// アドレス範囲 [start, end)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Region {
+    /// 範囲のバイト数
+    pub const fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// アドレスが範囲内かどうか
+    pub const fn contains(&self, addr: usize) -> bool {
+        addr >= self.start && addr < self.end
+    }
+}
+
+/// カーネルのメモリレイアウト
+///
+/// イメージ内の境界はlink.ldのシンボルから、RAMの範囲は実行時の
+/// プラットフォーム情報（デバイスツリー）から取得する。
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryLayout {
+    /// .text（XIPイメージではROM上）
+    pub text: Region,
+
+    /// .rodata（XIPイメージではROM上）
+    pub rodata: Region,
+
+    /// .data（実行アドレス）
+    pub data: Region,
+
+    /// .dataの初期値のロードアドレス
+    pub data_load: usize,
+
+    /// .bss
+    pub bss: Region,
+
+    /// 全ハートのスタック（ガードページを含む）
+    pub stacks: Region,
+
+    /// ハートあたりのスタックサイズ（ガードページを含む）
+    pub hart_stack_size: usize,
+
+    /// カーネルイメージの末尾
+    pub kernel_end: usize,
+
+    /// カーネル後の空きRAM（カーネルを含むRAM領域の末尾まで、
+    /// フレームアロケータとヒープが使う）
+    pub heap: Region,
+
+    /// 実行時に検出したRAM全体（全RAM領域を含む範囲）
+    pub ram: Region,
+}
+
+impl MemoryLayout {
+    /// ハートのスタック範囲（ガードページを含む）
+    pub fn hart_stack(&self, hart: usize) -> Region {
+        let end = self.stacks.end - hart * self.hart_stack_size;
+        Region {
+            start: end - self.hart_stack_size,
+            end,
+        }
+    }
+
+    /// いずれかのハートのスタック内のアドレスかどうか
+    pub fn is_stack(&self, addr: usize) -> bool {
+        self.stacks.contains(addr)
+    }
+
+    /// カーネルのコードまたはRAM上のアドレスかどうか
+    pub fn is_kernel_address(&self, addr: usize) -> bool {
+        self.ram.contains(addr) || self.text.contains(addr) || self.rodata.contains(addr)
+    }
+}
+
+/// リンカシンボルのアドレス
+macro_rules! symbol_addr {
+    ($name:ident) => {{
+        unsafe extern "C" {
+            unsafe static $name: u8;
+        }
+        unsafe { &$name as *const u8 as usize }
+    }};
+}
+
+/// カーネルのメモリレイアウトを取得
+pub fn layout() -> MemoryLayout {
+    use crate::arch::current::{platform, MAX_HARTS};
+
+    let region = |start, end| Region { start, end };
+    let stacks = region(symbol_addr!(__stacks_start), symbol_addr!(__stacks_end));
+    let kernel_end = symbol_addr!(__kernel_end);
+
+    // RAMはリンカスクリプトの固定値ではなく、デバイスツリーから得た領域を使う
+    let regions = platform::boot_info().memory_regions();
+    let ram_start = regions
+        .iter()
+        .map(|r| r.base)
+        .min()
+        .unwrap_or(platform::ram_start());
+    let ram_end = regions
+        .iter()
+        .map(|r| r.end())
+        .max()
+        .unwrap_or(platform::ram_end());
+    let heap_end = regions
+        .iter()
+        .find(|r| r.contains(kernel_end - 1))
+        .map_or(platform::ram_end(), |r| r.end());
+
+    MemoryLayout {
+        text: region(symbol_addr!(__text_start), symbol_addr!(__text_end)),
+        rodata: region(symbol_addr!(__rodata_start), symbol_addr!(__rodata_end)),
+        data: region(symbol_addr!(__data_start), symbol_addr!(__data_end)),
+        data_load: symbol_addr!(__data_load_start),
+        bss: region(symbol_addr!(__bss_start), symbol_addr!(__bss_end)),
+        stacks,
+        hart_stack_size: stacks.size() / MAX_HARTS,
+        kernel_end,
+        heap: region(symbol_addr!(__heap_start), heap_end),
+        ram: region(ram_start, ram_end),
+    }
+}
+
+/// ハートのスタック範囲 (bottom, top)
+///
+/// ハートNのスタックは`__stacks_end`から下向きにN番目の領域
+pub fn hart_stack(hart: usize) -> (usize, usize) {
+    let stack = layout().hart_stack(hart);
+    (stack.start, stack.end)
+}
+
+/// ハートのスタックガード領域 (base, size)
+///
+/// スタック領域の最下位ページ。PMP（M-mode）でアクセス禁止にし、
+/// オーバーフローしたスタックがここに触れるとアクセスフォルトになる。
+pub fn hart_stack_guard(hart: usize) -> (usize, usize) {
+    let (bottom, _) = hart_stack(hart);
+    (bottom, STACK_GUARD_SIZE)
+}
+
+/// メモリレイアウトの表示
+pub fn show_layout() {
+    use crate::console::hex;
+
+    let layout = layout();
+    crate::println!("=== MEMORY LAYOUT ===");
+    for (name, region) in [
+        ("  .text:   ", layout.text),
+        ("  .rodata: ", layout.rodata),
+        ("  .data:   ", layout.data),
+        ("  .bss:    ", layout.bss),
+        ("  stacks:  ", layout.stacks),
+        ("  heap:    ", layout.heap),
+        ("  RAM:     ", layout.ram),
+    ] {
+        crate::print!(name);
+        crate::println!("{} - {}", hex(region.start), hex(region.end));
+    }
 }
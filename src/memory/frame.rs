@@ -0,0 +1,539 @@
+// 物理ページフレームアロケータ（ビットマップ方式）
+//
+// プラットフォーム情報（デバイスツリーまたはQEMU virtの既定値）のRAM領域を
+// 4KiBフレーム単位で管理する。ビットマップはカーネルイメージ直後の
+// フレームに置き、1ビットが1フレームを表す。使用中ビットマップ（1 = 使用中）に
+// 加えて予約ビットマップ（1 = 予約済みまたはRAM外）を持ち、解放できるのは
+// allocで割り当てたフレームだけにする。
+//
+// 初期化時に以下を予約する:
+// - カーネルを含むRAM領域の先頭から`__kernel_end`まで
+//   （SBIファームウェア、カーネルイメージ、ハート別スタック）
+// - ビットマップ自身（使用中と予約の2つ）
+// - デバイスツリーのブロブとメモリ予約ブロックのエントリ
+
+use crate::arch::current::{platform, PAGE_SIZE};
+use crate::console::{hex, num};
+use crate::fdt::Fdt;
+use crate::println;
+use crate::sync::SpinLock;
+
+/// フレームサイズ（ページサイズと同じ）
+pub const FRAME_SIZE: usize = PAGE_SIZE;
+
+/// ビットマップの1ワードが管理するフレーム数
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// フレームアロケータのエラー
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameError {
+    /// `init`がまだ呼ばれていない
+    NotInitialized,
+
+    /// `init`が既に呼ばれている
+    AlreadyInitialized,
+
+    /// 要求を満たす空きフレームがない
+    OutOfMemory,
+
+    /// 管理範囲外またはフレーム境界にないアドレス
+    InvalidAddress,
+
+    /// 割り当てられていないフレームの解放（二重解放など）
+    NotAllocated,
+
+    /// 予約済みまたはRAM外のフレームの解放
+    Reserved,
+
+    /// フレーム数またはアラインメントが不正
+    InvalidCount,
+}
+
+impl FrameError {
+    /// エラーの説明文字列
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FrameError::NotInitialized => "Frame allocator not initialized",
+            FrameError::AlreadyInitialized => "Frame allocator already initialized",
+            FrameError::OutOfMemory => "Out of physical memory",
+            FrameError::InvalidAddress => "Invalid frame address",
+            FrameError::NotAllocated => "Frame not allocated",
+            FrameError::Reserved => "Frame is reserved",
+            FrameError::InvalidCount => "Invalid frame count or alignment",
+        }
+    }
+}
+
+impl core::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// フレームアロケータの統計
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    /// 管理範囲の先頭物理アドレス
+    pub base: usize,
+
+    /// 管理範囲の総フレーム数（領域間の穴を含む）
+    pub total_frames: usize,
+
+    /// 空きフレーム数
+    pub free_frames: usize,
+
+    /// 割り当て済みフレーム数
+    pub used_frames: usize,
+
+    /// 初期化時に予約されたフレーム数（カーネル、DTBなど）
+    pub reserved_frames: usize,
+
+    /// 最大の連続空きフレーム数
+    pub largest_free_run: usize,
+
+    /// 成功した割り当て回数
+    pub allocations: u64,
+
+    /// 解放回数
+    pub frees: u64,
+
+    /// 失敗した割り当て回数
+    pub failures: u64,
+}
+
+/// ビットマップアロケータ本体
+struct FrameAllocator {
+    /// 使用中ビットマップ（1 = 使用中/予約/RAM外）
+    bitmap: *mut u64,
+    /// 予約ビットマップ（1 = 予約/RAM外、解放できない）
+    reserved_map: *mut u64,
+    words: usize,
+    base: usize,
+    frames: usize,
+    free: usize,
+    reserved: usize,
+    /// RAM領域間の穴（管理範囲内だがRAMでないフレーム）の数
+    holes: usize,
+    /// 次の探索開始位置
+    hint: usize,
+    allocations: u64,
+    frees: u64,
+    failures: u64,
+}
+
+// Safety: ビットマップへのアクセスはFRAME_ALLOCATORのロックで排他される
+unsafe impl Send for FrameAllocator {}
+
+impl FrameAllocator {
+    const fn empty() -> Self {
+        Self {
+            bitmap: core::ptr::null_mut(),
+            reserved_map: core::ptr::null_mut(),
+            words: 0,
+            base: 0,
+            frames: 0,
+            free: 0,
+            reserved: 0,
+            holes: 0,
+            hint: 0,
+            allocations: 0,
+            frees: 0,
+            failures: 0,
+        }
+    }
+
+    fn is_initialized(&self) -> bool {
+        !self.bitmap.is_null()
+    }
+
+    fn word(&self, index: usize) -> u64 {
+        unsafe { *self.bitmap.add(index) }
+    }
+
+    fn word_mut(&mut self, index: usize) -> &mut u64 {
+        unsafe { &mut *self.bitmap.add(index) }
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.word(frame / BITS_PER_WORD) & (1 << (frame % BITS_PER_WORD)) != 0
+    }
+
+    fn set_used(&mut self, frame: usize) {
+        *self.word_mut(frame / BITS_PER_WORD) |= 1 << (frame % BITS_PER_WORD);
+    }
+
+    fn set_free(&mut self, frame: usize) {
+        *self.word_mut(frame / BITS_PER_WORD) &= !(1 << (frame % BITS_PER_WORD));
+    }
+
+    fn is_reserved(&self, frame: usize) -> bool {
+        let word = unsafe { *self.reserved_map.add(frame / BITS_PER_WORD) };
+        word & (1 << (frame % BITS_PER_WORD)) != 0
+    }
+
+    fn set_reserved(&mut self, frame: usize, reserved: bool) {
+        let word = unsafe { &mut *self.reserved_map.add(frame / BITS_PER_WORD) };
+        if reserved {
+            *word |= 1 << (frame % BITS_PER_WORD);
+        } else {
+            *word &= !(1 << (frame % BITS_PER_WORD));
+        }
+    }
+
+    /// 物理アドレスをフレーム番号に変換
+    fn frame_of(&self, addr: usize) -> Option<usize> {
+        if addr < self.base || addr % FRAME_SIZE != 0 {
+            return None;
+        }
+        let frame = (addr - self.base) / FRAME_SIZE;
+        (frame < self.frames).then_some(frame)
+    }
+
+    fn addr_of(&self, frame: usize) -> usize {
+        self.base + frame * FRAME_SIZE
+    }
+
+    /// 物理アドレス範囲をフレーム範囲に変換（範囲外は切り詰める）
+    fn frame_range(&self, start: usize, end: usize) -> (usize, usize) {
+        let limit = self.addr_of(self.frames);
+        let start = start.clamp(self.base, limit);
+        let end = end.clamp(self.base, limit);
+        if start >= end {
+            return (0, 0);
+        }
+        (
+            (start - self.base) / FRAME_SIZE,
+            (end - self.base).div_ceil(FRAME_SIZE),
+        )
+    }
+
+    /// 範囲内の空きフレームを予約済みにする
+    fn reserve(&mut self, start: usize, end: usize) {
+        let (first, last) = self.frame_range(start, end);
+        for frame in first..last {
+            if !self.is_used(frame) {
+                self.set_used(frame);
+                self.set_reserved(frame, true);
+                self.free -= 1;
+                self.reserved += 1;
+            }
+        }
+    }
+
+    /// `count`個の連続した空きフレームを探す
+    ///
+    /// 先頭フレームの物理アドレスは`align`フレーム境界に揃える
+    fn find_run(&self, count: usize, align: usize) -> Option<usize> {
+        let base_frame = self.base / FRAME_SIZE;
+        let align_up = |frame: usize| (base_frame + frame).next_multiple_of(align) - base_frame;
+
+        // ヒントから末尾まで、見つからなければ先頭からもう一度
+        for (from, to) in [(self.hint, self.frames), (0, self.hint + count)] {
+            let to = to.min(self.frames);
+            let mut start = align_up(from);
+
+            'search: while start + count <= to {
+                // 単一フレームはワード単位で全使用中を読み飛ばす
+                if count == 1
+                    && start % BITS_PER_WORD == 0
+                    && self.word(start / BITS_PER_WORD) == u64::MAX
+                {
+                    start = align_up(start + BITS_PER_WORD);
+                    continue;
+                }
+
+                for frame in start..start + count {
+                    if self.is_used(frame) {
+                        start = align_up(frame + 1);
+                        continue 'search;
+                    }
+                }
+                return Some(start);
+            }
+        }
+
+        None
+    }
+
+    fn alloc(&mut self, count: usize, align: usize) -> Result<usize, FrameError> {
+        if !self.is_initialized() {
+            return Err(FrameError::NotInitialized);
+        }
+        if count == 0 || align == 0 || !align.is_power_of_two() {
+            return Err(FrameError::InvalidCount);
+        }
+        if count > self.free {
+            self.failures += 1;
+            return Err(FrameError::OutOfMemory);
+        }
+
+        let Some(first) = self.find_run(count, align) else {
+            self.failures += 1;
+            return Err(FrameError::OutOfMemory);
+        };
+
+        for frame in first..first + count {
+            self.set_used(frame);
+        }
+        self.free -= count;
+        self.allocations += 1;
+        if count == 1 {
+            self.hint = first + 1;
+        }
+
+        Ok(self.addr_of(first))
+    }
+
+    fn free(&mut self, addr: usize, count: usize) -> Result<(), FrameError> {
+        if !self.is_initialized() {
+            return Err(FrameError::NotInitialized);
+        }
+        if count == 0 {
+            return Err(FrameError::InvalidCount);
+        }
+
+        let first = self.frame_of(addr).ok_or(FrameError::InvalidAddress)?;
+        if first + count > self.frames {
+            return Err(FrameError::InvalidAddress);
+        }
+
+        // 一部でも予約済みまたは未割り当てなら何も変更しない
+        if (first..first + count).any(|frame| self.is_reserved(frame)) {
+            return Err(FrameError::Reserved);
+        }
+        if (first..first + count).any(|frame| !self.is_used(frame)) {
+            return Err(FrameError::NotAllocated);
+        }
+
+        for frame in first..first + count {
+            self.set_free(frame);
+        }
+        self.free += count;
+        self.frees += 1;
+        self.hint = self.hint.min(first);
+        Ok(())
+    }
+
+    fn largest_free_run(&self) -> usize {
+        let mut largest = 0;
+        let mut run = 0;
+        for frame in 0..self.frames {
+            if self.is_used(frame) {
+                run = 0;
+            } else {
+                run += 1;
+                largest = largest.max(run);
+            }
+        }
+        largest
+    }
+
+    fn stats(&self) -> FrameStats {
+        FrameStats {
+            base: self.base,
+            total_frames: self.frames,
+            free_frames: self.free,
+            used_frames: self.frames - self.free - self.reserved - self.holes,
+            reserved_frames: self.reserved,
+            largest_free_run: self.largest_free_run(),
+            allocations: self.allocations,
+            frees: self.frees,
+            failures: self.failures,
+        }
+    }
+}
+
+/// グローバルフレームアロケータ
+static FRAME_ALLOCATOR: SpinLock<FrameAllocator> = SpinLock::new(FrameAllocator::empty());
+
+/// フレームアロケータの初期化
+///
+/// `platform::init`の後、プライマリハートから一度だけ呼ぶこと。
+///
+/// # Returns
+/// 成功時は`Ok(())`、ビットマップを置く空きがない場合は`OutOfMemory`
+pub fn init() -> Result<(), FrameError> {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    if allocator.is_initialized() {
+        return Err(FrameError::AlreadyInitialized);
+    }
+
+    let info = platform::boot_info();
+    let regions = info.memory_regions();
+    let layout = super::layout();
+
+    // 管理範囲: 全RAM領域を含むフレーム境界の範囲
+    let base = regions.iter().map(|r| r.base).min().unwrap_or(0) / FRAME_SIZE * FRAME_SIZE;
+    let end = regions.iter().map(|r| r.end()).max().unwrap_or(0) / FRAME_SIZE * FRAME_SIZE;
+    let frames = (end - base) / FRAME_SIZE;
+    let words = frames.div_ceil(BITS_PER_WORD);
+
+    // ビットマップ（使用中、予約の順）はカーネル直後のRAMに置く
+    let bitmap_start = layout.heap.start;
+    let bitmap_end = (bitmap_start + 2 * words * 8).next_multiple_of(FRAME_SIZE);
+    let Some(kernel_region) = regions.iter().find(|r| r.contains(layout.kernel_end - 1)) else {
+        return Err(FrameError::InvalidAddress);
+    };
+    if bitmap_end > kernel_region.end() {
+        return Err(FrameError::OutOfMemory);
+    }
+
+    allocator.bitmap = bitmap_start as *mut u64;
+    allocator.reserved_map = (bitmap_start + words * 8) as *mut u64;
+    allocator.words = words;
+    allocator.base = base;
+    allocator.frames = frames;
+
+    // 全フレームを使用中かつ予約（RAM外）にしてから、RAM領域内のフレームだけ空きにする
+    unsafe {
+        core::ptr::write_bytes(allocator.bitmap, 0xff, words);
+        core::ptr::write_bytes(allocator.reserved_map, 0xff, words);
+    }
+    for region in regions {
+        let (first, last) = allocator.frame_range(
+            region.base.next_multiple_of(FRAME_SIZE),
+            region.end() / FRAME_SIZE * FRAME_SIZE,
+        );
+        for frame in first..last {
+            if allocator.is_used(frame) {
+                allocator.set_free(frame);
+                allocator.set_reserved(frame, false);
+                allocator.free += 1;
+            }
+        }
+    }
+    allocator.holes = frames - allocator.free;
+
+    // ファームウェア、カーネルイメージ、スタック、ビットマップ
+    allocator.reserve(kernel_region.base, bitmap_end);
+
+    // デバイスツリーとそのメモリ予約ブロック
+    if info.dtb_addr != 0 {
+        allocator.reserve(info.dtb_addr, info.dtb_addr + info.dtb_size);
+        if let Ok(fdt) = unsafe { Fdt::from_addr(info.dtb_addr) } {
+            fdt.for_each_reservation(|addr, size| {
+                allocator.reserve(addr as usize, (addr + size) as usize);
+            });
+        }
+    }
+
+    allocator.hint = 0;
+    Ok(())
+}
+
+/// フレームアロケータが初期化済みかどうか
+pub fn is_initialized() -> bool {
+    FRAME_ALLOCATOR.lock().is_initialized()
+}
+
+/// 1フレームを割り当てる
+///
+/// # Returns
+/// フレームの物理アドレス（内容は初期化されない）
+pub fn alloc_frame() -> Result<usize, FrameError> {
+    FRAME_ALLOCATOR.lock().alloc(1, 1)
+}
+
+/// 物理的に連続した複数フレームを割り当てる
+///
+/// # Arguments
+/// * `count` - フレーム数
+///
+/// # Returns
+/// 先頭フレームの物理アドレス
+pub fn alloc_frames(count: usize) -> Result<usize, FrameError> {
+    FRAME_ALLOCATOR.lock().alloc(count, 1)
+}
+
+/// アラインメント指定付きで連続フレームを割り当てる
+///
+/// # Arguments
+/// * `count` - フレーム数
+/// * `align` - 先頭アドレスのアラインメント（フレーム数、2の累乗）
+///
+/// # Returns
+/// 先頭フレームの物理アドレス（`align * FRAME_SIZE`の倍数）
+pub fn alloc_frames_aligned(count: usize, align: usize) -> Result<usize, FrameError> {
+    FRAME_ALLOCATOR.lock().alloc(count, align)
+}
+
+/// 1フレームを解放する
+pub fn free_frame(addr: usize) -> Result<(), FrameError> {
+    FRAME_ALLOCATOR.lock().free(addr, 1)
+}
+
+/// `alloc_frames`で割り当てた連続フレームを解放する
+///
+/// # Arguments
+/// * `addr` - 先頭フレームの物理アドレス
+/// * `count` - 割り当て時と同じフレーム数
+pub fn free_frames(addr: usize, count: usize) -> Result<(), FrameError> {
+    FRAME_ALLOCATOR.lock().free(addr, count)
+}
+
+/// 物理アドレス範囲を予約し、以後割り当てられないようにする
+///
+/// # Arguments
+/// * `start` - 先頭アドレス（フレーム境界に切り下げ）
+/// * `size` - バイト数（フレーム境界に切り上げ）
+pub fn reserve(start: usize, size: usize) -> Result<(), FrameError> {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    if !allocator.is_initialized() {
+        return Err(FrameError::NotInitialized);
+    }
+    allocator.reserve(start, start + size);
+    Ok(())
+}
+
+/// 統計情報の取得
+pub fn stats() -> FrameStats {
+    FRAME_ALLOCATOR.lock().stats()
+}
+
+/// 統計情報の表示
+pub fn show_stats() {
+    if !is_initialized() {
+        println!("Frame allocator: not initialized");
+        return;
+    }
+
+    let stats = stats();
+    let kib = |frames: usize| num((frames * FRAME_SIZE / 1024) as u64);
+
+    println!("=== PHYSICAL FRAME ALLOCATOR ===");
+    println!(
+        "Managed range: {} - {}",
+        hex(stats.base),
+        hex(stats.base + stats.total_frames * FRAME_SIZE)
+    );
+    println!(
+        "Total:    {} frames ({} KiB)",
+        num(stats.total_frames as u64),
+        kib(stats.total_frames)
+    );
+    println!(
+        "Free:     {} frames ({} KiB)",
+        num(stats.free_frames as u64),
+        kib(stats.free_frames)
+    );
+    println!(
+        "Used:     {} frames ({} KiB)",
+        num(stats.used_frames as u64),
+        kib(stats.used_frames)
+    );
+    println!(
+        "Reserved: {} frames ({} KiB)",
+        num(stats.reserved_frames as u64),
+        kib(stats.reserved_frames)
+    );
+    println!(
+        "Largest free run: {} frames",
+        num(stats.largest_free_run as u64)
+    );
+    println!(
+        "Allocations: {}, frees: {}, failures: {}",
+        num(stats.allocations),
+        num(stats.frees),
+        num(stats.failures)
+    );
+}
@@ -0,0 +1,359 @@
+// カーネルヒープ（連結リスト方式の`#[global_allocator]`）
+//
+// フレームアロケータから連続フレームを借りてヒープ領域とし、空きブロックを
+// アドレス順の単方向リストで管理する。解放時には隣接する空きブロックと
+// 結合する。空きが足りない場合はフレームを追加で確保して拡張する。
+//
+// 割り当てに失敗するとallocクレートが`handle_alloc_error`を呼び、no_stdでは
+// パニックとして`#[panic_handler]`（`panic::enhanced_panic_handler`）に届く。
+// 失敗したレイアウトはここに記録され、パニック出力のヒープ統計に表示される。
+//
+// 注意: ヒープのロックは割り込みを禁止しないので、割り込みハンドラからは
+// 割り当てないこと。
+
+use super::frame::{self, FRAME_SIZE};
+use crate::console::num;
+use crate::println;
+use crate::sync::SpinLock;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+/// 初期ヒープのフレーム数（256KiB）
+const INITIAL_HEAP_FRAMES: usize = 64;
+
+/// 拡張時の最小フレーム数（64KiB）
+const GROW_FRAMES: usize = 16;
+
+/// ブロックの最小サイズとアラインメント（空きブロックヘッダのサイズ）
+const BLOCK_ALIGN: usize = core::mem::size_of::<FreeBlock>();
+
+/// 空きブロックのヘッダ（空きブロックの先頭に置く）
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// ヒープの統計
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    /// フレームアロケータから確保したヒープ領域の合計
+    pub heap_size: usize,
+
+    /// 割り当て中のバイト数（ブロック単位に切り上げ）
+    pub in_use: usize,
+
+    /// `in_use`の最大値
+    pub peak: usize,
+
+    /// 空きバイト数
+    pub free: usize,
+
+    /// 空きブロック数
+    pub free_blocks: usize,
+
+    /// 最大の空きブロック
+    pub largest_free_block: usize,
+
+    /// 断片化率（%）: 最大空きブロックに入らない空き容量の割合
+    pub fragmentation: usize,
+
+    /// ヒープ拡張の回数
+    pub grows: u64,
+
+    /// 成功した割り当て回数
+    pub allocations: u64,
+
+    /// 解放回数
+    pub frees: u64,
+
+    /// 失敗した割り当て回数
+    pub failures: u64,
+
+    /// 最後に失敗した要求（サイズ, アラインメント）
+    pub last_failure: Option<(usize, usize)>,
+}
+
+/// 連結リストヒープ本体
+struct Heap {
+    head: *mut FreeBlock,
+    heap_size: usize,
+    in_use: usize,
+    peak: usize,
+    grows: u64,
+    allocations: u64,
+    frees: u64,
+    failures: u64,
+    last_failure: Option<(usize, usize)>,
+}
+
+// Safety: 空きリストへのアクセスはHEAPのロックで排他される
+unsafe impl Send for Heap {}
+
+impl Heap {
+    const fn empty() -> Self {
+        Self {
+            head: ptr::null_mut(),
+            heap_size: 0,
+            in_use: 0,
+            peak: 0,
+            grows: 0,
+            allocations: 0,
+            frees: 0,
+            failures: 0,
+            last_failure: None,
+        }
+    }
+
+    /// 要求レイアウトを内部のブロックサイズとアラインメントに変換
+    fn block_layout(layout: Layout) -> (usize, usize) {
+        let size = layout.size().max(BLOCK_ALIGN).next_multiple_of(BLOCK_ALIGN);
+        let align = layout.align().max(BLOCK_ALIGN);
+        (size, align)
+    }
+
+    /// 空きブロックをアドレス順に挿入し、前後と結合する
+    ///
+    /// # Safety
+    /// `[addr, addr + size)`は未使用のヒープ領域で、`BLOCK_ALIGN`に揃っていること
+    unsafe fn insert_free(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut current = self.head;
+        while !current.is_null() && (current as usize) < addr {
+            prev = current;
+            current = (*current).next;
+        }
+
+        let block = addr as *mut FreeBlock;
+        block.write(FreeBlock {
+            size,
+            next: current,
+        });
+
+        // 後ろのブロックと結合
+        if !current.is_null() && addr + size == current as usize {
+            (*block).size += (*current).size;
+            (*block).next = (*current).next;
+        }
+
+        // 前のブロックと結合
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+
+    /// 空きリストから要求を満たすブロックを切り出す（ファーストフィット）
+    unsafe fn take(&mut self, size: usize, align: usize) -> Option<usize> {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut current = self.head;
+
+        while !current.is_null() {
+            let start = current as usize;
+            let end = start + (*current).size;
+            let aligned = start.next_multiple_of(align);
+
+            if aligned + size <= end {
+                // ブロックをリストから外し、前後の余りを戻す
+                let next = (*current).next;
+                if prev.is_null() {
+                    self.head = next;
+                } else {
+                    (*prev).next = next;
+                }
+
+                if aligned > start {
+                    self.insert_free(start, aligned - start);
+                }
+                if aligned + size < end {
+                    self.insert_free(aligned + size, end - (aligned + size));
+                }
+                return Some(aligned);
+            }
+
+            prev = current;
+            current = (*current).next;
+        }
+
+        None
+    }
+
+    /// フレームアロケータからヒープ領域を追加する
+    fn grow(&mut self, frames: usize) -> bool {
+        let Ok(addr) = frame::alloc_frames(frames) else {
+            return false;
+        };
+
+        let size = frames * FRAME_SIZE;
+        unsafe {
+            self.insert_free(addr, size);
+        }
+        self.heap_size += size;
+        self.grows += 1;
+        true
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::block_layout(layout);
+
+        let mut addr = unsafe { self.take(size, align) };
+        if addr.is_none() {
+            // アラインメントの余りも含めて入るだけのフレームを追加
+            let needed = (size + align).div_ceil(FRAME_SIZE);
+            if self.grow(needed.max(GROW_FRAMES)) {
+                addr = unsafe { self.take(size, align) };
+            }
+        }
+
+        match addr {
+            Some(addr) => {
+                self.in_use += size;
+                self.peak = self.peak.max(self.in_use);
+                self.allocations += 1;
+                addr as *mut u8
+            }
+            None => {
+                self.failures += 1;
+                self.last_failure = Some((layout.size(), layout.align()));
+                ptr::null_mut()
+            }
+        }
+    }
+
+    fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::block_layout(layout);
+        unsafe {
+            self.insert_free(ptr as usize, size);
+        }
+        self.in_use -= size;
+        self.frees += 1;
+    }
+
+    fn stats(&self) -> HeapStats {
+        let mut free = 0;
+        let mut free_blocks = 0;
+        let mut largest_free_block = 0;
+
+        let mut current = self.head;
+        while !current.is_null() {
+            let size = unsafe { (*current).size };
+            free += size;
+            free_blocks += 1;
+            largest_free_block = largest_free_block.max(size);
+            current = unsafe { (*current).next };
+        }
+
+        let fragmentation = if free == 0 {
+            0
+        } else {
+            (free - largest_free_block) * 100 / free
+        };
+
+        HeapStats {
+            heap_size: self.heap_size,
+            in_use: self.in_use,
+            peak: self.peak,
+            free,
+            free_blocks,
+            largest_free_block,
+            fragmentation,
+            grows: self.grows,
+            allocations: self.allocations,
+            frees: self.frees,
+            failures: self.failures,
+            last_failure: self.last_failure,
+        }
+    }
+}
+
+static HEAP: SpinLock<Heap> = SpinLock::new(Heap::empty());
+
+/// `GlobalAlloc`の実装（状態はHEAPが持つ）
+struct KernelHeap;
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        HEAP.lock().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        HEAP.lock().dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: KernelHeap = KernelHeap;
+
+/// ヒープの初期化
+///
+/// フレームアロケータの初期化後に呼ぶこと。以後`alloc`クレートの
+/// `Box`/`Vec`/`String`/`BTreeMap`などが使える。
+///
+/// # Returns
+/// 初期領域を確保できなかった場合はフレームアロケータのエラー
+pub fn init() -> Result<(), frame::FrameError> {
+    let mut heap = HEAP.lock();
+    if heap.heap_size != 0 {
+        return Err(frame::FrameError::AlreadyInitialized);
+    }
+
+    let addr = frame::alloc_frames(INITIAL_HEAP_FRAMES)?;
+    let size = INITIAL_HEAP_FRAMES * FRAME_SIZE;
+    unsafe {
+        heap.insert_free(addr, size);
+    }
+    heap.heap_size = size;
+    Ok(())
+}
+
+/// ヒープが初期化済みかどうか
+pub fn is_initialized() -> bool {
+    HEAP.lock().heap_size != 0
+}
+
+/// 統計情報の取得
+pub fn stats() -> HeapStats {
+    HEAP.lock().stats()
+}
+
+/// パニック時用の統計取得（ロック中なら`None`）
+///
+/// パニックがヒープ操作中に起きた場合でもデッドロックしないように
+/// `try_lock`を使う。
+pub fn try_stats() -> Option<HeapStats> {
+    HEAP.try_lock().map(|heap| heap.stats())
+}
+
+/// 統計情報の表示
+pub fn show_stats() {
+    if !is_initialized() {
+        println!("Kernel heap: not initialized");
+        return;
+    }
+
+    let stats = stats();
+    println!("=== KERNEL HEAP ===");
+    println!("Heap size: {} bytes", num(stats.heap_size as u64));
+    println!(
+        "In use:    {} bytes (peak {})",
+        num(stats.in_use as u64),
+        num(stats.peak as u64)
+    );
+    println!(
+        "Free:      {} bytes in {} blocks (largest {})",
+        num(stats.free as u64),
+        num(stats.free_blocks as u64),
+        num(stats.largest_free_block as u64)
+    );
+    println!("Fragmentation: {}%", num(stats.fragmentation as u64));
+    println!(
+        "Allocations: {}, frees: {}, failures: {}, grows: {}",
+        num(stats.allocations),
+        num(stats.frees),
+        num(stats.failures),
+        num(stats.grows)
+    );
+}
@@ -1,5 +1,5 @@
 // MSIP (Machine Software Interrupt Pending) 安全性検証
-use crate::{println, println_hex, println_number, UART0};
+use crate::{println, println_hex, println_number, trap, UART0};
 
 // QEMU virt machine CLINT addresses
 const CLINT_BASE: usize = 0x2000000;
@@ -201,10 +201,8 @@ pub fn basic_msip_test() {
 
 /// エラー処理付きMSIP読み取り
 pub fn safe_msip_read() -> Result<u32, &'static str> {
-    let msip_ptr = MSIP_BASE as *const u32;
-
-    // 読み取り試行
-    let val = unsafe { core::ptr::read_volatile(msip_ptr) };
+    // 読み取り試行（アクセス例外は例外修正テーブル経由でエラーになる）
+    let val = trap::probe_read::<u32>(MSIP_BASE).map_err(|fault| fault.as_str())?;
 
     // 基本的な値の妥当性チェック
     if val <= 1 {
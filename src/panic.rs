@@ -1,8 +1,11 @@
 // RISC-V Enhanced Panic Handler (Fixed Version)
 // 詳細なデバッグ情報とシステム状態ダンプ機能
 
+use crate::arch::current::{ipi, trap, Exception, CLINT_TIMER};
+use crate::arch::Timer;
 use crate::{arch::csr, panic_print, panic_print_hex, panic_print_number, panic_println, UART0};
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 /// パニック時のシステム状態
 #[derive(Clone, Copy)]
@@ -21,6 +24,9 @@ pub struct PanicState {
 static mut PANIC_COUNT: u32 = 0;
 static mut LAST_PANIC_PC: usize = 0;
 
+/// kassert!失敗時にパニックではなくデバッグモニタに入るか
+static BREAK_ON_ASSERT: AtomicBool = AtomicBool::new(false);
+
 /// 拡張パニックハンドラ
 pub fn enhanced_panic_handler(info: &PanicInfo) -> ! {
     // 割り込みを無効化してパニック処理を安全に実行
@@ -52,6 +58,9 @@ pub fn enhanced_panic_handler(info: &PanicInfo) -> ! {
     // パニック統計の表示
     print_panic_statistics();
 
+    // トラップ統計（原因別の回数と処理時間）
+    print_trap_statistics();
+
     // メモリ状況の簡易確認
     print_memory_status();
 
@@ -135,11 +144,7 @@ fn capture_system_state() -> PanicState {
 
 /// MIP (Machine Interrupt Pending) レジスタ読み取り
 fn read_mip() -> usize {
-    let mut val: usize;
-    unsafe {
-        core::arch::asm!("csrr {}, mip", out(reg) val);
-    }
-    val
+    csr::read_mip()
 }
 
 /// システム状態の詳細出力
@@ -192,9 +197,9 @@ fn print_system_state(state: &PanicState) {
 fn analyze_mstatus(mstatus: usize) {
     panic_println!("mstatus Analysis:");
 
-    let mie = (mstatus >> 3) & 1;
-    let mpie = (mstatus >> 7) & 1;
-    let mpp = (mstatus >> 11) & 3;
+    let mie = mstatus & csr::bits::STATUS_IE;
+    let mpie = mstatus & csr::bits::STATUS_PIE;
+    let mpp = (mstatus >> csr::bits::STATUS_PP_SHIFT) & csr::bits::STATUS_PP_MASK;
 
     panic_print!("  MIE (Global Interrupt Enable): ");
     if mie != 0 {
@@ -239,22 +244,27 @@ fn print_stack_info() {
     panic_println!();
 
     // スタック範囲の確認
-    let ram_start = 0x80000000;
-    let ram_end = 0x88000000; // 128MB
-    let stack_start = 0x80100000; // boot.sで設定されたスタック
+    let layout = crate::memory::layout();
+    let stack = layout.hart_stack(csr::read_mhartid() as usize); // boot.sで設定されたスタック
+    let stack_start = stack.end;
+    let stack_limit = stack.start + crate::memory::STACK_GUARD_SIZE;
 
     panic_print!("Stack base: ");
     panic_print_hex!(stack_start);
     panic_println!();
 
+    panic_print!("Stack limit: ");
+    panic_print_hex!(stack_limit);
+    panic_println!();
+
     panic_print!("RAM range:  ");
-    panic_print_hex!(ram_start);
+    panic_print_hex!(layout.ram.start);
     panic_print!(" to ");
-    panic_print_hex!(ram_end);
+    panic_print_hex!(layout.ram.end);
     panic_println!();
 
     // スタックの妥当性チェック
-    if sp >= ram_start && sp < ram_end {
+    if sp >= stack_limit && sp <= stack_start {
         panic_println!("Stack: ✓ Valid range");
 
         let stack_used = stack_start - sp;
@@ -262,10 +272,11 @@ fn print_stack_info() {
         panic_print_number!(stack_used as u64);
         panic_println!(" bytes");
 
-        if stack_used > 0x10000 {
-            // 64KB
+        if stack_used > (stack_start - stack_limit) * 3 / 4 {
             panic_println!("⚠ Stack usage high");
         }
+    } else if layout.ram.contains(sp) {
+        panic_println!("Stack: ⚠ Outside this hart's stack (trap or emergency stack?)");
     } else {
         panic_println!("Stack: ✗ CORRUPTED!");
     }
@@ -280,12 +291,14 @@ fn print_stack_info() {
 fn print_stack_dump(sp: usize) {
     panic_println!("Stack dump (last 8 words):");
 
+    let ram = crate::memory::layout().ram;
+
     // 8ワード（64バイト）をダンプ
     for i in 0..8 {
         let addr = sp + (i * 8);
 
         // アドレスの妥当性チェック
-        if addr >= 0x80000000 && addr < 0x88000000 {
+        if ram.contains(addr) {
             let value = unsafe { core::ptr::read_volatile(addr as *const u64) };
 
             panic_print!("  ");
@@ -314,62 +327,15 @@ fn analyze_trap_cause(state: &PanicState) {
     if interrupt {
         panic_println!(" (INTERRUPT)");
         panic_print!("Interrupt type: ");
-
-        if exception_code == 1 {
-            panic_println!("Supervisor software interrupt");
-        } else if exception_code == 3 {
-            panic_println!("Machine software interrupt");
-        } else if exception_code == 5 {
-            panic_println!("Supervisor timer interrupt");
-        } else if exception_code == 7 {
-            panic_println!("Machine timer interrupt");
-        } else if exception_code == 9 {
-            panic_println!("Supervisor external interrupt");
-        } else if exception_code == 11 {
-            panic_println!("Machine external interrupt");
-        } else {
-            panic_print!("Unknown interrupt (");
-            panic_print_number!(exception_code as u64);
-            panic_println!(")");
-        }
+        panic_print!(trap::interrupt_name(exception_code));
     } else {
         panic_println!(" (EXCEPTION)");
         panic_print!("Exception type: ");
-
-        if exception_code == 0 {
-            panic_println!("Instruction address misaligned");
-        } else if exception_code == 1 {
-            panic_println!("Instruction access fault");
-        } else if exception_code == 2 {
-            panic_println!("Illegal instruction");
-        } else if exception_code == 3 {
-            panic_println!("Breakpoint");
-        } else if exception_code == 4 {
-            panic_println!("Load address misaligned");
-        } else if exception_code == 5 {
-            panic_println!("Load access fault");
-        } else if exception_code == 6 {
-            panic_println!("Store/AMO address misaligned");
-        } else if exception_code == 7 {
-            panic_println!("Store/AMO access fault");
-        } else if exception_code == 8 {
-            panic_println!("Environment call from U-mode");
-        } else if exception_code == 9 {
-            panic_println!("Environment call from S-mode");
-        } else if exception_code == 11 {
-            panic_println!("Environment call from M-mode");
-        } else if exception_code == 12 {
-            panic_println!("Instruction page fault");
-        } else if exception_code == 13 {
-            panic_println!("Load page fault");
-        } else if exception_code == 15 {
-            panic_println!("Store/AMO page fault");
-        } else {
-            panic_print!("Unknown exception (");
-            panic_print_number!(exception_code as u64);
-            panic_println!(")");
-        }
+        panic_print!(Exception::from_code(exception_code).as_str());
     }
+    panic_print!(" (");
+    panic_print_number!(exception_code as u64);
+    panic_println!(")");
 
     // メモリアクセス関連の例外の場合、詳細情報
     if !interrupt && (exception_code == 1 || exception_code == 5 || exception_code == 7) {
@@ -379,7 +345,7 @@ fn analyze_trap_cause(state: &PanicState) {
         panic_println!();
 
         // アドレスの妥当性チェック
-        if state.mepc >= 0x80000000 && state.mepc < 0x88000000 {
+        if crate::memory::layout().is_kernel_address(state.mepc) {
             panic_println!("Fault address is in valid RAM range");
         } else {
             panic_println!("⚠ Fault address is OUTSIDE valid RAM range!");
@@ -409,23 +375,48 @@ fn print_panic_statistics() {
     panic_println!();
 }
 
+/// トラップ統計の出力（ハートごと、原因別）
+fn print_trap_statistics() {
+    use crate::arch::current::MAX_HARTS;
+    use crate::trap::stats;
+
+    panic_println!("=== TRAP STATISTICS ===");
+
+    for hart in 0..MAX_HARTS {
+        let total = stats::total(hart);
+        if total == 0 {
+            continue;
+        }
+
+        panic_print!("Hart ");
+        panic_print_number!(hart as u64);
+        panic_print!(": ");
+        panic_print_number!(total);
+        panic_println!(" traps");
+
+        stats::for_each_cause(hart, |_, name, cause| {
+            panic_print!("  ");
+            panic_print!(name);
+            panic_print!(": ");
+            panic_print_number!(cause.count);
+            panic_print!(" (max ");
+            panic_print_number!(cause.max_cycles);
+            panic_println!(" cycles)");
+        });
+    }
+
+    panic_println!();
+}
+
 /// メモリ状況の簡易確認
 fn print_memory_status() {
     panic_println!("=== MEMORY STATUS ===");
 
-    // 外部リンカシンボル
-    extern "C" {
-        static __bss_start: u8;
-        static __bss_end: u8;
-        static __data_start: u8;
-        static __data_end: u8;
-    }
-
-    unsafe {
-        let bss_start = &__bss_start as *const u8 as usize;
-        let bss_end = &__bss_end as *const u8 as usize;
-        let data_start = &__data_start as *const u8 as usize;
-        let data_end = &__data_end as *const u8 as usize;
+    // リンカシンボル由来のレイアウト
+    let layout = crate::memory::layout();
+    {
+        let (bss_start, bss_end) = (layout.bss.start, layout.bss.end);
+        let (data_start, data_end) = (layout.data.start, layout.data.end);
 
         panic_println!("Memory layout:");
         panic_print!("  .data start: ");
@@ -456,6 +447,38 @@ fn print_memory_status() {
         panic_println!(" bytes");
     }
 
+    // ヒープの状況（割り当て失敗によるパニックの場合は要求サイズも表示）
+    match crate::memory::heap::try_stats() {
+        Some(heap) if heap.heap_size != 0 => {
+            panic_println!("Kernel heap:");
+            panic_print!("  in use:      ");
+            panic_print_number!(heap.in_use as u64);
+            panic_print!(" / ");
+            panic_print_number!(heap.heap_size as u64);
+            panic_println!(" bytes");
+
+            panic_print!("  peak:        ");
+            panic_print_number!(heap.peak as u64);
+            panic_println!(" bytes");
+
+            panic_print!("  largest free: ");
+            panic_print_number!(heap.largest_free_block as u64);
+            panic_print!(" bytes (fragmentation ");
+            panic_print_number!(heap.fragmentation as u64);
+            panic_println!("%)");
+
+            if let Some((size, align)) = heap.last_failure {
+                panic_print!("  last failed allocation: ");
+                panic_print_number!(size as u64);
+                panic_print!(" bytes, align ");
+                panic_print_number!(align as u64);
+                panic_println!();
+            }
+        }
+        Some(_) => panic_println!("Kernel heap: not initialized"),
+        None => panic_println!("Kernel heap: locked (panic during allocation?)"),
+    }
+
     panic_println!();
 }
 
@@ -480,17 +503,12 @@ pub fn halt_system() -> ! {
     }
 
     // タイマを停止（無限に先の時間に設定）
-    let far_future = u64::MAX;
     unsafe {
-        let mtimecmp_addr = 0x2004000 as *mut u64;
-        core::ptr::write_volatile(mtimecmp_addr, far_future);
+        let _ = CLINT_TIMER.stop();
     }
 
     // MSIPもクリア
-    unsafe {
-        let msip_addr = 0x2000000 as *mut u32;
-        core::ptr::write_volatile(msip_addr, 0);
-    }
+    let _ = ipi::clear(csr::read_mhartid() as usize);
 
     // 最終的な停止ループ
     loop {
@@ -512,6 +530,35 @@ pub fn assertion_failed(condition: &str, file: &str, line: u32) -> ! {
     panic!("Assertion failed: {} at {}:{}", condition, file, line);
 }
 
+/// kassert!失敗時の動作を設定（true: 対話モードのモニタに入り、復帰後は処理を続行）
+///
+/// モニタが対話モードでない場合は、有効にしても通常どおりパニックする。
+pub fn set_break_on_assert(enabled: bool) {
+    BREAK_ON_ASSERT.store(enabled, Ordering::Relaxed);
+}
+
+/// アサーション失敗をブレークポイントで処理する（有効な場合）
+///
+/// 対話モードのモニタで処理した場合だけtrueを返し、呼び出し側はパニックしない。
+/// 報告だけのモードやモニタ無効では、失敗したアサーションの先へ進まないよう
+/// falseを返してパニックさせる。
+pub fn break_on_assert(condition: &'static str, file: &'static str, line: u32) -> bool {
+    use crate::trap::breakpoint::{monitor_mode, MonitorMode};
+
+    if !BREAK_ON_ASSERT.load(Ordering::Relaxed) || monitor_mode() != MonitorMode::Interactive {
+        return false;
+    }
+
+    crate::println!(
+        "Assertion failed: {} at {}:{}",
+        crate::console::str(condition),
+        crate::console::str(file),
+        crate::console::num(line as u64)
+    );
+    crate::kbreak!();
+    true
+}
+
 /// メモリ破損検出時のパニック
 pub fn memory_corruption_panic(address: usize, expected: u64, actual: u64) -> ! {
     panic!(
@@ -525,16 +572,16 @@ pub fn stack_overflow_panic(sp: usize, limit: usize) -> ! {
     panic!("Stack overflow: SP={:#x}, limit={:#x}", sp, limit);
 }
 
-/// より詳細なアサーションマクロ
+/// より詳細なアサーションマクロ（set_break_on_assert(true)の場合は失敗時にデバッグモニタに入る）
 #[macro_export]
 macro_rules! kassert {
     ($cond:expr) => {
-        if !($cond) {
+        if !($cond) && !$crate::panic::break_on_assert(stringify!($cond), file!(), line!()) {
             $crate::panic::assertion_failed(stringify!($cond), file!(), line!());
         }
     };
     ($cond:expr, $msg:expr) => {
-        if !($cond) {
+        if !($cond) && !$crate::panic::break_on_assert(stringify!($cond), file!(), line!()) {
             panic!("Assertion failed: {} - {}", stringify!($cond), $msg);
         }
     };
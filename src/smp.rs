@@ -0,0 +1,182 @@
+// マルチハート起動（セカンダリハートの解放と管理）
+//
+// セカンダリハートはboot.sの_startでハート別スタックを設定した後、
+// MSIEのみ有効にしたWFIループで待機する。プライマリハートが初期化を
+// 終えてからCLINTのMSIP配列で解放し、各ハートはrust_secondary_mainに入る。
+//
+// S-modeビルド（smode feature）ではSBIファームウェアが起動ハート以外を
+// 停止状態で保持しているため、SBI HSMのhart_startで_secondary_startから
+// 起動する。
+//
+// オンラインになったハートはソフトウェア割り込みを有効にして待機し、
+// ipiモジュールのメッセージ（関数呼び出し、TLBフラッシュ、停止など）を受け付ける。
+
+pub mod ipi;
+
+#[cfg(feature = "smode")]
+use crate::arch::current::sbi;
+use crate::arch::current::{timer::CLINT_TIMER, RiscvError, MAX_HARTS};
+use crate::arch::{csr, Timer};
+use crate::console::num;
+use crate::println;
+use core::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+
+/// セカンダリハートの起動待ちタイムアウト
+const START_TIMEOUT_MS: u64 = 100;
+
+/// ハートごとのオンライン状態
+static HART_ONLINE: [AtomicBool; MAX_HARTS] = [const { AtomicBool::new(false) }; MAX_HARTS];
+
+/// オンラインのハート数
+static ONLINE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// ハートをオンラインとして記録
+fn mark_online(hart: usize) {
+    if hart < MAX_HARTS && !HART_ONLINE[hart].swap(true, Ordering::AcqRel) {
+        ONLINE_COUNT.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// ハートをオフラインとして記録（IPIのStopで停止したとき）
+fn mark_offline(hart: usize) {
+    if hart < MAX_HARTS && HART_ONLINE[hart].swap(false, Ordering::AcqRel) {
+        ONLINE_COUNT.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// ハートがオンラインかどうか
+pub fn is_online(hart: usize) -> bool {
+    hart < MAX_HARTS && HART_ONLINE[hart].load(Ordering::Acquire)
+}
+
+/// オンラインのハート数
+pub fn online_count() -> usize {
+    ONLINE_COUNT.load(Ordering::Acquire)
+}
+
+/// オンラインのハートのビットマスク
+pub fn online_mask() -> usize {
+    (0..MAX_HARTS)
+        .filter(|&hart| is_online(hart))
+        .fold(0, |mask, hart| mask | (1 << hart))
+}
+
+/// プライマリハートの登録（rust_mainの先頭で呼ばれる）
+pub fn init_primary(hart: usize) {
+    mark_online(hart);
+}
+
+/// 待機中のセカンダリハートをMSIPで解放する
+///
+/// `hart_mask`に含まれるハート（プライマリを除く）にソフトウェア割り込みを
+/// 送り、オンラインになるまで待つ。応答しないハートはタイムアウトで無視される。
+///
+/// # Arguments
+/// * `hart_mask` - デバイスツリーの/cpusから得たハートIDのビットマスク
+///
+/// # Returns
+/// 新たにオンラインになったハート数
+pub fn start_secondary_harts(primary: usize, hart_mask: usize) -> usize {
+    let hart_mask = hart_mask & ((1 << MAX_HARTS) - 1);
+    let expected = (hart_mask | (1 << primary)).count_ones() as usize;
+    let before = online_count();
+
+    // BSS/.dataの初期化結果をセカンダリハートから見えるようにする
+    fence(Ordering::SeqCst);
+
+    for hart in 0..MAX_HARTS {
+        if hart_mask & (1 << hart) == 0 || hart == primary || is_online(hart) {
+            continue;
+        }
+
+        println!("Releasing hart {}...", num(hart as u64));
+        if release_hart(hart).is_err() {
+            println!("✗ Failed to signal hart {}", num(hart as u64));
+        }
+    }
+
+    // 起動完了を待つ
+    let deadline = CLINT_TIMER.now() + CLINT_TIMER.ms_to_ticks(START_TIMEOUT_MS);
+    while online_count() < expected && CLINT_TIMER.now() < deadline {
+        core::hint::spin_loop();
+    }
+
+    // 応答しなかったハートのMSIPを戻しておく（S-modeでは不要）
+    if cfg!(not(feature = "smode")) {
+        for hart in 0..MAX_HARTS {
+            if hart_mask & (1 << hart) != 0 && !is_online(hart) {
+                let _ = crate::arch::current::ipi::clear(hart);
+            }
+        }
+    }
+
+    online_count() - before
+}
+
+/// 待機中のハートを1つ解放する（M-mode: MSIPで起床させる）
+#[cfg(not(feature = "smode"))]
+fn release_hart(hart: usize) -> Result<(), RiscvError> {
+    crate::arch::current::ipi::send(hart)
+}
+
+/// 停止中のハートを1つ起動する（S-mode: SBI HSMで_secondary_startから開始）
+#[cfg(feature = "smode")]
+fn release_hart(hart: usize) -> Result<(), RiscvError> {
+    extern "C" {
+        fn _secondary_start();
+    }
+
+    sbi::hart_start(hart, _secondary_start as usize, 0)?;
+    Ok(())
+}
+
+/// オンライン状態の表示
+pub fn show_status() {
+    println!("Hart status:");
+    for hart in 0..MAX_HARTS {
+        if is_online(hart) {
+            println!("  hart {}: online", num(hart as u64));
+        } else {
+            println!("  hart {}: offline", num(hart as u64));
+        }
+    }
+    println!("Online harts: {}", num(online_count() as u64));
+}
+
+/// セカンダリハートのRustエントリポイント（boot.sから呼ばれる）
+#[unsafe(no_mangle)]
+pub extern "C" fn rust_secondary_main(hartid: usize) -> ! {
+    // 起床に使ったMSIPをクリア（S-modeでは何も保留されていない）
+    let _ = crate::arch::current::ipi::clear(hartid);
+
+    // PMPはハートごとのレジスタなので各ハートで設定する
+    #[cfg(not(feature = "smode"))]
+    if crate::arch::current::pmp::protect_kernel().is_err() {
+        println!("⚠ Secondary hart PMP setup failed");
+    }
+
+    // トラップスタックへの切り替えとスタック境界チェックを有効化
+    crate::trap::init_trap_stack(hartid);
+
+    // プライマリがページングを有効にした後に起動した場合はカーネル空間へ切り替える
+    // （有効化前に起動したハートはmmu::initがIPIで切り替える）
+    let _ = crate::arch::current::mmu::activate_kernel();
+
+    // PLICのコンテキストもハートごと（全ソース無効、しきい値0）
+    crate::arch::current::PLIC.init_hart(hartid);
+
+    // IPIを受けられるようにトラップベクタとソフトウェア割り込みを設定する
+    crate::trap::set_trap_mode(crate::trap::DEFAULT_TRAP_MODE);
+    unsafe {
+        let _ = csr::enable_machine_software_interrupt();
+    }
+
+    mark_online(hartid);
+
+    unsafe {
+        let _ = csr::enable_global_interrupts();
+    }
+
+    // 現時点でセカンダリハートに割り当てる仕事はないので、IPIと遅延処理を待って待機する
+    crate::interrupt::deferred::worker_loop()
+}
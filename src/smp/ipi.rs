@@ -0,0 +1,433 @@
+// src/smp/ipi.rs
+// ハート間割り込み（IPI）によるメッセージ送信
+//
+// 各ハートはメールボックスを1つ持つ。送信側はメールボックスにメッセージを
+// 積んでから宛先ハートのソフトウェア割り込みを上げ（M-mode: CLINTのMSIP配列、
+// S-mode: SBI IPI）、受信側はソフトウェア割り込みハンドラでまとめて処理する。
+//
+// 関数呼び出し以外のメッセージは処理されるまで1つにまとめられる。関数呼び出しは
+// 関数と引数の組をハートごとのキューに積む。
+//
+// 送信のたびに宛先ハートのシーケンス番号を進め、受信側は処理を終えた時点までの
+// 番号を応答済みとして公開する。送信側は返されたIpiTicketで応答を待てる。
+
+use crate::arch::csr::{self, bits};
+use crate::arch::current::{ipi, timer::CLINT_TIMER, MAX_HARTS, PAGE_SIZE};
+use crate::arch::Timer;
+use crate::console::{num, str};
+use crate::println;
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+/// ハートごとに積める関数呼び出し要求の数
+pub const CALL_QUEUE_LEN: usize = 8;
+
+/// 応答待ちの既定のタイムアウト
+const ACK_TIMEOUT_MS: u64 = 100;
+
+/// メッセージの種類数
+const MESSAGE_KINDS: usize = 4;
+
+const KIND_RESCHEDULE: usize = 0;
+const KIND_CALL_FUNCTION: usize = 1;
+const KIND_TLB_FLUSH: usize = 2;
+const KIND_STOP: usize = 3;
+
+const KIND_NAMES: [&str; MESSAGE_KINDS] = ["reschedule", "call-function", "tlb-flush", "stop"];
+
+/// TLBフラッシュ要求なし（ページ境界にない値を番兵に使う）
+const TLB_NONE: usize = usize::MAX;
+
+/// 全エントリのフラッシュ要求
+const TLB_ALL: usize = usize::MAX - 1;
+
+/// IPIで呼び出す関数（宛先ハートのトラップコンテキストで実行される）
+pub type IpiCall = fn(arg: usize);
+
+/// ハートに送るメッセージ
+#[derive(Debug, Clone, Copy)]
+pub enum IpiMessage {
+    /// 再スケジュール要求（スケジューラ導入までは起床と計数のみ）
+    Reschedule,
+    /// 関数を引数付きで呼び出す
+    CallFunction(IpiCall, usize),
+    /// TLBをフラッシュする（Noneなら全エントリ）
+    TlbFlush(Option<usize>),
+    /// ハートを停止する（オフラインになり、以後は割り込みも受けない）
+    Stop,
+}
+
+impl IpiMessage {
+    fn kind(&self) -> usize {
+        match self {
+            IpiMessage::Reschedule => KIND_RESCHEDULE,
+            IpiMessage::CallFunction(..) => KIND_CALL_FUNCTION,
+            IpiMessage::TlbFlush(_) => KIND_TLB_FLUSH,
+            IpiMessage::Stop => KIND_STOP,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        KIND_NAMES[self.kind()]
+    }
+}
+
+/// IPI送信エラー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpiError {
+    /// ハートマスクが空、または存在しないハートを含む
+    InvalidHart,
+    /// 宛先ハートがオンラインではない
+    HartOffline,
+    /// 関数呼び出しキューが一杯
+    QueueFull,
+    /// ソフトウェア割り込みを上げられなかった
+    SendFailed,
+    /// 応答が時間内に返らなかった
+    Timeout,
+}
+
+impl IpiError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IpiError::InvalidHart => "Invalid target hart",
+            IpiError::HartOffline => "Target hart is offline",
+            IpiError::QueueFull => "IPI call queue full",
+            IpiError::SendFailed => "Failed to raise software interrupt",
+            IpiError::Timeout => "IPI acknowledgement timed out",
+        }
+    }
+}
+
+impl core::fmt::Display for IpiError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+const SLOT_FREE: u8 = 0;
+const SLOT_WRITING: u8 = 1;
+const SLOT_READY: u8 = 2;
+
+/// 関数呼び出し要求のスロット
+struct CallSlot {
+    state: AtomicU8,
+    func: AtomicUsize,
+    arg: AtomicUsize,
+}
+
+impl CallSlot {
+    const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(SLOT_FREE),
+            func: AtomicUsize::new(0),
+            arg: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// ハート1つ分のメールボックス
+struct Mailbox {
+    /// 未処理のメッセージ種別（ビットごと）
+    pending: AtomicUsize,
+    calls: [CallSlot; CALL_QUEUE_LEN],
+    /// フラッシュするアドレス（TLB_NONE / TLB_ALL / ページアドレス）
+    tlb_addr: AtomicUsize,
+    /// 最後に積まれたメッセージのシーケンス番号
+    sent: AtomicU64,
+    /// 処理を終えたメッセージのシーケンス番号
+    acked: AtomicU64,
+    /// 種別ごとの処理回数
+    received: [AtomicU64; MESSAGE_KINDS],
+}
+
+impl Mailbox {
+    const fn new() -> Self {
+        Self {
+            pending: AtomicUsize::new(0),
+            calls: [const { CallSlot::new() }; CALL_QUEUE_LEN],
+            tlb_addr: AtomicUsize::new(TLB_NONE),
+            sent: AtomicU64::new(0),
+            acked: AtomicU64::new(0),
+            received: [const { AtomicU64::new(0) }; MESSAGE_KINDS],
+        }
+    }
+
+    /// メッセージの中身を積み、種別のビットを立てる
+    fn post(&self, message: &IpiMessage) -> Result<(), IpiError> {
+        match *message {
+            IpiMessage::CallFunction(func, arg) => self.push_call(func, arg)?,
+            IpiMessage::TlbFlush(addr) => {
+                let addr = addr.map_or(TLB_ALL, |addr| addr & !(PAGE_SIZE - 1));
+                // 別のアドレスの要求が残っていれば全体のフラッシュにまとめる
+                let _ = self
+                    .tlb_addr
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |old| {
+                        Some(if old == TLB_NONE || old == addr {
+                            addr
+                        } else {
+                            TLB_ALL
+                        })
+                    });
+            }
+            IpiMessage::Reschedule | IpiMessage::Stop => {}
+        }
+
+        self.pending.fetch_or(1 << message.kind(), Ordering::AcqRel);
+        Ok(())
+    }
+
+    fn push_call(&self, func: IpiCall, arg: usize) -> Result<(), IpiError> {
+        for slot in self.calls.iter() {
+            if slot
+                .state
+                .compare_exchange(
+                    SLOT_FREE,
+                    SLOT_WRITING,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                slot.func.store(func as usize, Ordering::Relaxed);
+                slot.arg.store(arg, Ordering::Relaxed);
+                slot.state.store(SLOT_READY, Ordering::Release);
+                return Ok(());
+            }
+        }
+        Err(IpiError::QueueFull)
+    }
+
+    /// 積まれている関数呼び出しをすべて実行する
+    ///
+    /// # Returns
+    /// 実行した数
+    fn run_calls(&self) -> usize {
+        let mut count = 0;
+        for slot in self.calls.iter() {
+            if slot.state.load(Ordering::Acquire) != SLOT_READY {
+                continue;
+            }
+            let func = slot.func.load(Ordering::Relaxed);
+            let arg = slot.arg.load(Ordering::Relaxed);
+            slot.state.store(SLOT_FREE, Ordering::Release);
+
+            let func: IpiCall = unsafe { core::mem::transmute(func) };
+            func(arg);
+            count += 1;
+        }
+        count
+    }
+
+    /// 要求されたTLBフラッシュを行う
+    fn flush_tlb(&self) {
+        match self.tlb_addr.swap(TLB_NONE, Ordering::AcqRel) {
+            TLB_NONE => {}
+            TLB_ALL => csr::sfence_vma_all(),
+            addr => csr::sfence_vma(addr),
+        }
+    }
+}
+
+static MAILBOXES: [Mailbox; MAX_HARTS] = [const { Mailbox::new() }; MAX_HARTS];
+
+fn current_hart() -> usize {
+    csr::read_mhartid() as usize
+}
+
+/// 送信したメッセージの応答待ちに使う控え
+#[derive(Debug, Clone, Copy)]
+pub struct IpiTicket {
+    mask: usize,
+    seq: [u64; MAX_HARTS],
+}
+
+impl IpiTicket {
+    const fn empty() -> Self {
+        Self {
+            mask: 0,
+            seq: [0; MAX_HARTS],
+        }
+    }
+
+    /// メッセージを受け付けたハートのマスク
+    pub fn harts(&self) -> usize {
+        self.mask
+    }
+
+    /// すべての宛先ハートが処理を終えたか
+    pub fn is_acked(&self) -> bool {
+        (0..MAX_HARTS).all(|hart| {
+            self.mask & (1 << hart) == 0
+                || MAILBOXES[hart].acked.load(Ordering::Acquire) >= self.seq[hart]
+        })
+    }
+
+    /// 既定のタイムアウトで応答を待つ
+    pub fn wait(&self) -> Result<(), IpiError> {
+        self.wait_timeout(ACK_TIMEOUT_MS)
+    }
+
+    /// 応答を待つ
+    ///
+    /// 自ハートが宛先に含まれていてソフトウェア割り込みを受けられない状態
+    /// （割り込み禁止中など）なら、待機中にその場でメールボックスを処理する。
+    pub fn wait_timeout(&self, timeout_ms: u64) -> Result<(), IpiError> {
+        let hart = current_hart();
+        let deadline = CLINT_TIMER.now() + CLINT_TIMER.ms_to_ticks(timeout_ms);
+
+        while !self.is_acked() {
+            if self.mask & (1 << hart) != 0 && !can_take_ipi() {
+                handle_ipi();
+                continue;
+            }
+            if CLINT_TIMER.now() >= deadline {
+                return Err(IpiError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+}
+
+/// 実行中のハートでソフトウェア割り込みが受け付けられるか
+fn can_take_ipi() -> bool {
+    csr::interrupts_enabled() && csr::read_mie() & bits::IE_SOFT != 0
+}
+
+/// ハートにメッセージを送る
+///
+/// # Returns
+/// 応答待ちに使うIpiTicket
+pub fn send_ipi(hart: usize, message: IpiMessage) -> Result<IpiTicket, IpiError> {
+    if hart >= MAX_HARTS {
+        return Err(IpiError::InvalidHart);
+    }
+    send_ipi_mask(1 << hart, message)
+}
+
+/// マスクに含まれる全ハートにメッセージを送る
+///
+/// 宛先はすべてオンラインである必要がある。途中のハートで関数呼び出し
+/// キューが一杯になった場合、それまでのハートには送信したうえでエラーを返す。
+pub fn send_ipi_mask(hart_mask: usize, message: IpiMessage) -> Result<IpiTicket, IpiError> {
+    if hart_mask == 0 || hart_mask >> MAX_HARTS != 0 {
+        return Err(IpiError::InvalidHart);
+    }
+    if (0..MAX_HARTS).any(|hart| hart_mask & (1 << hart) != 0 && !super::is_online(hart)) {
+        return Err(IpiError::HartOffline);
+    }
+
+    let mut ticket = IpiTicket::empty();
+    let mut error = None;
+    for hart in (0..MAX_HARTS).filter(|hart| hart_mask & (1 << hart) != 0) {
+        let mailbox = &MAILBOXES[hart];
+        if let Err(e) = mailbox.post(&message) {
+            error = Some(e);
+            break;
+        }
+        // 中身を積んでから番号を進める（受信側は番号を読んでから中身を取り出す）
+        ticket.seq[hart] = mailbox.sent.fetch_add(1, Ordering::AcqRel) + 1;
+        ticket.mask |= 1 << hart;
+    }
+
+    if ticket.mask != 0 && ipi::send_mask(ticket.mask).is_err() {
+        return Err(IpiError::SendFailed);
+    }
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(ticket),
+    }
+}
+
+/// マスクに含まれる全ハートで関数を呼び、完了を待つ
+pub fn call_function_mask(hart_mask: usize, func: IpiCall, arg: usize) -> Result<(), IpiError> {
+    send_ipi_mask(hart_mask, IpiMessage::CallFunction(func, arg))?.wait()
+}
+
+/// マスクに含まれる全ハートのTLBをフラッシュし、完了を待つ
+pub fn flush_tlb_mask(hart_mask: usize, addr: Option<usize>) -> Result<(), IpiError> {
+    send_ipi_mask(hart_mask, IpiMessage::TlbFlush(addr))?.wait()
+}
+
+/// 実行中ハート宛てのメッセージを処理する（ソフトウェア割り込みハンドラから呼ばれる）
+///
+/// ソフトウェア割り込みを先にクリアするので、処理中に届いたメッセージは
+/// 次の割り込みで処理される。Stopを受け取った場合は応答してから停止し、戻らない。
+///
+/// # Returns
+/// 処理したメッセージ数（0ならメッセージを伴わないソフトウェア割り込み）
+pub fn handle_ipi() -> usize {
+    let hart = current_hart();
+    let _ = ipi::clear(hart);
+
+    let Some(mailbox) = MAILBOXES.get(hart) else {
+        return 0;
+    };
+
+    let seq = mailbox.sent.load(Ordering::Acquire);
+    let pending = mailbox.pending.swap(0, Ordering::AcqRel);
+
+    let mut handled = [0u64; MESSAGE_KINDS];
+    if pending & (1 << KIND_RESCHEDULE) != 0 {
+        handled[KIND_RESCHEDULE] = 1;
+    }
+    if pending & (1 << KIND_TLB_FLUSH) != 0 {
+        mailbox.flush_tlb();
+        handled[KIND_TLB_FLUSH] = 1;
+    }
+    if pending & (1 << KIND_CALL_FUNCTION) != 0 {
+        handled[KIND_CALL_FUNCTION] = mailbox.run_calls() as u64;
+    }
+    if pending & (1 << KIND_STOP) != 0 {
+        handled[KIND_STOP] = 1;
+    }
+
+    for (counter, count) in mailbox.received.iter().zip(handled.iter()) {
+        counter.fetch_add(*count, Ordering::Relaxed);
+    }
+    mailbox.acked.fetch_max(seq, Ordering::AcqRel);
+
+    if pending & (1 << KIND_STOP) != 0 {
+        stop_current_hart(hart);
+    }
+
+    handled.iter().sum::<u64>() as usize
+}
+
+/// 実行中のハートをオフラインにして停止する
+fn stop_current_hart(hart: usize) -> ! {
+    super::mark_offline(hart);
+    unsafe {
+        csr::write_mie(0);
+        csr::disable_interrupts_save();
+    }
+    loop {
+        unsafe {
+            core::arch::asm!("wfi");
+        }
+    }
+}
+
+/// ハートごとの送受信状況を表示
+pub fn show_status() {
+    println!("=== IPI Status ===");
+    for (hart, mailbox) in MAILBOXES.iter().enumerate() {
+        let sent = mailbox.sent.load(Ordering::Relaxed);
+        if sent == 0 && !super::is_online(hart) {
+            continue;
+        }
+        println!(
+            "  hart {}: sent {}, acked {}",
+            num(hart as u64),
+            num(sent),
+            num(mailbox.acked.load(Ordering::Relaxed))
+        );
+        for (name, counter) in KIND_NAMES.into_iter().zip(mailbox.received.iter()) {
+            let count = counter.load(Ordering::Relaxed);
+            if count > 0 {
+                println!("    {}: {}", str(name), num(count));
+            }
+        }
+    }
+}
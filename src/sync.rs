@@ -0,0 +1,136 @@
+// 同期プリミティブ
+//
+// 複数ハートから共有されるカーネルデータ構造を保護するためのスピンロック。
+// 割り込みの禁止は行わないので、割り込みハンドラと共有するデータには
+// 呼び出し側で割り込みを止めてからロックを取ること。
+//
+// ハートごとに保持中のロック数を数えており、遅延処理はロックを保持していない
+// ときにだけトラップ出口で実行される。
+
+use crate::arch::csr;
+use crate::arch::current::MAX_HARTS;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// ハートごとの保持中のスピンロック数
+static HELD_LOCKS: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// 実行中のハートが保持しているスピンロックの数
+pub fn held_locks() -> usize {
+    HELD_LOCKS
+        .get(csr::read_mhartid() as usize)
+        .map_or(0, |held| held.load(Ordering::Relaxed))
+}
+
+/// ロックの取得を試みる前に保持数を増やす
+///
+/// 取得の直後に割り込まれても、トラップ出口の遅延処理がこのハートで
+/// 保持中のロックを待ってスピンしないよう、先に数える。
+///
+/// # Returns
+/// 実行中のハート（保持数を戻すときに使う）
+fn enter_lock() -> usize {
+    let hart = csr::read_mhartid() as usize;
+    if let Some(held) = HELD_LOCKS.get(hart) {
+        held.fetch_add(1, Ordering::Relaxed);
+    }
+    hart
+}
+
+/// ロックの解放後、または取得に失敗したときに保持数を戻す
+fn exit_lock(hart: usize) {
+    if let Some(held) = HELD_LOCKS.get(hart) {
+        held.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// スピンロック
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+// Safety: 内部データへのアクセスはロックで排他されるため、
+// T: Sendであればハート間で共有してよい
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    /// 新しいスピンロックを作成
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// ロックを取得（取得できるまでスピンする）
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let hart = enter_lock();
+        loop {
+            if self
+                .locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return SpinLockGuard { lock: self, hart };
+            }
+
+            // 解放されるまで読み取りのみで待つ
+            while self.locked.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// ロックの取得を1回だけ試みる
+    ///
+    /// # Returns
+    /// 取得できた場合はガード、他のハートが保持中なら`None`
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let hart = enter_lock();
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            exit_lock(hart);
+            return None;
+        }
+        Some(SpinLockGuard { lock: self, hart })
+    }
+
+    /// ロックが保持されているかどうか（診断用）
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+/// スピンロックのガード（ドロップ時に解放）
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    /// ロックを取得したハート
+    hart: usize,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        exit_lock(self.hart);
+    }
+}
@@ -26,182 +26,438 @@
 
 // NEW CODE (replace the above with this):
 
-use crate::arch::current::timer;
-use crate::{arch, println, println_hex, UART0};
+pub mod breakpoint;
+pub mod emulate;
+pub mod extable;
+pub mod fault;
+pub mod misaligned;
+pub mod stats;
+
+pub use extable::{probe_read, Fault};
+
+use crate::arch::csr::bits;
+use crate::arch::current::{timer, Exception, TrapFrame, TrapMode, MAX_HARTS, PLIC, TRAP_HANDLER};
+use crate::console::num;
+use crate::interrupt::WorkItem;
+use crate::{arch, interrupt, memory, print, println, println_hex, smp, UART0};
+use core::ptr::{addr_of, addr_of_mut};
 
 // Define traps
 #[derive(Debug)]
 pub enum TrapCause {
     SoftwareInterrupt, // Software interrupt
     TimerInterrupt,    // Timer interrupt
-    Ecall,
-    Other(usize),
+    ExternalInterrupt, // External interrupt (PLIC)
+    Interrupt(usize),  // Any other interrupt code
+    Ecall,             // Environment call from the kernel's own mode
+    Exception(Exception),
 }
 
 impl TrapCause {
     pub fn from_mcause(mcause: usize) -> Self {
-        let interrupt = (mcause >> 63) != 0;
-        let exception_code = mcause & 0x7FFFFFFFFFFFFFFF;
+        let interrupt = mcause & bits::MCAUSE_INTERRUPT_BIT != 0;
+        let code = mcause & bits::MCAUSE_EXCEPTION_MASK;
 
         if interrupt {
-            match exception_code {
-                3 => TrapCause::SoftwareInterrupt, // Machine software interrupt
-                7 => TrapCause::TimerInterrupt,    // Machine timer interrupt
-                _ => TrapCause::Other(mcause),
+            match code {
+                bits::INTERRUPT_SW => TrapCause::SoftwareInterrupt, // Software interrupt
+                bits::INTERRUPT_TIMER => TrapCause::TimerInterrupt, // Timer interrupt
+                bits::INTERRUPT_EXT => TrapCause::ExternalInterrupt, // External interrupt
+                _ => TrapCause::Interrupt(code),
             }
         } else {
-            match exception_code {
-                11 => TrapCause::Ecall, // Environment call from M-mode
-                _ => TrapCause::Other(mcause),
+            match code {
+                bits::EXCEPTION_ECALL => TrapCause::Ecall, // Environment call from own mode
+                _ => TrapCause::Exception(Exception::from_code(code)),
             }
         }
     }
 }
 
+/// Rust trap entry, called from `trap.s` with the saved register state
+///
+/// Handlers registered in `TRAP_HANDLER` take precedence over the built-in
+/// handling below. Changes to `frame` (registers, `mepc`, `mstatus`) take
+/// effect when the trap returns.
 #[no_mangle]
-pub extern "C" fn rust_trap_handler() {
-    let mcause = arch::csr::read_mcause();
-    let mepc = arch::csr::read_mepc();
+pub extern "C" fn rust_trap_handler(frame: &mut TrapFrame) {
+    let start = stats::start();
+    let previous = TRAP_HANDLER.begin_trap(frame);
 
-    let trap_cause = TrapCause::from_mcause(mcause);
+    // Faults at sites listed in the fixup table are expected, even inside
+    // another trap handler (debug tools probe memory from there)
+    if !frame.is_interrupt() && extable::fixup(frame) {
+        TRAP_HANDLER.end_trap(previous);
+        stats::record(frame.mcause, start);
+        return;
+    }
 
-    match trap_cause {
-        TrapCause::SoftwareInterrupt => {
-            // Software interrupt processing (existing code unchanged)
-            let msip_addr = 0x2000000 as *mut u32;
-            unsafe {
-                // Success marker (debug use)
-                core::ptr::write_volatile(UART0, b'[');
-                core::ptr::write_volatile(UART0, b'S');
-                core::ptr::write_volatile(UART0, b'W');
-                core::ptr::write_volatile(UART0, b']');
+    // An exception while another trap is being handled means the handler
+    // itself is broken; nested interrupts are fine
+    if !previous.is_null() && !frame.is_interrupt() {
+        nested_trap_panic(unsafe { &*previous }, frame);
+    }
 
-                // Clear MSIP directly (important: prevents infinite loop)
-                core::ptr::write_volatile(msip_addr, 0);
+    if !TRAP_HANDLER.dispatch(frame) {
+        handle_builtin(frame);
+    }
 
-                // Completion marker
-                core::ptr::write_volatile(UART0, b'S');
-                core::ptr::write_volatile(UART0, b'\n');
-            }
-        }
-        TrapCause::TimerInterrupt => {
-            // Timer interrupt processing (UPDATED for HAL)
-            unsafe {
-                // Timer interrupt marker (debug use)
-                core::ptr::write_volatile(UART0, b'[');
-                core::ptr::write_volatile(UART0, b'T');
-                core::ptr::write_volatile(UART0, b'I');
-                core::ptr::write_volatile(UART0, b'M');
-                core::ptr::write_volatile(UART0, b']');
-            }
+    TRAP_HANDLER.end_trap(previous);
+    stats::record(frame.mcause, start);
+    exit_trap(frame, previous);
+}
+
+/// Run deferred work once the outermost trap on this hart has been handled
+///
+/// Runs after the trap is accounted for, so deferred work does not count
+/// towards trap latency.
+fn exit_trap(frame: &TrapFrame, previous: *mut TrapFrame) {
+    if previous.is_null() {
+        interrupt::deferred::run_on_trap_exit(frame.mstatus);
+    }
+}
 
-            // Call unified HAL timer handler
-            timer::handle_timer_interrupt();
+/// Report an exception taken inside a trap handler with both contexts
+fn nested_trap_panic(outer: &TrapFrame, inner: &TrapFrame) -> ! {
+    println!("=== NESTED TRAP ===");
+    print!("Outer trap: ");
+    print_cause(outer.mcause);
+    outer.print();
 
-            unsafe {
-                core::ptr::write_volatile(UART0, b'T');
-                core::ptr::write_volatile(UART0, b'\n');
-            }
+    print!("Inner trap: ");
+    print_cause(inner.mcause);
+    let exception = Exception::from_code(inner.cause_code());
+    fault::FaultReport::new(inner, exception).print();
+
+    panic!("Exception inside trap handler (see nested trap report)");
+}
+
+/// Print the name of a trap cause
+fn print_cause(mcause: usize) {
+    let code = mcause & bits::MCAUSE_EXCEPTION_MASK;
+    if mcause & bits::MCAUSE_INTERRUPT_BIT != 0 {
+        print!(arch::current::trap::interrupt_name(code));
+    } else {
+        print!(Exception::from_code(code).as_str());
+    }
+    println!(" (code {})", num(code as u64));
+}
+
+/// Vectored-mode entry for software interrupts (no `mcause` decoding)
+#[no_mangle]
+pub extern "C" fn rust_software_interrupt(frame: &mut TrapFrame) {
+    vectored_interrupt(frame, bits::INTERRUPT_SW, handle_software_interrupt);
+}
+
+/// Vectored-mode entry for timer interrupts (no `mcause` decoding)
+#[no_mangle]
+pub extern "C" fn rust_timer_interrupt(frame: &mut TrapFrame) {
+    vectored_interrupt(frame, bits::INTERRUPT_TIMER, handle_timer_interrupt);
+}
+
+/// Vectored-mode entry for external interrupts (no `mcause` decoding)
+#[no_mangle]
+pub extern "C" fn rust_external_interrupt(frame: &mut TrapFrame) {
+    vectored_interrupt(frame, bits::INTERRUPT_EXT, handle_external_interrupt);
+}
+
+/// Run the registered handler for a known interrupt code, or the built-in one
+fn vectored_interrupt(frame: &mut TrapFrame, code: usize, builtin: fn(&mut TrapFrame)) {
+    let start = stats::start();
+    let previous = TRAP_HANDLER.begin_trap(frame);
+
+    if !TRAP_HANDLER.dispatch_interrupt(code, frame) {
+        builtin(frame);
+    }
+
+    TRAP_HANDLER.end_trap(previous);
+    stats::record(frame.mcause, start);
+    exit_trap(frame, previous);
+}
+
+fn handle_software_interrupt(_frame: &mut TrapFrame) {
+    // Clear MSIP/SSIP (important: prevents infinite loop) and process the
+    // hart's IPI mailbox
+    if smp::ipi::handle_ipi() > 0 {
+        return;
+    }
+
+    // A plain software interrupt without messages (e.g. yield)
+    unsafe {
+        // Success marker (debug use)
+        core::ptr::write_volatile(UART0, b'[');
+        core::ptr::write_volatile(UART0, b'S');
+        core::ptr::write_volatile(UART0, b'W');
+        core::ptr::write_volatile(UART0, b']');
+
+        // Completion marker
+        core::ptr::write_volatile(UART0, b'S');
+        core::ptr::write_volatile(UART0, b'\n');
+    }
+}
+
+/// Timer tick report, printed outside trap context
+static TIMER_TICK_REPORT: WorkItem = WorkItem::new(report_timer_tick, 0);
+
+fn handle_timer_interrupt(_frame: &mut TrapFrame) {
+    // Re-arm the timer in trap context; the console report is deferred
+    timer::handle_timer_interrupt();
+    TIMER_TICK_REPORT.schedule();
+}
+
+fn report_timer_tick(_data: usize) {
+    timer::report_tick();
+}
+
+/// Claim, dispatch and complete every pending PLIC source
+///
+/// Sources are dispatched to the handlers attached with
+/// `interrupt::request_irq`. A source nobody attached to is reported and
+/// masked on this hart to avoid an interrupt storm.
+fn handle_external_interrupt(_frame: &mut TrapFrame) {
+    let hart = arch::csr::read_mhartid() as usize;
+
+    while let Some(source) = PLIC.claim() {
+        if !interrupt::irq::handle_irq(source) {
+            println!("Unhandled IRQ {} - masking it", num(source as u64));
+            interrupt::irq::mask_irq(hart, source);
         }
+        PLIC.complete(source);
+    }
+}
+
+/// Kernel default handling for traps without a registered handler
+fn handle_builtin(frame: &mut TrapFrame) {
+    match TrapCause::from_mcause(frame.mcause) {
+        TrapCause::SoftwareInterrupt => handle_software_interrupt(frame),
+        TrapCause::TimerInterrupt => handle_timer_interrupt(frame),
+        TrapCause::ExternalInterrupt => handle_external_interrupt(frame),
         TrapCause::Ecall => {
             // ecall processing - advance mepc to next instruction
+            frame.skip_instruction(4);
             unsafe {
-                arch::csr::write_mepc(mepc + 4);
                 core::ptr::write_volatile(UART0, b'E');
                 core::ptr::write_volatile(UART0, b'\n');
             }
         }
-        TrapCause::Other(_cause) => {
-            // Debug information output (existing code unchanged)
-            let interrupt = (mcause >> 63) != 0;
-            let exception_code = mcause & 0x7FFFFFFFFFFFFFFF;
-
-            unsafe {
-                core::ptr::write_volatile(UART0, b'?');
-
-                // More detailed debug information
-                if interrupt {
-                    core::ptr::write_volatile(UART0, b'I'); // Interrupt
-
-                    // Output exception code (hex)
-                    let code_high = (exception_code >> 4) & 0xF;
-                    let code_low = exception_code & 0xF;
-
-                    let hex_high = if code_high < 10 {
-                        b'0' + code_high as u8
-                    } else {
-                        b'a' + (code_high - 10) as u8
-                    };
-                    let hex_low = if code_low < 10 {
-                        b'0' + code_low as u8
-                    } else {
-                        b'a' + (code_low - 10) as u8
-                    };
-
-                    core::ptr::write_volatile(UART0, hex_high);
-                    core::ptr::write_volatile(UART0, hex_low);
-                } else {
-                    core::ptr::write_volatile(UART0, b'E'); // Exception
-
-                    let code = exception_code & 0xF;
-                    let hex_char = if code < 10 {
-                        b'0' + code as u8
-                    } else {
-                        b'a' + (code - 10) as u8
-                    };
-                    core::ptr::write_volatile(UART0, hex_char);
-                }
-
-                // mepc details
-                core::ptr::write_volatile(UART0, b'@');
-                let mepc_low = (mepc >> 4) & 0xF;
-                let mepc_hex = if mepc_low < 10 {
-                    b'0' + mepc_low as u8
-                } else {
-                    b'a' + (mepc_low - 10) as u8
-                };
-                core::ptr::write_volatile(UART0, mepc_hex);
-
-                core::ptr::write_volatile(UART0, b'\n');
+        TrapCause::Exception(Exception::Breakpoint) if breakpoint::handle(frame) => {}
+        TrapCause::Exception(exception) => {
+            if emulate_exception(frame, exception) {
+                return;
             }
 
-            // Emergency handling for software interrupts that come to Other case
-            if interrupt && exception_code == 3 {
-                let msip_addr = 0x2000000 as *mut u32;
-                unsafe {
-                    // Emergency processing marker
-                    core::ptr::write_volatile(UART0, b'[');
-                    core::ptr::write_volatile(UART0, b'E');
-                    core::ptr::write_volatile(UART0, b'M');
-                    core::ptr::write_volatile(UART0, b'E');
-                    core::ptr::write_volatile(UART0, b'R');
-                    core::ptr::write_volatile(UART0, b'G');
-                    core::ptr::write_volatile(UART0, b']');
-
-                    core::ptr::write_volatile(msip_addr, 0); // Emergency MSIP clear
-
-                    core::ptr::write_volatile(UART0, b'S');
-                    core::ptr::write_volatile(UART0, b'\n');
-                }
+            // Let a registered fallback handler deal with it first
+            if !TRAP_HANDLER.dispatch_fallback(frame) {
+                fault::handle_exception(frame, exception);
+            }
+        }
+        TrapCause::Interrupt(code) => {
+            if !TRAP_HANDLER.dispatch_fallback(frame) {
+                handle_unexpected_interrupt(code);
             }
         }
     }
 }
 
-pub fn init_trap() {
-    extern "C" {
-        fn trap_handler();
+/// Try to recover from an exception by emulating the faulting instruction
+///
+/// # Returns
+/// `true` if the instruction was emulated and execution can continue
+fn emulate_exception(frame: &mut TrapFrame, exception: Exception) -> bool {
+    match exception {
+        Exception::LoadMisaligned | Exception::StoreMisaligned => misaligned::emulate(frame),
+        Exception::IllegalInstruction => emulate::emulate(frame),
+        _ => false,
+    }
+}
+
+/// Report an interrupt nobody handles and mask it to avoid an interrupt storm
+fn handle_unexpected_interrupt(code: usize) {
+    print!("Unexpected interrupt: ");
+    print!(arch::current::trap::interrupt_name(code));
+    println!(" (code {})", num(code as u64));
+
+    if code < usize::BITS as usize {
+        unsafe {
+            arch::csr::write_mie(arch::csr::read_mie() & !(1 << code));
+        }
+        println!("Interrupt {} masked in mie", num(code as u64));
+    }
+}
+
+/// Size of each hart's emergency stack used to report stack overflows
+const EMERGENCY_STACK_SIZE: usize = 4096;
+
+/// Size of each hart's trap stack (trap frames and Rust handlers)
+pub const TRAP_STACK_SIZE: usize = 16 * 1024;
+
+/// Per-hart trap state used by the trap entry (pointed to by mscratch)
+///
+/// The layout must match the `STATE_*` offsets in `trap.s`.
+#[repr(C)]
+struct HartTrapState {
+    /// Lowest usable task stack address (just above the guard page)
+    limit: usize,
+    /// Top of the task stack
+    top: usize,
+    /// Top of the emergency stack
+    emergency_top: usize,
+    /// Top of the trap stack
+    trap_top: usize,
+    /// Bottom of the trap stack
+    trap_limit: usize,
+    /// Number of traps currently being handled on this hart
+    depth: usize,
+    /// Save slots for t1 and t2 during entry
+    save_t1: usize,
+    save_t2: usize,
+}
+
+#[repr(C, align(16))]
+struct EmergencyStack([u8; EMERGENCY_STACK_SIZE]);
+
+#[repr(C, align(16))]
+struct TrapStack([u8; TRAP_STACK_SIZE]);
+
+static mut HART_TRAP_STATE: [HartTrapState; MAX_HARTS] = [const {
+    HartTrapState {
+        limit: 0,
+        top: 0,
+        emergency_top: 0,
+        trap_top: 0,
+        trap_limit: 0,
+        depth: 0,
+        save_t1: 0,
+        save_t2: 0,
+    }
+}; MAX_HARTS];
+
+static mut EMERGENCY_STACKS: [EmergencyStack; MAX_HARTS] =
+    [const { EmergencyStack([0; EMERGENCY_STACK_SIZE]) }; MAX_HARTS];
+
+static mut TRAP_STACKS: [TrapStack; MAX_HARTS] =
+    [const { TrapStack([0; TRAP_STACK_SIZE]) }; MAX_HARTS];
+
+/// Switch a hart's traps to its dedicated trap stack
+///
+/// Also enables stack bounds checking of the interrupted stack and nesting
+/// depth tracking in the trap entry. Must run on the hart itself, since it
+/// programs the hart's mscratch.
+///
+/// # Arguments
+/// * `hart` - The calling hart's ID
+pub fn init_trap_stack(hart: usize) {
+    if hart >= MAX_HARTS {
+        return;
+    }
+
+    let (bottom, top) = memory::hart_stack(hart);
+    unsafe {
+        let state = &mut (*addr_of_mut!(HART_TRAP_STATE))[hart];
+        let emergency = addr_of_mut!(EMERGENCY_STACKS[hart]) as usize;
+        let trap_stack = addr_of_mut!(TRAP_STACKS[hart]) as usize;
+        state.limit = bottom + memory::STACK_GUARD_SIZE;
+        state.top = top;
+        state.emergency_top = emergency + EMERGENCY_STACK_SIZE;
+        state.trap_limit = trap_stack;
+        state.trap_top = trap_stack + TRAP_STACK_SIZE;
+        state.depth = 0;
+        arch::csr::write_mscratch(state as *mut HartTrapState as usize);
     }
+}
+
+/// Trap stack of a hart
+///
+/// # Returns
+/// `(bottom, top)` of the hart's trap stack
+pub fn trap_stack(hart: usize) -> (usize, usize) {
+    let bottom = unsafe { addr_of_mut!(TRAP_STACKS[hart % MAX_HARTS]) as usize };
+    (bottom, bottom + TRAP_STACK_SIZE)
+}
+
+/// Number of traps currently being handled on the calling hart
+pub fn trap_depth() -> usize {
+    let state = arch::csr::read_mscratch() as *const HartTrapState;
+    if state.is_null() {
+        0
+    } else {
+        unsafe { core::ptr::read_volatile(addr_of!((*state).depth)) }
+    }
+}
+
+/// Called by the trap entry on the emergency stack when `sp` is out of bounds
+///
+/// `limit` is the trap stack bottom when nested traps exhausted the trap
+/// stack, and the task stack bound otherwise.
+///
+/// The entry clears mscratch before calling this, so traps taken while the
+/// report is printed use the current (emergency) stack without bounds
+/// checks, and `trap_depth` reads 0.
+#[no_mangle]
+extern "C" fn trap_stack_overflow_handler(sp: usize, limit: usize) -> ! {
+    let (trap_bottom, _) = trap_stack(arch::csr::read_mhartid() as usize);
+    if limit == trap_bottom {
+        println!("Trap stack exhausted (too many nested traps)");
+    }
+    crate::panic::stack_overflow_panic(sp, limit)
+}
+
+/// Trap vector mode used by `init_trap`
+///
+/// Direct mode decodes every trap in `rust_trap_handler`; switch with
+/// [`set_trap_mode`] to compare interrupt latency.
+pub const DEFAULT_TRAP_MODE: TrapMode = TrapMode::Direct;
 
-    let handler_addr = trap_handler as usize;
+extern "C" {
+    fn trap_handler();
+    fn trap_vector_table();
+}
+
+/// Install the trap vector on the calling hart
+///
+/// # Arguments
+/// * `mode` - `Direct` for the single `trap_handler` entry, `Vectored` for
+///   the per-interrupt entry stubs in `trap_vector_table`
+pub fn set_trap_mode(mode: TrapMode) {
+    let base = match mode {
+        TrapMode::Direct => trap_handler as usize,
+        TrapMode::Vectored => trap_vector_table as usize,
+    };
     unsafe {
-        arch::csr::write_mtvec(handler_addr);
+        arch::csr::write_mtvec(mode.tvec(base));
     }
+}
+
+/// Trap vector mode currently installed on the calling hart
+pub fn trap_mode() -> TrapMode {
+    TrapMode::from_tvec(arch::csr::read_mtvec())
+}
+
+pub fn init_trap() {
+    init_trap_stack(arch::csr::read_mhartid() as usize);
+
+    set_trap_mode(DEFAULT_TRAP_MODE);
+    unsafe {
+        PLIC.init();
+    }
+    let handler_addr = arch::csr::read_mtvec();
 
     println!("Safe trap handler initialized (HAL timer integrated)");
-    println_hex!("mtvec: ", handler_addr);
+    let (bottom, top) = memory::hart_stack(arch::csr::read_mhartid() as usize);
+    println_hex!("Stack limit: ", bottom + memory::STACK_GUARD_SIZE);
+    println_hex!("Stack top:   ", top);
+    let (_, trap_top) = trap_stack(arch::csr::read_mhartid() as usize);
+    println_hex!("Trap stack:  ", trap_top);
+    if cfg!(feature = "smode") {
+        println_hex!("stvec: ", handler_addr);
+    } else {
+        println_hex!("mtvec: ", handler_addr);
+    }
+    print!("Trap mode: ");
+    println!(trap_mode().as_str());
 }
 
+#[cfg(not(feature = "smode"))]
 pub fn test_ecall_safe() {
     println!("Testing safe ecall...");
     unsafe {
@@ -209,3 +465,15 @@ pub fn test_ecall_safe() {
     }
     println!("Safe ecall returned!");
 }
+
+/// In S-mode an ecall goes to the SBI firmware, so issue a harmless
+/// Base extension call instead of a bare ecall
+#[cfg(feature = "smode")]
+pub fn test_ecall_safe() {
+    println!("Testing safe ecall (SBI)...");
+    match arch::current::sbi::get_spec_version() {
+        Ok(version) => println_hex!("SBI spec version: ", version),
+        Err(_) => println!("SBI base extension not available"),
+    }
+    println!("Safe ecall returned!");
+}
@@ -0,0 +1,323 @@
+// src/trap/breakpoint.rs
+//! Kernel breakpoints and the UART debug monitor
+//!
+//! [`kbreak!`](crate::kbreak) executes `ebreak`. Breakpoints without a
+//! registered handler are passed to [`handle`]. By default it prints the
+//! registers and a backtrace and resumes, so headless runs never block.
+//! This deliberately differs from suspending into the monitor on every
+//! `kbreak!()`: that is opt-in with [`MonitorMode::Interactive`], which
+//! suspends the hart in a small command monitor on the console UART that
+//! shows registers, memory and a backtrace of the interrupted code. Memory
+//! is read through [`probe_read`], so bad addresses are reported instead of
+//! faulting. Both resume after the `ebreak`, which is 2 or 4 bytes long
+//! depending on whether it was compressed.
+//!
+//! A failed `kassert!` only breaks into the monitor (instead of panicking)
+//! in interactive mode; otherwise it must not run past the assertion.
+//!
+//! Only the hart that hit the breakpoint stops; the others keep running.
+
+use super::extable::probe_read;
+use crate::arch::current::trap::{reg, REG_NAMES};
+use crate::arch::current::TrapFrame;
+use crate::console::{self, hex, num, str};
+use crate::{arch, debug, print, println};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+/// Longest command line accepted by the monitor
+const LINE_MAX: usize = 64;
+
+/// Bytes dumped by `mem` when no length is given
+const DUMP_DEFAULT: usize = 64;
+
+/// Largest dump a single `mem` command prints
+const DUMP_MAX: usize = 512;
+
+/// Maximum depth of the monitor backtrace
+const BACKTRACE_DEPTH: usize = 16;
+
+/// Break into the debug monitor
+///
+/// Executes `ebreak`; execution continues after it once the monitor has
+/// reported the breakpoint (or exited, in interactive mode).
+#[macro_export]
+macro_rules! kbreak {
+    () => {
+        unsafe { core::arch::asm!("ebreak") }
+    };
+}
+
+/// What a breakpoint without a registered handler does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MonitorMode {
+    /// Report it through the fault path (`trap::fault` breakpoint policy)
+    Off = 0,
+    /// Print registers and a backtrace, then resume without waiting (default)
+    Report = 1,
+    /// Wait for commands on the console UART (opt-in, blocks until `c`)
+    Interactive = 2,
+}
+
+impl MonitorMode {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => MonitorMode::Off,
+            1 => MonitorMode::Report,
+            _ => MonitorMode::Interactive,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MonitorMode::Off => "off",
+            MonitorMode::Report => "report",
+            MonitorMode::Interactive => "interactive",
+        }
+    }
+}
+
+static MODE: AtomicU8 = AtomicU8::new(MonitorMode::Report as u8);
+
+/// Number of breakpoints handled by the monitor
+static BREAKPOINT_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Serializes the monitor between harts (they share the UART)
+static MONITOR_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Set what unhandled breakpoints do
+pub fn set_monitor_mode(mode: MonitorMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// What unhandled breakpoints currently do
+pub fn monitor_mode() -> MonitorMode {
+    MonitorMode::from_u8(MODE.load(Ordering::Relaxed))
+}
+
+/// Number of breakpoints handled by the monitor since boot
+pub fn breakpoint_count() -> u64 {
+    BREAKPOINT_COUNT.load(Ordering::Relaxed)
+}
+
+/// Handle a breakpoint exception
+///
+/// # Arguments
+/// * `frame` - Saved state at the `ebreak`; registers and `mepc` may be
+///   changed from the monitor
+///
+/// # Returns
+/// `false` if the monitor is off and the breakpoint must be reported
+pub fn handle(frame: &mut TrapFrame) -> bool {
+    let mode = monitor_mode();
+    if mode == MonitorMode::Off {
+        return false;
+    }
+
+    BREAKPOINT_COUNT.fetch_add(1, Ordering::Relaxed);
+
+    while MONITOR_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+
+    let pc = frame.mepc;
+    print_banner(frame);
+    if mode == MonitorMode::Report {
+        frame.print();
+        backtrace(frame);
+    } else {
+        run_monitor(frame);
+    }
+
+    MONITOR_LOCK.store(false, Ordering::Release);
+
+    // Step over the ebreak unless the monitor moved the PC
+    if frame.mepc == pc {
+        let len = unsafe { frame.instruction_length() };
+        frame.skip_instruction(len);
+    }
+    true
+}
+
+fn print_banner(frame: &TrapFrame) {
+    println!("=== KERNEL BREAKPOINT ===");
+    println!(
+        "Hart: {}  pc: {}  trap depth: {}",
+        num(arch::csr::read_mhartid()),
+        hex(frame.mepc),
+        num(super::trap_depth() as u64)
+    );
+}
+
+fn backtrace(frame: &TrapFrame) {
+    debug::print_stack_trace_from(frame.reg(reg::FP), frame.mepc, BACKTRACE_DEPTH);
+}
+
+/// Read and execute commands until `continue`
+fn run_monitor(frame: &mut TrapFrame) {
+    println!("Type 'help' for commands, 'c' to continue");
+
+    let mut line = [0u8; LINE_MAX];
+    loop {
+        print!("kdb> ");
+        let len = read_line(&mut line);
+        let Ok(text) = core::str::from_utf8(&line[..len]) else {
+            println!("Invalid input");
+            continue;
+        };
+
+        let mut words = text.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+
+        match command {
+            "c" | "continue" => break,
+            "r" | "regs" => frame.print(),
+            "m" | "mem" => mem_command(words.next(), words.next()),
+            "bt" => backtrace(frame),
+            "set" => set_command(frame, words.next(), words.next()),
+            "i" | "info" => print_banner(frame),
+            "h" | "help" => print_help(),
+            _ => {
+                print!("Unknown command: ");
+                print!(command);
+                println!(" (try 'help')");
+            }
+        }
+    }
+
+    println!("Resuming at {}", hex(frame.mepc));
+}
+
+fn print_help() {
+    println!("  regs | r                  show saved registers");
+    println!("  mem | m <addr> [len]      dump memory (hex)");
+    println!("  bt                        backtrace");
+    println!("  set <reg|pc> <value>      change a register (hex)");
+    println!("  info | i                  breakpoint location");
+    println!("  continue | c              resume execution");
+}
+
+/// Read one line with echo and backspace handling
+///
+/// # Returns
+/// Number of bytes stored in `buffer`
+fn read_line(buffer: &mut [u8]) -> usize {
+    let mut len = 0;
+    loop {
+        match console::get_char() {
+            b'\r' | b'\n' => {
+                println!();
+                return len;
+            }
+            // Backspace / DEL
+            0x08 | 0x7f => {
+                if len > 0 {
+                    len -= 1;
+                    print!("\x08 \x08");
+                }
+            }
+            c if len < buffer.len() && (0x20..0x7f).contains(&c) => {
+                buffer[len] = c;
+                len += 1;
+                console::put_char(c);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parse a hexadecimal number with an optional `0x` prefix
+fn parse_hex(text: &str) -> Option<usize> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    usize::from_str_radix(digits, 16).ok()
+}
+
+/// Parse a register name (`a0`, `fp`, `x10`, ...)
+fn parse_register(text: &str) -> Option<usize> {
+    if text == "fp" {
+        return Some(reg::FP);
+    }
+    if let Some(index) = REG_NAMES.iter().position(|name| *name == text) {
+        return Some(index);
+    }
+    let index: usize = text.strip_prefix('x')?.parse().ok()?;
+    (index < 32).then_some(index)
+}
+
+fn mem_command(addr: Option<&str>, len: Option<&str>) {
+    let Some(addr) = addr.and_then(parse_hex) else {
+        println!("Usage: mem <addr> [len]");
+        return;
+    };
+    let len = len.and_then(parse_hex).unwrap_or(DUMP_DEFAULT);
+    dump_memory(addr, len.min(DUMP_MAX));
+}
+
+/// Print memory as 32-bit words, four per line
+///
+/// Words that cannot be read are shown as `????????`.
+fn dump_memory(addr: usize, len: usize) {
+    let start = addr & !3;
+    let end = addr.saturating_add(len);
+
+    let mut line = start;
+    while line < end {
+        print!("{}:", hex(line));
+        for word in 0..4 {
+            let addr = line + word * 4;
+            if addr >= end {
+                break;
+            }
+            console::put_char(b' ');
+            match probe_read::<u32>(addr) {
+                Ok(value) => print_word(value),
+                Err(_) => print!("????????"),
+            }
+        }
+        println!();
+        line += 16;
+    }
+}
+
+/// Print a 32-bit value as eight hex digits
+fn print_word(value: u32) {
+    const HEX_CHARS: &[u8] = b"0123456789abcdef";
+    for shift in (0..8).rev() {
+        console::put_char(HEX_CHARS[(value >> (shift * 4)) as usize & 0xf]);
+    }
+}
+
+fn set_command(frame: &mut TrapFrame, name: Option<&str>, value: Option<&str>) {
+    let (Some(name), Some(value)) = (name, value.and_then(parse_hex)) else {
+        println!("Usage: set <reg|pc> <value>");
+        return;
+    };
+
+    if name == "pc" {
+        frame.mepc = value;
+    } else if let Some(index) = parse_register(name) {
+        frame.set_reg(index, value);
+    } else {
+        print!("Unknown register: ");
+        println!(name);
+        return;
+    }
+
+    print!(name);
+    println!(" = {}", hex(value));
+}
+
+/// Print the monitor configuration
+pub fn show_status() {
+    println!("=== Debug Monitor ===");
+    println!("Mode: {}", str(monitor_mode().as_str()));
+    println!("Breakpoints handled: {}", num(breakpoint_count()));
+}
@@ -0,0 +1,387 @@
+// src/trap/emulate.rs
+//! Illegal-instruction emulation
+//!
+//! When `EXCEPTION_ILLEGAL_INSTR` is raised, the trap path offers the
+//! faulting instruction to a chain of emulators before reporting a fault.
+//! An emulator that recognizes the instruction updates the trap frame and
+//! returns `true`; the framework then steps over the instruction.
+//!
+//! Built-in emulators:
+//! - `rdtime`/`rdtimeh` (and other read-only accesses to `time`/`timeh`),
+//!   backed by `ClintTimer::read_mtime` (M-mode builds only; in S-mode the
+//!   SBI firmware emulates `time`)
+//! - The M extension (`mul*`, `div*`, `rem*`), off by default and enabled
+//!   with [`set_muldiv_emulation`]. The kernel itself is built for rv64gc
+//!   (boot code and the trap path use `mul`/`div`), so it does not run on
+//!   cores without M and the emulator is not a substitute for the extension.
+//!
+//! Further emulators can be added at runtime with [`register_emulator`].
+
+use crate::arch::current::TrapFrame;
+use crate::console::num;
+use crate::println;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Maximum number of registered emulators
+pub const MAX_EMULATORS: usize = 8;
+
+/// Emulator for an illegal instruction
+///
+/// # Arguments
+/// * `frame` - Saved state of the faulting code
+/// * `insn` - Instruction bits (a compressed instruction in the low 16 bits)
+///
+/// # Returns
+/// `true` if the instruction was emulated; the framework then advances
+/// `mepc` past it
+pub type EmulatorFn = fn(frame: &mut TrapFrame, insn: u32) -> bool;
+
+/// Emulator registration errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulatorError {
+    /// All emulator slots are in use
+    TableFull,
+    /// The emulator is already registered
+    AlreadyRegistered,
+}
+
+impl EmulatorError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmulatorError::TableFull => "Emulator table full",
+            EmulatorError::AlreadyRegistered => "Emulator already registered",
+        }
+    }
+}
+
+impl core::fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Illegal-instruction emulation statistics
+#[derive(Debug, Clone, Copy)]
+pub struct EmulationStats {
+    /// `time`/`timeh` reads emulated
+    pub rdtime: u64,
+    /// M-extension instructions emulated
+    pub muldiv: u64,
+    /// Instructions emulated by registered emulators
+    pub custom: u64,
+    /// Illegal instructions no emulator recognized
+    pub failed: u64,
+}
+
+static EMULATORS: [AtomicUsize; MAX_EMULATORS] = [const { AtomicUsize::new(0) }; MAX_EMULATORS];
+static MULDIV_EMULATION: AtomicBool = AtomicBool::new(false);
+
+static RDTIME_COUNT: AtomicU64 = AtomicU64::new(0);
+static MULDIV_COUNT: AtomicU64 = AtomicU64::new(0);
+static CUSTOM_COUNT: AtomicU64 = AtomicU64::new(0);
+static FAILED_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Enable or disable software emulation of the M extension
+pub fn set_muldiv_emulation(enabled: bool) {
+    MULDIV_EMULATION.store(enabled, Ordering::Relaxed);
+}
+
+/// Check whether software emulation of the M extension is enabled
+pub fn muldiv_emulation() -> bool {
+    MULDIV_EMULATION.load(Ordering::Relaxed)
+}
+
+/// Register an emulator for illegal instructions
+///
+/// Emulators are tried in registration order after the built-in ones.
+///
+/// # Arguments
+/// * `emulator` - Function that recognizes and emulates instructions
+///
+/// # Returns
+/// `Ok(())` on success, or an error if the table is full or the emulator
+/// is already registered
+pub fn register_emulator(emulator: EmulatorFn) -> Result<(), EmulatorError> {
+    let addr = emulator as usize;
+    if EMULATORS
+        .iter()
+        .any(|slot| slot.load(Ordering::Acquire) == addr)
+    {
+        return Err(EmulatorError::AlreadyRegistered);
+    }
+
+    for slot in EMULATORS.iter() {
+        if slot
+            .compare_exchange(0, addr, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+    Err(EmulatorError::TableFull)
+}
+
+/// Remove a registered emulator
+pub fn unregister_emulator(emulator: EmulatorFn) {
+    let addr = emulator as usize;
+    for slot in EMULATORS.iter() {
+        let _ = slot.compare_exchange(addr, 0, Ordering::AcqRel, Ordering::Acquire);
+    }
+}
+
+/// Get emulation statistics
+pub fn stats() -> EmulationStats {
+    EmulationStats {
+        rdtime: RDTIME_COUNT.load(Ordering::Relaxed),
+        muldiv: MULDIV_COUNT.load(Ordering::Relaxed),
+        custom: CUSTOM_COUNT.load(Ordering::Relaxed),
+        failed: FAILED_COUNT.load(Ordering::Relaxed),
+    }
+}
+
+/// Print emulation statistics
+pub fn show_stats() {
+    let stats = stats();
+    println!("=== Illegal Instruction Emulation ===");
+    println!("rdtime emulated:  {}", num(stats.rdtime));
+    println!("mul/div emulated: {}", num(stats.muldiv));
+    println!("Custom emulated:  {}", num(stats.custom));
+    println!("Not recognized:   {}", num(stats.failed));
+    let registered = EMULATORS
+        .iter()
+        .filter(|slot| slot.load(Ordering::Relaxed) != 0)
+        .count();
+    println!("Registered emulators: {}", num(registered as u64));
+}
+
+/// Emulate the illegal instruction that trapped
+///
+/// # Arguments
+/// * `frame` - Saved state of the faulting code
+///
+/// # Returns
+/// `true` if the instruction was emulated and `mepc` advanced
+pub fn emulate(frame: &mut TrapFrame) -> bool {
+    let insn = fetch_instruction(frame);
+    let len = if insn & 0b11 == 0b11 { 4 } else { 2 };
+
+    let emulated = if emulate_builtin(frame, insn) {
+        true
+    } else if emulate_registered(frame, insn) {
+        CUSTOM_COUNT.fetch_add(1, Ordering::Relaxed);
+        true
+    } else {
+        false
+    };
+
+    if emulated {
+        frame.skip_instruction(len);
+    } else {
+        FAILED_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+    emulated
+}
+
+/// Instruction bits from `mtval`, or fetched from `mepc` if not provided
+fn fetch_instruction(frame: &TrapFrame) -> u32 {
+    if frame.mtval != 0 {
+        return frame.mtval as u32;
+    }
+
+    // The instruction may only be 2-byte aligned, so fetch it in halves
+    let low = unsafe { core::ptr::read_volatile(frame.mepc as *const u16) } as u32;
+    if low & 0b11 != 0b11 {
+        return low;
+    }
+    let high = unsafe { core::ptr::read_volatile((frame.mepc + 2) as *const u16) } as u32;
+    low | (high << 16)
+}
+
+fn emulate_builtin(frame: &mut TrapFrame, insn: u32) -> bool {
+    #[cfg(not(feature = "smode"))]
+    if emulate_rdtime(frame, insn) {
+        RDTIME_COUNT.fetch_add(1, Ordering::Relaxed);
+        return true;
+    }
+
+    if MULDIV_EMULATION.load(Ordering::Relaxed) && emulate_muldiv(frame, insn) {
+        MULDIV_COUNT.fetch_add(1, Ordering::Relaxed);
+        return true;
+    }
+
+    false
+}
+
+fn emulate_registered(frame: &mut TrapFrame, insn: u32) -> bool {
+    for slot in EMULATORS.iter() {
+        let addr = slot.load(Ordering::Acquire);
+        if addr == 0 {
+            continue;
+        }
+        // Only ever stores `EmulatorFn` addresses (see `register_emulator`)
+        let emulator = unsafe { core::mem::transmute::<usize, EmulatorFn>(addr) };
+        if emulator(frame, insn) {
+            return true;
+        }
+    }
+    false
+}
+
+/// CSR number of `time`
+#[cfg(not(feature = "smode"))]
+const CSR_TIME: u32 = 0xC01;
+
+/// CSR number of `timeh`
+#[cfg(not(feature = "smode"))]
+const CSR_TIMEH: u32 = 0xC81;
+
+/// Emulate read-only accesses to `time`/`timeh` (`rdtime`, `rdtimeh`)
+#[cfg(not(feature = "smode"))]
+fn emulate_rdtime(frame: &mut TrapFrame, insn: u32) -> bool {
+    if insn & 0x7f != 0x73 {
+        return false;
+    }
+
+    let funct3 = (insn >> 12) & 0x7;
+    let rs1 = (insn >> 15) & 0x1f;
+    let rd = ((insn >> 7) & 0x1f) as usize;
+    let csr = insn >> 20;
+
+    // csrrs/csrrc (and the immediate forms) with x0/0 only read the CSR
+    let read_only = matches!(funct3, 2 | 3 | 6 | 7) && rs1 == 0;
+    if !read_only {
+        return false;
+    }
+
+    let time = crate::arch::current::CLINT_TIMER.read_mtime();
+    let value = match csr {
+        CSR_TIME => time as usize,
+        CSR_TIMEH => (time >> 32) as usize,
+        _ => return false,
+    };
+    frame.set_reg(rd, value);
+    true
+}
+
+/// Emulate an M-extension instruction (OP / OP-32 with funct7 = 1)
+fn emulate_muldiv(frame: &mut TrapFrame, insn: u32) -> bool {
+    let opcode = insn & 0x7f;
+    if (opcode != 0x33 && opcode != 0x3b) || insn >> 25 != 1 {
+        return false;
+    }
+
+    let funct3 = (insn >> 12) & 0x7;
+    let rd = ((insn >> 7) & 0x1f) as usize;
+    let a = frame.reg(((insn >> 15) & 0x1f) as usize) as u64;
+    let b = frame.reg(((insn >> 20) & 0x1f) as usize) as u64;
+
+    let value = if opcode == 0x33 {
+        match funct3 {
+            0 => mul_wide(a, b) as u64,
+            1 => mulh(a, b),
+            2 => mulhsu(a, b),
+            3 => (mul_wide(a, b) >> 64) as u64,
+            4 => div(a, b).0,
+            5 => divu(a, b).0,
+            6 => div(a, b).1,
+            _ => divu(a, b).1,
+        }
+    } else {
+        let (a32, b32) = (sext32(a), sext32(b));
+        let (ua32, ub32) = (a & 0xffff_ffff, b & 0xffff_ffff);
+        let result = match funct3 {
+            0 => mul_wide(a, b) as u64,
+            4 => div(a32, b32).0,
+            5 => divu(ua32, ub32).0,
+            6 => div(a32, b32).1,
+            7 => divu(ua32, ub32).1,
+            _ => return false,
+        };
+        sext32(result)
+    };
+
+    frame.set_reg(rd, value as usize);
+    true
+}
+
+/// Sign-extend the low 32 bits
+fn sext32(value: u64) -> u64 {
+    value as u32 as i32 as i64 as u64
+}
+
+/// Full 128-bit unsigned product by shift and add
+fn mul_wide(a: u64, b: u64) -> u128 {
+    let mut product = 0u128;
+    let mut addend = a as u128;
+    let mut multiplier = b;
+    while multiplier != 0 {
+        if multiplier & 1 != 0 {
+            product = product.wrapping_add(addend);
+        }
+        addend <<= 1;
+        multiplier >>= 1;
+    }
+    product
+}
+
+/// High 64 bits of the signed × signed product
+fn mulh(a: u64, b: u64) -> u64 {
+    let mut high = (mul_wide(a, b) >> 64) as u64;
+    if (a as i64) < 0 {
+        high = high.wrapping_sub(b);
+    }
+    if (b as i64) < 0 {
+        high = high.wrapping_sub(a);
+    }
+    high
+}
+
+/// High 64 bits of the signed × unsigned product
+fn mulhsu(a: u64, b: u64) -> u64 {
+    let high = (mul_wide(a, b) >> 64) as u64;
+    if (a as i64) < 0 {
+        high.wrapping_sub(b)
+    } else {
+        high
+    }
+}
+
+/// Unsigned division by restoring shift-subtract (quotient, remainder)
+///
+/// Division by zero yields all ones and the dividend, as in hardware.
+fn divu(dividend: u64, divisor: u64) -> (u64, u64) {
+    if divisor == 0 {
+        return (u64::MAX, dividend);
+    }
+
+    let mut quotient = 0u64;
+    let mut remainder = 0u128;
+    for bit in (0..64).rev() {
+        remainder = (remainder << 1) | ((dividend >> bit) & 1) as u128;
+        if remainder >= divisor as u128 {
+            remainder -= divisor as u128;
+            quotient |= 1 << bit;
+        }
+    }
+    (quotient, remainder as u64)
+}
+
+/// Signed division with RISC-V semantics (quotient, remainder)
+fn div(dividend: u64, divisor: u64) -> (u64, u64) {
+    let (n, d) = (dividend as i64, divisor as i64);
+    if d == 0 {
+        return (u64::MAX, dividend);
+    }
+    if n == i64::MIN && d == -1 {
+        return (dividend, 0);
+    }
+
+    let (q, r) = divu(n.unsigned_abs(), d.unsigned_abs());
+    let q = if (n < 0) != (d < 0) {
+        q.wrapping_neg()
+    } else {
+        q
+    };
+    let r = if n < 0 { r.wrapping_neg() } else { r };
+    (q, r)
+}
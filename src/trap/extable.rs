@@ -0,0 +1,209 @@
+// src/trap/extable.rs
+//! Exception fixup table and fault-tolerant memory probing
+//!
+//! Individual load/store sites can record a fixup address in the
+//! `__ex_table` section. When one of those instructions raises a memory
+//! exception, the trap path resumes execution at the fixup address instead
+//! of reporting a fault. Before resuming, the trap handler stores the
+//! exception code in `a1` and the faulting address (`mtval`) in `a0`, so
+//! sites must treat both registers as clobbered.
+//!
+//! [`probe_read`] is built on top of this and lets debug tools read
+//! arbitrary addresses without risking a fatal access fault.
+
+use crate::arch::current::trap::reg;
+use crate::arch::current::{Exception, TrapFrame};
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Value left in `a1` by a probe that did not fault
+const NO_FAULT: usize = usize::MAX;
+
+/// One `__ex_table` entry (emitted by inline assembly at each site)
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ExtableEntry {
+    /// Address of the instruction that may fault
+    pub insn: usize,
+    /// Address execution resumes at after a fault
+    pub fixup: usize,
+}
+
+/// Memory fault caught by a probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    /// Exception raised by the access
+    pub exception: Exception,
+    /// Faulting address
+    pub addr: usize,
+}
+
+impl Fault {
+    pub fn as_str(&self) -> &'static str {
+        self.exception.as_str()
+    }
+}
+
+impl core::fmt::Display for Fault {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{} at {:#x}", self.as_str(), self.addr)
+    }
+}
+
+/// Number of exceptions resolved through the fixup table
+static FIXUP_COUNT: AtomicU64 = AtomicU64::new(0);
+
+unsafe extern "C" {
+    unsafe static __extable_start: ExtableEntry;
+    unsafe static __extable_end: ExtableEntry;
+}
+
+/// All entries linked into the kernel
+pub fn entries() -> &'static [ExtableEntry] {
+    unsafe {
+        let start = addr_of!(__extable_start);
+        let end = addr_of!(__extable_end);
+        let len = (end as usize - start as usize) / core::mem::size_of::<ExtableEntry>();
+        core::slice::from_raw_parts(start, len)
+    }
+}
+
+/// Find the fixup address for a faulting instruction
+pub fn search(pc: usize) -> Option<usize> {
+    entries()
+        .iter()
+        .find(|entry| entry.insn == pc)
+        .map(|entry| entry.fixup)
+}
+
+/// Number of exceptions resolved through the fixup table since boot
+pub fn fixup_count() -> u64 {
+    FIXUP_COUNT.load(Ordering::Relaxed)
+}
+
+/// Redirect a faulting access to its fixup address
+///
+/// Only memory exceptions are fixed up; anything else raised at a site in
+/// the table is still reported normally.
+///
+/// # Arguments
+/// * `frame` - Saved state of the faulting code; `mepc`, `a0` and `a1` are
+///   updated on success
+///
+/// # Returns
+/// `true` if the exception was resolved and the trap can return
+pub fn fixup(frame: &mut TrapFrame) -> bool {
+    let exception = Exception::from_code(frame.cause_code());
+    if !is_memory_fault(exception) {
+        return false;
+    }
+
+    let Some(fixup) = search(frame.mepc) else {
+        return false;
+    };
+
+    frame.set_reg(reg::A0, frame.mtval);
+    frame.set_reg(reg::A0 + 1, exception.code());
+    frame.mepc = fixup;
+    FIXUP_COUNT.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+fn is_memory_fault(exception: Exception) -> bool {
+    matches!(
+        exception,
+        Exception::LoadMisaligned
+            | Exception::LoadAccessFault
+            | Exception::LoadPageFault
+            | Exception::StoreMisaligned
+            | Exception::StoreAccessFault
+            | Exception::StorePageFault
+    )
+}
+
+/// Integer types that can be read with [`probe_read`]
+pub trait Probe: Copy {
+    /// Build a value from the low bytes of a little-endian load
+    fn from_raw(raw: u64) -> Self;
+}
+
+macro_rules! impl_probe {
+    ($($ty:ty),*) => {
+        $(
+            impl Probe for $ty {
+                fn from_raw(raw: u64) -> Self {
+                    raw as $ty
+                }
+            }
+        )*
+    };
+}
+
+impl_probe!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+/// Emit a single load guarded by the fixup table
+macro_rules! guarded_load {
+    ($insn:literal, $addr:expr) => {{
+        let value: usize;
+        let code: usize;
+        unsafe {
+            core::arch::asm!(
+                concat!("1: ", $insn, " a0, 0(a0)"),
+                "li a1, -1",
+                "2:",
+                ".pushsection __ex_table, \"a\"",
+                ".balign 8",
+                ".dword 1b, 2b",
+                ".popsection",
+                inout("a0") $addr => value,
+                out("a1") code,
+                options(nostack),
+            );
+        }
+        (value, code)
+    }};
+}
+
+/// Load `size` bytes (1, 2, 4 or 8) from a naturally aligned address
+fn load(addr: usize, size: usize) -> Result<u64, Fault> {
+    let (value, code) = match size {
+        1 => guarded_load!("lbu", addr),
+        2 => guarded_load!("lhu", addr),
+        4 => guarded_load!("lwu", addr),
+        _ => guarded_load!("ld", addr),
+    };
+
+    if code == NO_FAULT {
+        Ok(value as u64)
+    } else {
+        Err(Fault {
+            exception: Exception::from_code(code),
+            addr: value,
+        })
+    }
+}
+
+/// Read a value from an address that may not be mapped
+///
+/// Naturally aligned addresses are read with a single access (so MMIO
+/// registers see the expected width); misaligned ones are read byte by
+/// byte.
+///
+/// # Arguments
+/// * `addr` - Address to read
+///
+/// # Returns
+/// The value, or the fault the access raised
+pub fn probe_read<T: Probe>(addr: usize) -> Result<T, Fault> {
+    let size = core::mem::size_of::<T>();
+
+    if addr % size == 0 {
+        return load(addr, size).map(T::from_raw);
+    }
+
+    let mut raw = 0u64;
+    for i in 0..size {
+        raw |= load(addr.wrapping_add(i), 1)? << (i * 8);
+    }
+    Ok(T::from_raw(raw))
+}
@@ -0,0 +1,277 @@
+// src/trap/fault.rs
+//! Fault reports and recovery policy for synchronous exceptions
+//!
+//! Exceptions without a registered handler end up here. Each one produces a
+//! [`FaultReport`] (cause, `mepc`, decoded `mtval`, register dump and stack
+//! trace), after which the configured [`FaultAction`] for that exception
+//! decides whether the kernel panics, kills the current task, or resumes
+//! after the faulting instruction.
+
+use crate::arch::csr::bits;
+use crate::arch::current::trap::{reg, TrapValue};
+use crate::arch::current::{platform, Exception, TrapFrame, MAX_HARTS};
+use crate::console::{hex, num, str};
+use crate::{arch, debug, memory, print, println};
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+/// Maximum depth of the stack trace in a fault report
+const STACK_TRACE_DEPTH: usize = 8;
+
+/// Number of exception codes with a configurable action
+const POLICY_SLOTS: usize = 16;
+
+/// What to do after reporting an unhandled exception
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FaultAction {
+    /// Halt the system through the panic handler
+    Panic = 0,
+    /// Abandon the current task by redirecting it to the task exit hook
+    KillTask = 1,
+    /// Skip the faulting instruction and continue
+    Resume = 2,
+}
+
+impl FaultAction {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => FaultAction::KillTask,
+            2 => FaultAction::Resume,
+            _ => FaultAction::Panic,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FaultAction::Panic => "panic",
+            FaultAction::KillTask => "kill task",
+            FaultAction::Resume => "resume",
+        }
+    }
+}
+
+/// Default action for an exception code
+///
+/// Breakpoints and environment calls from lower modes are harmless to
+/// step over; everything else is a kernel bug.
+const fn default_action(code: usize) -> FaultAction {
+    match code {
+        bits::EXCEPTION_BREAKPOINT | bits::EXCEPTION_ECALL_UMODE | bits::EXCEPTION_ECALL_SMODE => {
+            FaultAction::Resume
+        }
+        _ => FaultAction::Panic,
+    }
+}
+
+static FAULT_POLICY: [AtomicU8; POLICY_SLOTS] = {
+    let mut policy = [const { AtomicU8::new(FaultAction::Panic as u8) }; POLICY_SLOTS];
+    let mut code = 0;
+    while code < POLICY_SLOTS {
+        policy[code] = AtomicU8::new(default_action(code) as u8);
+        code += 1;
+    }
+    policy
+};
+
+/// Task exit hook used by `FaultAction::KillTask` (0 = none)
+static TASK_EXIT_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of fault reports produced
+static FAULT_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Function the faulting task is redirected to by `FaultAction::KillTask`
+///
+/// Called in the task's context with the exception code and faulting PC.
+pub type TaskExitHook = extern "C" fn(code: usize, mepc: usize) -> !;
+
+/// Set the action taken after reporting an exception
+///
+/// # Arguments
+/// * `exception` - Exception to configure
+/// * `action` - Action taken when no handler is registered for it
+pub fn set_fault_action(exception: Exception, action: FaultAction) {
+    if let Some(slot) = FAULT_POLICY.get(exception.code()) {
+        slot.store(action as u8, Ordering::Relaxed);
+    }
+}
+
+/// Action taken after reporting an exception
+pub fn fault_action(exception: Exception) -> FaultAction {
+    match FAULT_POLICY.get(exception.code()) {
+        Some(slot) => FaultAction::from_u8(slot.load(Ordering::Relaxed)),
+        None => FaultAction::Panic,
+    }
+}
+
+/// Set or clear the hook used to kill the current task
+///
+/// Without a hook, `FaultAction::KillTask` falls back to a panic.
+pub fn set_task_exit_hook(hook: Option<TaskExitHook>) {
+    let addr = hook.map_or(0, |hook| hook as usize);
+    TASK_EXIT_HOOK.store(addr, Ordering::Release);
+}
+
+/// Number of fault reports produced since boot
+pub fn fault_count() -> u64 {
+    FAULT_COUNT.load(Ordering::Relaxed)
+}
+
+/// Snapshot of an exception for reporting
+pub struct FaultReport {
+    pub exception: Exception,
+    pub hart: usize,
+    pub frame: TrapFrame,
+}
+
+impl FaultReport {
+    /// Capture a report from the trap frame
+    pub fn new(frame: &TrapFrame, exception: Exception) -> Self {
+        Self {
+            exception,
+            hart: arch::csr::read_mhartid() as usize,
+            frame: *frame,
+        }
+    }
+
+    /// Privilege mode the exception was taken from
+    pub fn previous_mode(&self) -> &'static str {
+        match (self.frame.mstatus >> bits::STATUS_PP_SHIFT) & bits::STATUS_PP_MASK {
+            0 => "U",
+            1 => "S",
+            _ => "M",
+        }
+    }
+
+    /// Print the full report
+    pub fn print(&self) {
+        let frame = &self.frame;
+
+        println!("=== FAULT REPORT ===");
+        print!("Exception: ");
+        print!(self.exception.as_str());
+        println!(" (code {})", num(self.exception.code() as u64));
+        println!(
+            "Hart: {}  Mode: {}",
+            num(self.hart as u64),
+            str(self.previous_mode())
+        );
+        println!("mepc:  {}", hex(frame.mepc));
+
+        match self.exception.trap_value() {
+            TrapValue::Address => {
+                println!(
+                    "mtval: {} (faulting address, {})",
+                    hex(frame.mtval),
+                    str(classify_address(frame.mtval))
+                );
+            }
+            TrapValue::Instruction => {
+                if frame.mtval == 0 {
+                    println!("mtval: 0 (instruction bits not provided)");
+                } else {
+                    println!("mtval: {} (faulting instruction)", hex(frame.mtval));
+                }
+            }
+            TrapValue::None => println!("mtval: {}", hex(frame.mtval)),
+        }
+
+        #[cfg(not(feature = "smode"))]
+        if matches!(
+            self.exception,
+            Exception::InstructionAccessFault
+                | Exception::LoadAccessFault
+                | Exception::StoreAccessFault
+        ) {
+            arch::current::pmp::report_fault(self.exception.code(), frame.mepc, frame.mtval);
+        }
+
+        println!("--- Registers ---");
+        frame.print();
+        debug::print_stack_trace_from(frame.reg(reg::FP), frame.mepc, STACK_TRACE_DEPTH);
+    }
+}
+
+/// Describe where an address lies in the kernel memory layout
+fn classify_address(addr: usize) -> &'static str {
+    let layout = memory::layout();
+
+    if (0..MAX_HARTS).any(|hart| {
+        let (base, size) = memory::hart_stack_guard(hart);
+        addr >= base && addr < base + size
+    }) {
+        "stack guard page"
+    } else if layout.text.contains(addr) {
+        "kernel text"
+    } else if layout.rodata.contains(addr) {
+        "kernel rodata"
+    } else if layout.data.contains(addr) || layout.bss.contains(addr) {
+        "kernel data"
+    } else if layout.is_stack(addr) {
+        "hart stack"
+    } else if layout.heap.contains(addr) {
+        "heap"
+    } else if platform::is_ram(addr) {
+        "RAM"
+    } else if addr < 4096 {
+        "null page"
+    } else {
+        "outside RAM"
+    }
+}
+
+/// Report an exception and apply its configured action
+///
+/// # Arguments
+/// * `frame` - Saved state of the faulting code
+/// * `exception` - Decoded exception
+pub fn handle_exception(frame: &mut TrapFrame, exception: Exception) {
+    FAULT_COUNT.fetch_add(1, Ordering::Relaxed);
+
+    let report = FaultReport::new(frame, exception);
+    report.print();
+
+    let mut action = fault_action(exception);
+    if action == FaultAction::Resume && exception.is_fetch_fault() {
+        println!("Cannot resume after an instruction fetch fault");
+        action = FaultAction::Panic;
+    }
+
+    let hook = TASK_EXIT_HOOK.load(Ordering::Acquire);
+    if action == FaultAction::KillTask && hook == 0 {
+        println!("No task exit hook registered");
+        action = FaultAction::Panic;
+    }
+
+    print!("Action: ");
+    println!(action.as_str());
+
+    match action {
+        FaultAction::Resume => {
+            // The instruction was fetched, so it is readable
+            let len = unsafe { frame.instruction_length() };
+            frame.skip_instruction(len);
+        }
+        FaultAction::KillTask => {
+            frame.set_reg(reg::A0, exception.code());
+            frame.set_reg(reg::A0 + 1, frame.mepc);
+            frame.mepc = hook;
+        }
+        FaultAction::Panic => panic!("Unhandled exception (see fault report)"),
+    }
+}
+
+/// Print the configured action for each standard exception
+pub fn show_policy() {
+    println!("=== Fault Policy ===");
+    for code in 0..POLICY_SLOTS {
+        let exception = Exception::from_code(code);
+        if let Exception::Unknown(_) = exception {
+            continue;
+        }
+        print!("  ");
+        print!(exception.as_str());
+        print!(": ");
+        println!(fault_action(exception).as_str());
+    }
+    println!("Faults reported: {}", num(fault_count()));
+}
@@ -0,0 +1,337 @@
+// src/trap/misaligned.rs
+//! Misaligned load/store emulation
+//!
+//! Cores without hardware support for misaligned accesses raise
+//! `EXCEPTION_LOAD_MISALIGNED` / `EXCEPTION_STORE_MISALIGNED`. The trap path
+//! hands those to [`emulate`], which decodes the faulting integer load or
+//! store (including the compressed forms), performs it byte by byte, writes
+//! the result register back into the trap frame and steps over the
+//! instruction.
+//!
+//! Floating-point and atomic accesses are not emulated; they fall through
+//! to the normal fault report.
+
+use crate::arch::current::trap::reg;
+use crate::arch::current::{platform, TrapFrame};
+use crate::console::{hex, num};
+use crate::println;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Number of distinct PCs remembered for warn-once reporting
+const WARN_SLOTS: usize = 32;
+
+/// Kind of memory access being emulated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    /// Load of `size` bytes, sign-extended if `signed`
+    Load { rd: usize, signed: bool },
+    /// Store of the low `size` bytes of a register
+    Store { rs2: usize },
+}
+
+/// Decoded misaligned load/store
+#[derive(Debug, Clone, Copy)]
+struct MemoryOp {
+    access: Access,
+    /// Base address register
+    rs1: usize,
+    /// Address offset
+    offset: isize,
+    /// Access size in bytes
+    size: usize,
+    /// Instruction length in bytes
+    len: usize,
+}
+
+/// Misaligned access emulation statistics
+#[derive(Debug, Clone, Copy)]
+pub struct MisalignedStats {
+    /// Emulated loads
+    pub loads: u64,
+    /// Emulated stores
+    pub stores: u64,
+    /// Emulated accesses that used a compressed instruction
+    pub compressed: u64,
+    /// Misaligned traps that could not be emulated
+    pub unsupported: u64,
+    /// Warnings not printed because the warn-once table was full
+    pub suppressed_warnings: u64,
+}
+
+static LOADS: AtomicU64 = AtomicU64::new(0);
+static STORES: AtomicU64 = AtomicU64::new(0);
+static COMPRESSED: AtomicU64 = AtomicU64::new(0);
+static UNSUPPORTED: AtomicU64 = AtomicU64::new(0);
+static SUPPRESSED_WARNINGS: AtomicU64 = AtomicU64::new(0);
+
+static WARN_ONCE: AtomicBool = AtomicBool::new(false);
+static WARNED_PCS: [AtomicUsize; WARN_SLOTS] = [const { AtomicUsize::new(0) }; WARN_SLOTS];
+
+/// Enable or disable a one-time warning for each PC that needs emulation
+pub fn set_warn_once(enabled: bool) {
+    WARN_ONCE.store(enabled, Ordering::Relaxed);
+}
+
+/// Get emulation statistics
+pub fn stats() -> MisalignedStats {
+    MisalignedStats {
+        loads: LOADS.load(Ordering::Relaxed),
+        stores: STORES.load(Ordering::Relaxed),
+        compressed: COMPRESSED.load(Ordering::Relaxed),
+        unsupported: UNSUPPORTED.load(Ordering::Relaxed),
+        suppressed_warnings: SUPPRESSED_WARNINGS.load(Ordering::Relaxed),
+    }
+}
+
+/// Print emulation statistics
+pub fn show_stats() {
+    let stats = stats();
+    println!("=== Misaligned Access Emulation ===");
+    println!("Loads emulated:   {}", num(stats.loads));
+    println!("Stores emulated:  {}", num(stats.stores));
+    println!("Compressed:       {}", num(stats.compressed));
+    println!("Unsupported:      {}", num(stats.unsupported));
+    println!("Suppressed warns: {}", num(stats.suppressed_warnings));
+}
+
+/// Emulate the misaligned load or store that trapped
+///
+/// # Arguments
+/// * `frame` - Saved state of the faulting code; the destination register
+///   and `mepc` are updated on success
+///
+/// # Returns
+/// `true` if the access was emulated, `false` if it must be reported
+pub fn emulate(frame: &mut TrapFrame) -> bool {
+    let Some(op) = fetch_and_decode(frame.mepc) else {
+        UNSUPPORTED.fetch_add(1, Ordering::Relaxed);
+        return false;
+    };
+
+    let addr = frame.reg(op.rs1).wrapping_add_signed(op.offset);
+    if !is_emulatable(addr, op.size) {
+        UNSUPPORTED.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+
+    match op.access {
+        Access::Load { rd, signed } => {
+            let mut value = 0usize;
+            for i in 0..op.size {
+                let byte = unsafe { core::ptr::read_volatile((addr + i) as *const u8) };
+                value |= (byte as usize) << (i * 8);
+            }
+            if signed && op.size < 8 {
+                let shift = 64 - op.size * 8;
+                value = (((value << shift) as isize) >> shift) as usize;
+            }
+            frame.set_reg(rd, value);
+            LOADS.fetch_add(1, Ordering::Relaxed);
+        }
+        Access::Store { rs2 } => {
+            let value = frame.reg(rs2);
+            for i in 0..op.size {
+                unsafe {
+                    core::ptr::write_volatile((addr + i) as *mut u8, (value >> (i * 8)) as u8);
+                }
+            }
+            STORES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    if op.len == 2 {
+        COMPRESSED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    warn(frame.mepc, addr, op.access);
+    frame.skip_instruction(op.len);
+    true
+}
+
+/// Only emulate accesses that lie entirely in RAM (never MMIO)
+fn is_emulatable(addr: usize, size: usize) -> bool {
+    platform::is_ram(addr) && platform::is_ram(addr + size - 1)
+}
+
+/// Print a warning the first time a PC needs emulation (if enabled)
+///
+/// Once all [`WARN_SLOTS`] PCs are remembered, further PCs are only counted
+/// as suppressed so the log cannot be flooded.
+fn warn(pc: usize, addr: usize, access: Access) {
+    if !WARN_ONCE.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut remembered = false;
+    for slot in WARNED_PCS.iter() {
+        match slot.compare_exchange(0, pc, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => {
+                remembered = true;
+                break;
+            }
+            Err(existing) if existing == pc => return,
+            Err(_) => continue,
+        }
+    }
+    if !remembered {
+        SUPPRESSED_WARNINGS.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    let kind = match access {
+        Access::Load { .. } => "load",
+        Access::Store { .. } => "store",
+    };
+    println!(
+        "⚠ Emulated misaligned {} at pc {} (address {})",
+        crate::console::str(kind),
+        hex(pc),
+        hex(addr)
+    );
+}
+
+/// Read and decode the instruction at `pc`
+fn fetch_and_decode(pc: usize) -> Option<MemoryOp> {
+    // The instruction was just executed, so it is readable; it may only be
+    // 2-byte aligned, so fetch it in halves
+    let low = unsafe { core::ptr::read_volatile(pc as *const u16) } as u32;
+    if low & 0b11 != 0b11 {
+        return decode_compressed(low as u16);
+    }
+    let high = unsafe { core::ptr::read_volatile((pc + 2) as *const u16) } as u32;
+    decode(low | (high << 16))
+}
+
+/// Decode a 32-bit integer load or store
+fn decode(insn: u32) -> Option<MemoryOp> {
+    let opcode = insn & 0x7f;
+    let funct3 = (insn >> 12) & 0x7;
+    let rd = ((insn >> 7) & 0x1f) as usize;
+    let rs1 = ((insn >> 15) & 0x1f) as usize;
+    let rs2 = ((insn >> 20) & 0x1f) as usize;
+
+    match opcode {
+        // LOAD
+        0x03 => {
+            let (size, signed) = match funct3 {
+                0 => (1, true),  // lb
+                1 => (2, true),  // lh
+                2 => (4, true),  // lw
+                3 => (8, false), // ld
+                4 => (1, false), // lbu
+                5 => (2, false), // lhu
+                6 => (4, false), // lwu
+                _ => return None,
+            };
+            Some(MemoryOp {
+                access: Access::Load { rd, signed },
+                rs1,
+                offset: ((insn as i32) >> 20) as isize,
+                size,
+                len: 4,
+            })
+        }
+        // STORE
+        0x23 => {
+            let size = match funct3 {
+                0 => 1, // sb
+                1 => 2, // sh
+                2 => 4, // sw
+                3 => 8, // sd
+                _ => return None,
+            };
+            let imm = (((insn as i32) >> 25) << 5) | ((insn >> 7) & 0x1f) as i32;
+            Some(MemoryOp {
+                access: Access::Store { rs2 },
+                rs1,
+                offset: imm as isize,
+                size,
+                len: 4,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Decode a compressed integer load or store (RV64C)
+fn decode_compressed(insn: u16) -> Option<MemoryOp> {
+    let insn = insn as usize;
+    let quadrant = insn & 0b11;
+    let funct3 = (insn >> 13) & 0x7;
+    let bit = |n: usize| (insn >> n) & 1;
+
+    // Registers x8..x15 encoded in three bits
+    let rd_prime = ((insn >> 2) & 0x7) + 8;
+    let rs1_prime = ((insn >> 7) & 0x7) + 8;
+    // Full register fields used by the stack-pointer forms
+    let rd_full = (insn >> 7) & 0x1f;
+    let rs2_full = (insn >> 2) & 0x1f;
+
+    let (access, rs1, offset, size) = match (quadrant, funct3) {
+        // c.lw
+        (0b00, 0b010) => {
+            let offset = ((insn >> 10) & 0x7) << 3 | bit(6) << 2 | bit(5) << 6;
+            let access = Access::Load {
+                rd: rd_prime,
+                signed: true,
+            };
+            (access, rs1_prime, offset, 4)
+        }
+        // c.ld
+        (0b00, 0b011) => {
+            let offset = ((insn >> 10) & 0x7) << 3 | ((insn >> 5) & 0x3) << 6;
+            let access = Access::Load {
+                rd: rd_prime,
+                signed: false,
+            };
+            (access, rs1_prime, offset, 8)
+        }
+        // c.sw
+        (0b00, 0b110) => {
+            let offset = ((insn >> 10) & 0x7) << 3 | bit(6) << 2 | bit(5) << 6;
+            (Access::Store { rs2: rd_prime }, rs1_prime, offset, 4)
+        }
+        // c.sd
+        (0b00, 0b111) => {
+            let offset = ((insn >> 10) & 0x7) << 3 | ((insn >> 5) & 0x3) << 6;
+            (Access::Store { rs2: rd_prime }, rs1_prime, offset, 8)
+        }
+        // c.lwsp
+        (0b10, 0b010) if rd_full != 0 => {
+            let offset = bit(12) << 5 | ((insn >> 4) & 0x7) << 2 | ((insn >> 2) & 0x3) << 6;
+            let access = Access::Load {
+                rd: rd_full,
+                signed: true,
+            };
+            (access, reg::SP, offset, 4)
+        }
+        // c.ldsp
+        (0b10, 0b011) if rd_full != 0 => {
+            let offset = bit(12) << 5 | ((insn >> 5) & 0x3) << 3 | ((insn >> 2) & 0x7) << 6;
+            let access = Access::Load {
+                rd: rd_full,
+                signed: false,
+            };
+            (access, reg::SP, offset, 8)
+        }
+        // c.swsp
+        (0b10, 0b110) => {
+            let offset = ((insn >> 9) & 0xf) << 2 | ((insn >> 7) & 0x3) << 6;
+            (Access::Store { rs2: rs2_full }, reg::SP, offset, 4)
+        }
+        // c.sdsp
+        (0b10, 0b111) => {
+            let offset = ((insn >> 10) & 0x7) << 3 | ((insn >> 7) & 0x7) << 6;
+            (Access::Store { rs2: rs2_full }, reg::SP, offset, 8)
+        }
+        _ => return None,
+    };
+
+    Some(MemoryOp {
+        access,
+        rs1,
+        offset: offset as isize,
+        size,
+        len: 2,
+    })
+}
@@ -0,0 +1,259 @@
+// src/trap/stats.rs
+//! Per-hart trap accounting
+//!
+//! Every trap that reaches Rust is counted per hart and per cause. Its
+//! handling time, from the Rust entry point to the return into `trap.s`,
+//! is measured with the cycle counter. Each hart keeps the total and
+//! maximum cycles for every cause, plus a log2 histogram of all trap
+//! latencies. Time spent in nested interrupts is included in the trap they
+//! interrupted.
+
+use crate::arch::csr::{self, bits};
+use crate::arch::current::trap::interrupt_name;
+use crate::arch::current::{Exception, MAX_HARTS};
+use crate::console::{num, str};
+use crate::{print, println};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Exception and interrupt codes tracked individually (per kind)
+const CODES_PER_KIND: usize = 16;
+
+/// Counter slots per hart: exceptions first, then interrupts
+const CAUSE_SLOTS: usize = CODES_PER_KIND * 2;
+
+/// Number of latency histogram buckets
+///
+/// Bucket `n` counts traps that took less than `2^n` cycles (and at least
+/// `2^(n-1)`); the last bucket also holds everything longer.
+pub const HISTOGRAM_BUCKETS: usize = 32;
+
+/// Statistics for one trap cause on one hart
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CauseStats {
+    /// Number of traps
+    pub count: u64,
+    /// Cycles spent handling them
+    pub total_cycles: u64,
+    /// Longest single trap in cycles
+    pub max_cycles: u64,
+}
+
+impl CauseStats {
+    /// Average handling time in cycles
+    pub fn average_cycles(&self) -> u64 {
+        self.total_cycles.checked_div(self.count).unwrap_or(0)
+    }
+}
+
+struct HartTrapStats {
+    count: [AtomicU64; CAUSE_SLOTS],
+    cycles: [AtomicU64; CAUSE_SLOTS],
+    max_cycles: [AtomicU64; CAUSE_SLOTS],
+    /// Traps whose code does not fit a slot
+    other: AtomicU64,
+    histogram: [AtomicU64; HISTOGRAM_BUCKETS],
+}
+
+impl HartTrapStats {
+    const fn new() -> Self {
+        Self {
+            count: [const { AtomicU64::new(0) }; CAUSE_SLOTS],
+            cycles: [const { AtomicU64::new(0) }; CAUSE_SLOTS],
+            max_cycles: [const { AtomicU64::new(0) }; CAUSE_SLOTS],
+            other: AtomicU64::new(0),
+            histogram: [const { AtomicU64::new(0) }; HISTOGRAM_BUCKETS],
+        }
+    }
+}
+
+static STATS: [HartTrapStats; MAX_HARTS] = [const { HartTrapStats::new() }; MAX_HARTS];
+
+/// Counter slot for a raw `mcause` value
+fn slot(mcause: usize) -> Option<usize> {
+    let code = mcause & bits::MCAUSE_EXCEPTION_MASK;
+    if code >= CODES_PER_KIND {
+        return None;
+    }
+    if mcause & bits::MCAUSE_INTERRUPT_BIT != 0 {
+        Some(CODES_PER_KIND + code)
+    } else {
+        Some(code)
+    }
+}
+
+/// Histogram bucket for a latency in cycles
+fn bucket(cycles: u64) -> usize {
+    ((u64::BITS - cycles.leading_zeros()) as usize).min(HISTOGRAM_BUCKETS - 1)
+}
+
+/// Cycle counter value at trap entry, passed back to [`record`]
+#[inline]
+pub fn start() -> u64 {
+    csr::read_cycle()
+}
+
+/// Account for a finished trap on the current hart
+///
+/// # Arguments
+/// * `mcause` - Raw cause of the trap
+/// * `start` - Value returned by [`start`] when the trap was entered
+pub fn record(mcause: usize, start: u64) {
+    let cycles = csr::read_cycle().wrapping_sub(start);
+    let Some(stats) = STATS.get(csr::read_mhartid() as usize) else {
+        return;
+    };
+
+    match slot(mcause) {
+        Some(slot) => {
+            stats.count[slot].fetch_add(1, Ordering::Relaxed);
+            stats.cycles[slot].fetch_add(cycles, Ordering::Relaxed);
+            stats.max_cycles[slot].fetch_max(cycles, Ordering::Relaxed);
+        }
+        None => {
+            stats.other.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    stats.histogram[bucket(cycles)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Statistics for one cause on one hart
+///
+/// # Arguments
+/// * `hart` - Hart ID
+/// * `mcause` - Raw cause (interrupt bit and code)
+pub fn cause_stats(hart: usize, mcause: usize) -> CauseStats {
+    match (STATS.get(hart), slot(mcause)) {
+        (Some(stats), Some(slot)) => CauseStats {
+            count: stats.count[slot].load(Ordering::Relaxed),
+            total_cycles: stats.cycles[slot].load(Ordering::Relaxed),
+            max_cycles: stats.max_cycles[slot].load(Ordering::Relaxed),
+        },
+        _ => CauseStats::default(),
+    }
+}
+
+/// Number of traps with a cause, summed over all harts
+///
+/// # Arguments
+/// * `mcause` - Raw cause (interrupt bit and code)
+pub fn cause_count(mcause: usize) -> u64 {
+    (0..MAX_HARTS)
+        .map(|hart| cause_stats(hart, mcause).count)
+        .sum()
+}
+
+/// Total number of traps taken by a hart
+pub fn total(hart: usize) -> u64 {
+    let Some(stats) = STATS.get(hart) else {
+        return 0;
+    };
+    let counted: u64 = stats
+        .count
+        .iter()
+        .map(|count| count.load(Ordering::Relaxed))
+        .sum();
+    counted + stats.other.load(Ordering::Relaxed)
+}
+
+/// Latency histogram of a hart (see [`HISTOGRAM_BUCKETS`])
+pub fn histogram(hart: usize) -> [u64; HISTOGRAM_BUCKETS] {
+    let mut buckets = [0; HISTOGRAM_BUCKETS];
+    if let Some(stats) = STATS.get(hart) {
+        for (bucket, count) in buckets.iter_mut().zip(stats.histogram.iter()) {
+            *bucket = count.load(Ordering::Relaxed);
+        }
+    }
+    buckets
+}
+
+/// Clear all counters and histograms
+pub fn reset() {
+    for stats in STATS.iter() {
+        let counters = stats
+            .count
+            .iter()
+            .chain(stats.cycles.iter())
+            .chain(stats.max_cycles.iter())
+            .chain(stats.histogram.iter())
+            .chain(core::iter::once(&stats.other));
+        for counter in counters {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Raw `mcause` value for a counter slot
+fn slot_mcause(slot: usize) -> usize {
+    if slot < CODES_PER_KIND {
+        slot
+    } else {
+        bits::MCAUSE_INTERRUPT_BIT | (slot - CODES_PER_KIND)
+    }
+}
+
+/// Name of a trap cause
+fn cause_name(mcause: usize) -> &'static str {
+    let code = mcause & bits::MCAUSE_EXCEPTION_MASK;
+    if mcause & bits::MCAUSE_INTERRUPT_BIT != 0 {
+        interrupt_name(code)
+    } else {
+        Exception::from_code(code).as_str()
+    }
+}
+
+/// Call `f` with every cause a hart has taken at least once
+pub fn for_each_cause(hart: usize, mut f: impl FnMut(usize, &'static str, CauseStats)) {
+    for slot in 0..CAUSE_SLOTS {
+        let mcause = slot_mcause(slot);
+        let stats = cause_stats(hart, mcause);
+        if stats.count > 0 {
+            f(mcause, cause_name(mcause), stats);
+        }
+    }
+}
+
+/// Print per-cause counters and the latency histogram of every active hart
+pub fn show() {
+    println!("=== Trap Statistics ===");
+    for hart in 0..MAX_HARTS {
+        let total = total(hart);
+        if total == 0 {
+            continue;
+        }
+
+        println!("Hart {}: {} traps", num(hart as u64), num(total));
+        for_each_cause(hart, |_, name, stats| {
+            print!("  ");
+            print!(name);
+            println!(
+                ": {} (avg {} cycles, max {})",
+                num(stats.count),
+                num(stats.average_cycles()),
+                num(stats.max_cycles)
+            );
+        });
+
+        let other = STATS[hart].other.load(Ordering::Relaxed);
+        if other > 0 {
+            println!("  other causes: {}", num(other));
+        }
+
+        println!("  Latency (cycles):");
+        for (bucket, count) in histogram(hart).iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let (bound, power) = if bucket == HISTOGRAM_BUCKETS - 1 {
+                (">=", bucket - 1)
+            } else {
+                ("< ", bucket)
+            };
+            println!(
+                "    {} 2^{}: {}",
+                str(bound),
+                num(power as u64),
+                num(*count)
+            );
+        }
+    }
+}